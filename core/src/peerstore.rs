@@ -63,6 +63,8 @@ struct PeerRecord {
     key: Option<PublicKey>,
     /// The protocols supported by the peer.
     protos: HashSet<String>,
+    /// The agent version of the peer, as reported by Identify.
+    agent_version: Option<String>,
 }
 
 impl PeerRecord {
@@ -72,6 +74,7 @@ impl PeerRecord {
             addrs,
             key,
             protos,
+            agent_version: None,
         }
     }
 }
@@ -196,6 +199,20 @@ impl PeerStore {
         guard.get(peer_id).and_then(|pr| pr.key.clone())
     }
 
+    /// Sets the agent version by peer_id.
+    pub fn add_agent_version(&self, peer_id: &PeerId, agent_version: String) {
+        let mut guard = self.inner.lock().unwrap();
+        if let Some(pr) = guard.get_mut(peer_id) {
+            pr.agent_version = Some(agent_version);
+        }
+    }
+
+    /// Gets the agent version by peer_id.
+    pub fn get_agent_version(&self, peer_id: &PeerId) -> Option<String> {
+        let guard = self.inner.lock().unwrap();
+        guard.get(peer_id).and_then(|pr| pr.agent_version.clone())
+    }
+
     /// Add address to address_book by peer_id, if exists, update rtt.
     pub fn add_addr(&self, peer_id: &PeerId, addr: Multiaddr, ttl: Duration) {
         self.add_addrs(peer_id, vec![addr], ttl)
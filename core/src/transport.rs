@@ -300,6 +300,9 @@ pub enum TransportError {
 
     /// websocket error
     WsError(Box<dyn Error + Send + Sync>),
+
+    /// dns transport error
+    DnsError(Box<dyn Error + Send + Sync>),
 }
 
 impl From<std::io::Error> for TransportError {
@@ -336,6 +339,7 @@ impl fmt::Display for TransportError {
             TransportError::SecurityError(err) => write!(f, "SecurityError layer error {:?}", err),
             TransportError::StreamMuxerError(err) => write!(f, "StreamMuxerError layer error {:?}", err),
             TransportError::WsError(err) => write!(f, "Websocket transport  error: {}", err),
+            TransportError::DnsError(err) => write!(f, "DNS transport error: {}", err),
         }
     }
 }
@@ -355,6 +359,7 @@ impl Error for TransportError {
             TransportError::SecurityError(err) => Some(&**err),
             TransportError::StreamMuxerError(err) => Some(&**err),
             TransportError::WsError(err) => Some(&**err),
+            TransportError::DnsError(err) => Some(&**err),
         }
     }
 }
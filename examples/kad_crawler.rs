@@ -0,0 +1,118 @@
+// Copyright 2020 Netwarps Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Crawls the Kad DHT network and prints the peers found as JSON.
+//!
+//! Usage: kad_crawler <bootstrap-peer-id> <bootstrap-address> [protocol-name]
+
+use libp2prs_core::identity;
+use libp2prs_core::transport::upgrade::TransportUpgrade;
+use libp2prs_core::upgrade::Selector;
+use libp2prs_core::{Multiaddr, PeerId};
+use libp2prs_kad::crawler::{Crawler, CrawlerConfig};
+use libp2prs_kad::kad::{Kademlia, KademliaConfig};
+use libp2prs_kad::store::MemoryStore;
+use libp2prs_mplex as mplex;
+use libp2prs_noise::{Keypair, NoiseConfig, X25519Spec};
+use libp2prs_runtime::task;
+use libp2prs_secio as secio;
+use libp2prs_swarm::identify::IdentifyConfig;
+use libp2prs_swarm::Swarm;
+use libp2prs_tcp::TcpConfig;
+use libp2prs_yamux as yamux;
+
+use std::convert::TryFrom;
+
+fn main() {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+    let args = std::env::args().collect::<Vec<_>>();
+    if args.len() != 3 && args.len() != 4 {
+        println!("Usage: {} <bootstrap-peer-id> <bootstrap-address> [protocol-name]", args[0]);
+        return;
+    }
+
+    let peer = match PeerId::try_from(args[1].clone()) {
+        Ok(peer) => peer,
+        Err(e) => {
+            println!("bad peer id: {:?}", e);
+            return;
+        }
+    };
+    let addr = match Multiaddr::try_from(args[2].clone()) {
+        Ok(addr) => addr,
+        Err(e) => {
+            println!("bad multiaddr: {:?}", e);
+            return;
+        }
+    };
+
+    let mut config = CrawlerConfig::default();
+    if let Some(name) = args.get(3) {
+        // the protocol name lives as long as the crawler
+        let name: &'static str = Box::leak(name.clone().into_boxed_str());
+        config = config.with_protocol_name(name.as_bytes().into());
+    }
+
+    run_crawler(peer, addr, config);
+}
+
+fn run_crawler(bootstrap_peer: PeerId, bootstrap_addr: Multiaddr, config: CrawlerConfig) {
+    let keys = identity::Keypair::generate_ed25519();
+
+    let dh = Keypair::<X25519Spec>::new().into_authentic(&keys).unwrap();
+
+    let sec_noise = NoiseConfig::xx(dh, keys.clone());
+    let sec_secio = secio::Config::new(keys.clone());
+    let sec = Selector::new(sec_noise, sec_secio);
+
+    let mux = Selector::new(yamux::Config::new(), mplex::Config::new());
+    let tu = TransportUpgrade::new(TcpConfig::default(), mux, sec);
+
+    let mut swarm = Swarm::new(keys.public())
+        .with_transport(Box::new(tu))
+        .with_identify(IdentifyConfig::new(false));
+
+    log::info!("Swarm created, local-peer-id={:?}", swarm.local_peer_id());
+
+    task::block_on(async {
+        let mut swarm_control = swarm.control();
+
+        // Kad is only used for the routing, the crawler talks to the peers directly
+        let kad_config = KademliaConfig::default().with_refresh_interval(None);
+        let store = MemoryStore::new(*swarm.local_peer_id());
+        let kad = Kademlia::with_config(*swarm.local_peer_id(), store, kad_config);
+        let mut kad_control = kad.control();
+
+        swarm = swarm.with_protocol(kad).with_routing(Box::new(kad_control.clone()));
+        swarm.start();
+
+        let mut crawler = Crawler::new(swarm_control.clone(), kad_control.clone(), config);
+        let result = crawler.crawl(vec![(bootstrap_peer, vec![bootstrap_addr])]).await;
+
+        log::info!("{} peers found, {} reachable", result.peers.len(), result.num_reachable());
+        match result.to_json() {
+            Ok(json) => println!("{}", json),
+            Err(e) => log::error!("failed to export the result: {:?}", e),
+        }
+
+        kad_control.close();
+        swarm_control.close();
+    });
+}
@@ -25,6 +25,7 @@ use libp2prs_core::identity;
 use libp2prs_core::transport::upgrade::TransportUpgrade;
 use libp2prs_core::upgrade::Selector;
 use libp2prs_core::{Multiaddr, PeerId};
+use libp2prs_dns::{DnsConfig, NameServerResolver, SystemResolver};
 use libp2prs_mplex as mplex;
 use libp2prs_noise::{Keypair, NoiseConfig, X25519Spec};
 use libp2prs_runtime::task;
@@ -65,9 +66,35 @@ fn main() {
             }
         };
 
-        run_server(peer, addr);
+        run_server(vec![(peer, addr)]);
+    } else if std::env::args().len() == 2 {
+        // the bootstrap list, e.g. /dnsaddr/bootstrap.libp2p.io
+        let a1 = std::env::args().nth(1).unwrap();
+        let addr = match Multiaddr::try_from(a1) {
+            Ok(addr) => addr,
+            Err(e) => {
+                println!("bad multiaddr: {:?}", e);
+                return;
+            }
+        };
+
+        // The system resolver looks up the TXT records through the name servers in /etc/resolv.conf,
+        // which doesn't exist on e.g. Windows, so a public name server is configured as the fallback.
+        let resolver = SystemResolver::new().with_txt_fallback(NameServerResolver::new().with_udp("1.1.1.1:53".parse().unwrap()));
+        let dns = DnsConfig::new(TcpConfig::default()).with_resolver(resolver);
+        let bootstrapper = match task::block_on(dns.resolve_peers(&addr)) {
+            Ok(peers) => peers,
+            Err(e) => {
+                println!("failed to resolve {}: {:?}", addr, e);
+                return;
+            }
+        };
+        log::info!("Resolved {} bootstrap peers from {}", bootstrapper.len(), addr);
+
+        run_server(bootstrapper);
     } else {
-        println!("Usage: {} <bootstrap-address>", std::env::args().next().unwrap());
+        println!("Usage: {} <bootstrap-peer> <bootstrap-address>", std::env::args().next().unwrap());
+        println!("       {} <dnsaddr>", std::env::args().next().unwrap());
     }
 }
 
@@ -76,7 +103,7 @@ lazy_static! {
 }
 
 #[allow(clippy::empty_loop)]
-fn run_server(bootstrap_peer: PeerId, bootstrap_addr: Multiaddr) {
+fn run_server(bootstrapper: Vec<(PeerId, Multiaddr)>) {
     let keys = SERVER_KEY.clone();
 
     let listen_addr1: Multiaddr = "/ip4/0.0.0.0/tcp/8086".parse().unwrap();
@@ -88,7 +115,7 @@ fn run_server(bootstrap_peer: PeerId, bootstrap_addr: Multiaddr) {
     let sec = Selector::new(sec_noise, sec_secio);
 
     let mux = Selector::new(yamux::Config::new(), mplex::Config::new());
-    let tu = TransportUpgrade::new(TcpConfig::default(), mux, sec);
+    let tu = TransportUpgrade::new(DnsConfig::new(TcpConfig::default()), mux, sec);
 
     let mut swarm = Swarm::new(keys.public())
         .with_transport(Box::new(tu))
@@ -113,7 +140,6 @@ fn run_server(bootstrap_peer: PeerId, bootstrap_addr: Multiaddr) {
         swarm = swarm.with_protocol(kad).with_routing(Box::new(kad_control.clone()));
         swarm.start();
 
-        let bootstrapper = vec![(bootstrap_peer, bootstrap_addr)];
         kad_control.bootstrap(bootstrapper).await;
 
         let mut app = App::new("xCLI").version("v0.1").author("kingwel.xie@139.com");
@@ -23,6 +23,7 @@ use std::fs::File;
 use std::io;
 use std::io::BufReader;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 use structopt::StructOpt;
 
 use async_trait::async_trait;
@@ -43,11 +44,12 @@ use libp2prs_swarm::Swarm;
 use libp2prs_traits::{ReadEx, WriteEx};
 use libp2prs_websocket::{tls, WsConfig};
 
-use rustls::internal::pemfile::{certs, rsa_private_keys};
-use rustls::{Certificate, PrivateKey};
+use rustls::internal::pemfile::certs;
+use rustls::Certificate;
 
 /// run server:
 /// RUST_LOG=trace cargo run --example websocket_tls server
+/// the certificates are reloaded when the files change, or when enter is pressed
 /// run client:
 /// RUST_LOG=trace cargo run --example websocket_tls client
 #[allow(dead_code)]
@@ -70,6 +72,22 @@ struct ServerTlsConfig {
     /// key file
     #[structopt(short = "k", long = "key", parse(from_os_str), default_value = "examples/websocket/cert/end.rsa")]
     key: PathBuf,
+
+    /// server name selecting the additional certificate via SNI
+    #[structopt(long = "sni-name")]
+    sni_name: Option<String>,
+
+    /// cert file of the additional certificate
+    #[structopt(long = "sni-cert", parse(from_os_str))]
+    sni_cert: Option<PathBuf>,
+
+    /// key file of the additional certificate
+    #[structopt(long = "sni-key", parse(from_os_str))]
+    sni_key: Option<PathBuf>,
+
+    /// seconds between checks of the cert and key files for changes
+    #[structopt(short = "i", long = "interval", default_value = "10")]
+    interval: u64,
 }
 
 #[allow(dead_code)]
@@ -105,19 +123,6 @@ fn load_certs(path: &Path) -> io::Result<Vec<Certificate>> {
     certs(&mut BufReader::new(File::open(path)?)).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid cert"))
 }
 
-/// Load the passed keys file
-fn load_keys(path: &Path) -> io::Result<Vec<PrivateKey>> {
-    rsa_private_keys(&mut BufReader::new(File::open(path)?)).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid key"))
-}
-
-/// A TLS server needs a certificate and a fitting private key
-fn load_config(options: &ServerTlsConfig) -> io::Result<(PrivateKey, Vec<Certificate>)> {
-    let certs = load_certs(&options.cert)?;
-    let keys = load_keys(&options.key)?;
-    let one = keys.get(0).unwrap().to_owned();
-    Ok((one, certs))
-}
-
 // Build tls client config
 fn build_client_tls_config(options: &ClientTlsConfig) -> tls::Config {
     let ca = load_certs(&options.cafile).unwrap();
@@ -126,13 +131,16 @@ fn build_client_tls_config(options: &ClientTlsConfig) -> tls::Config {
     let mut builder = tls::Config::builder();
     builder.add_trust(ca_cert).unwrap().clone().finish()
 }
-// Build tls server config
-fn build_server_tls_config(options: &ServerTlsConfig) -> tls::Config {
-    let (pk, certs) = load_config(&options).unwrap();
-    log::trace!("pk  {:?}", &pk);
-    log::trace!("certs  {:?}", &certs);
-    let cert_iter = certs.into_iter().map(|c| tls::Certificate::new(c.0));
-    tls::Config::new(tls::PrivateKey::new(pk.0), cert_iter).unwrap()
+// Build tls server config, the certificates are reloaded when the files change
+fn build_server_tls_config(options: &ServerTlsConfig) -> (tls::Config, tls::Control) {
+    let resolver = tls::CertResolver::new();
+    resolver.add_pem_files(None, &options.cert, &options.key).unwrap();
+    if let (Some(name), Some(cert), Some(key)) = (&options.sni_name, &options.sni_cert, &options.sni_key) {
+        resolver.add_pem_files(Some(name.as_str()), cert, key).unwrap();
+    }
+    log::trace!("certs  {:?}", &resolver);
+    let control = resolver.watch(Duration::from_secs(options.interval));
+    (tls::Config::new_with_resolver(resolver), control)
 }
 
 struct MyProtocol;
@@ -193,7 +201,6 @@ lazy_static! {
 }
 const PROTO_NAME: &[u8] = b"/my/1.0.0";
 
-#[allow(clippy::empty_loop)]
 fn run_server() -> io::Result<()> {
     let options = ServerTlsConfig::from_args();
     let addr = format!("/ip4/{}/tcp/{}/wss", &options.host, &options.port);
@@ -205,7 +212,8 @@ fn run_server() -> io::Result<()> {
     let sec = plaintext::PlainTextConfig::new(keys.clone());
     let mux = mplex::Config::new();
 
-    let ws = WsConfig::new().set_tls_config(build_server_tls_config(&options)).to_owned();
+    let (tls_config, mut tls_control) = build_server_tls_config(&options);
+    let ws = WsConfig::new().set_tls_config(tls_config).to_owned();
     let tu = TransportUpgrade::new(ws, mux, sec);
 
     let mut swarm = Swarm::new(keys.public())
@@ -221,7 +229,16 @@ fn run_server() -> io::Result<()> {
 
     swarm.start();
 
-    loop {}
+    // press enter to reload the certificates, without waiting for the files to change
+    let mut line = String::new();
+    while io::stdin().read_line(&mut line)? > 0 {
+        match task::block_on(tls_control.reload()) {
+            Ok(n) => log::info!("{} certificate(s) reloaded", n),
+            Err(e) => log::warn!("failed to reload certificates: {}", e),
+        }
+        line.clear();
+    }
+    Ok(())
 }
 
 fn run_client() -> io::Result<()> {
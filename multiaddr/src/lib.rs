@@ -33,6 +33,28 @@ static_assertions::const_assert! {
     std::mem::size_of::<usize>() <= std::mem::size_of::<u64>()
 }
 
+fn is_global_ipv4(ip: &Ipv4Addr) -> bool {
+    let octets = ip.octets();
+    let shared = octets[0] == 100 && (octets[1] & 0xc0) == 64;
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        || shared
+        || octets[0] == 0)
+}
+
+fn is_global_ipv6(ip: &Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+    let link_local = (first & 0xffc0) == 0xfe80;
+    let unique_local = (first & 0xfe00) == 0xfc00;
+    let documentation = first == 0x2001 && ip.segments()[1] == 0x0db8;
+    !(ip.is_unspecified() || ip.is_loopback() || ip.is_multicast() || link_local || unique_local || documentation)
+}
+
 /// Representation of a Multiaddr.
 #[allow(clippy::rc_buffer)]
 #[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Hash)]
@@ -160,6 +182,25 @@ impl Multiaddr {
         is_private
     }
 
+    /// Returns true if the address is a globally reachable IP address.
+    ///
+    /// Unspecified, loopback, private, link-local, shared (RFC 6598), documentation
+    /// and broadcast IPv4 addresses are not global, neither are unspecified, loopback,
+    /// link-local (fe80::/10), unique local (fc00::/7), documentation and multicast
+    /// IPv6 addresses. Addresses without an IP component, e.g. `/dns` or `/memory`,
+    /// are never global.
+    pub fn is_global_addr(&self) -> bool {
+        match self.iter().next() {
+            Some(protocol::Protocol::Ip4(ip)) => is_global_ipv4(&ip),
+            Some(protocol::Protocol::Ip6(ip)) => match ip.to_ipv4() {
+                // IPv4-mapped addresses are judged by their IPv4 part
+                Some(v4) if ip.segments()[5] == 0xffff => is_global_ipv4(&v4),
+                _ => is_global_ipv6(&ip),
+            },
+            _ => false,
+        }
+    }
+
     /// we don't consume FD's for relay addresses for now as they will be consumed when the Relay Transport actually dials the Relay server.
     /// That dial call will also pass through this limiter with the address of the relay server i.e. non-relay address.
     pub fn should_consume_fd(&self) -> bool {
@@ -428,3 +428,30 @@ fn unknown_protocol_string() {
         },
     }
 }
+
+#[test]
+fn global_addr() {
+    let is_global = |s: &str| s.parse::<Multiaddr>().unwrap().is_global_addr();
+
+    assert!(is_global("/ip4/8.8.8.8/tcp/4001"));
+    assert!(is_global("/ip6/2606:4700::1111/tcp/4001"));
+    assert!(is_global("/ip6/::ffff:8.8.8.8/tcp/4001"));
+
+    // unspecified
+    assert!(!is_global("/ip4/0.0.0.0/tcp/4001"));
+    assert!(!is_global("/ip6/::/tcp/4001"));
+    // loopback, private, link-local and shared
+    assert!(!is_global("/ip4/127.0.0.1/tcp/4001"));
+    assert!(!is_global("/ip4/192.168.1.1/tcp/4001"));
+    assert!(!is_global("/ip4/169.254.0.1/tcp/4001"));
+    assert!(!is_global("/ip4/100.64.0.1/tcp/4001"));
+    assert!(!is_global("/ip6/::1/tcp/4001"));
+    assert!(!is_global("/ip6/::ffff:10.0.0.1/tcp/4001"));
+    // IPv6 link-local, unique local and documentation
+    assert!(!is_global("/ip6/fe80::1/tcp/4001"));
+    assert!(!is_global("/ip6/fd00::1/tcp/4001"));
+    assert!(!is_global("/ip6/2001:db8::1/tcp/4001"));
+    // no IP component
+    assert!(!is_global("/memory/1234"));
+    assert!(!is_global("/dns/example.com/tcp/4001"));
+}
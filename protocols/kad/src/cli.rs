@@ -200,13 +200,14 @@ fn cli_dump_statistics(app: &App, _args: &[&str]) -> XcliResult {
     task::block_on(async {
         let stats = kad.dump_statistics().await.unwrap();
         println!("Total refreshes        : {}", stats.total_refreshes);
+        println!("Rejected peers         : {}", stats.rejected_peers);
         println!("Iter query executed    : {}", stats.query.iter_query_executed);
         println!("Iter query completed   : {}", stats.query.iter_query_completed);
         println!("Iter query timeout     : {}", stats.query.iter_query_timeout);
+        println!("Iter query cancelled   : {}", stats.query.iter_query_cancelled);
         println!("Fixed query executed   : {}", stats.query.fixed_query_executed);
         println!("Fixed query completed  : {}", stats.query.fixed_query_completed);
-        let running = stats.query.iter_query_executed - stats.query.iter_query_completed - stats.query.iter_query_timeout;
-        println!("Iter query in progress : {}", running);
+        println!("Iter query in progress : {}", stats.query.iter_query_running());
         let running = stats.query.fixed_query_executed - stats.query.fixed_query_completed;
         println!("Fixed query in progress: {}", running);
         println!("Iter query details     : {:?}", stats.query.iterative);
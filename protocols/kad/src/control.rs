@@ -23,13 +23,16 @@ use futures::SinkExt;
 
 use async_trait::async_trait;
 
+use libp2prs_core::identity::Keypair;
 use libp2prs_core::routing::{IRouting, Routing};
 use libp2prs_core::transport::TransportError;
 use libp2prs_core::{Multiaddr, PeerId};
 
+use crate::events::KadEvent;
 use crate::kad::{KBucketView, KademliaStats};
 use crate::protocol::{KadMessengerView, KadPeer};
-use crate::query::PeerRecord;
+use crate::query::{PeerRecord, QueryStream, Quorum};
+use crate::record::ipns::{ipns_key, IpnsRecord};
 use crate::{record, KadError};
 
 type Result<T> = std::result::Result<T, KadError>;
@@ -46,20 +49,42 @@ pub(crate) enum ControlCommand {
     FindPeer(PeerId, oneshot::Sender<Result<KadPeer>>),
     /// Lookups peers who are able to provide a given key.
     FindProviders(record::Key, usize, oneshot::Sender<Result<Vec<KadPeer>>>),
+    /// Lookups peers who are able to provide a given key, the providers are
+    /// delivered as soon as they are found. The query is cancelled when the
+    /// oneshot receiver is resolved.
+    FindProvidersStream(record::Key, usize, mpsc::UnboundedSender<KadPeer>, oneshot::Receiver<()>),
     /// Adds the given key to the content routing system.
     ///
     /// It also announces it, otherwise it is just kept in the local
     /// accounting of which objects are being provided.
     Providing(record::Key, oneshot::Sender<Result<()>>),
+    /// Adds the given keys to the content routing system, and announces them
+    /// in batches. Returns the number of keys announced.
+    ProvidingMany(Vec<record::Key>, oneshot::Sender<Result<usize>>),
+    /// Announces the sorted keys which can share the closest peers of the first
+    /// key. Returns the number of keys covered and the number of keys announced.
+    ProvideBatch(Vec<record::Key>, oneshot::Sender<Result<(usize, usize)>>),
     /// Removes the give key from the local provider store.
     ///
     /// This is a local operation. The local node will still be considered as a
     /// provider for the key by other nodes until these provider records expire.
     Unprovide(record::Key),
-    /// Adds value corresponding to given Key.
-    PutValue(record::Key, Vec<u8>, oneshot::Sender<Result<()>>),
-    /// Searches value corresponding to given Key.
-    GetValue(record::Key, oneshot::Sender<Result<PeerRecord>>),
+    /// Adds value corresponding to given Key, returns the number of peers
+    /// which have stored the value.
+    PutValue(record::Key, Vec<u8>, Quorum, oneshot::Sender<Result<usize>>),
+    /// Searches value corresponding to given Key, returns all distinct records.
+    GetValue(record::Key, Quorum, oneshot::Sender<Result<Vec<PeerRecord>>>),
+    /// Searches value corresponding to given Key, the records are delivered as
+    /// soon as they are found. The query is cancelled when the oneshot receiver
+    /// is resolved.
+    GetValueStream(record::Key, Quorum, mpsc::UnboundedSender<PeerRecord>, oneshot::Receiver<()>),
+    /// Publishes the value as the IPNS record of the keypair, and republishes
+    /// it periodically.
+    PublishName(Keypair, Vec<u8>, oneshot::Sender<Result<()>>),
+    /// Stops republishing the IPNS record of the peer.
+    UnpublishName(PeerId),
+    /// Subscribes to the Kad events.
+    Subscribe(oneshot::Sender<mpsc::UnboundedReceiver<KadEvent>>),
     /// Dumps commands for debugging purpose.
     Dump(DumpCommand),
     /// Adds a peer node to Kad KBuckets, and its multiaddr to Peerstore.
@@ -106,6 +131,16 @@ impl Control {
         let _ = self.control_sender.send(ControlCommand::RemoveNode(peer_id)).await;
     }
 
+    /// Subscribes to the Kad events, i.e. the changes of the routing table, the
+    /// inbound requests, the iterative queries and the records stored or expired.
+    ///
+    /// The subscription ends when the receiver is dropped.
+    pub async fn subscribe(&mut self) -> Result<mpsc::UnboundedReceiver<KadEvent>> {
+        let (tx, rx) = oneshot::channel();
+        self.control_sender.send(ControlCommand::Subscribe(tx)).await?;
+        Ok(rx.await?)
+    }
+
     pub async fn dump_storage(&mut self) -> Result<()> {
         let (tx, rx) = oneshot::channel();
         self.control_sender.send(ControlCommand::Dump(DumpCommand::Storage(tx))).await?;
@@ -158,17 +193,72 @@ impl Control {
     }
 
     pub async fn put_value(&mut self, key: Vec<u8>, value: Vec<u8>) -> Result<()> {
+        self.put_value_with_quorum(key, value, Quorum::One).await.map(|_| ())
+    }
+
+    /// Stores the value to the closest peers of the key, returns the number of
+    /// peers which have stored it.
+    ///
+    /// [`KadError::QuorumFailed`] is returned if the quorum is not met.
+    pub async fn put_value_with_quorum(&mut self, key: Vec<u8>, value: Vec<u8>, quorum: Quorum) -> Result<usize> {
         let (tx, rx) = oneshot::channel();
         let key = record::Key::from(key);
-        self.control_sender.send(ControlCommand::PutValue(key, value, tx)).await?;
+        self.control_sender.send(ControlCommand::PutValue(key, value, quorum, tx)).await?;
         rx.await?
     }
 
     pub async fn get_value(&mut self, key: Vec<u8>) -> Result<Vec<u8>> {
+        let mut records = self.get_value_with_quorum(key, Quorum::All).await?;
+        Ok(records.swap_remove(0).record.value)
+    }
+
+    /// Searches the value of the key, returns as soon as the quorum is reached.
+    ///
+    /// All distinct records found are returned, the best one comes first.
+    pub async fn get_value_with_quorum(&mut self, key: Vec<u8>, quorum: Quorum) -> Result<Vec<PeerRecord>> {
         let (tx, rx) = oneshot::channel();
         let key = record::Key::from(key);
-        self.control_sender.send(ControlCommand::GetValue(key, tx)).await?;
-        rx.await?.map(|t| t.record.value)
+        self.control_sender.send(ControlCommand::GetValue(key, quorum, tx)).await?;
+        rx.await?
+    }
+
+    /// Searches the value of the key, returns a stream of the records as soon as
+    /// they are found. The records are validated, but not deduplicated.
+    ///
+    /// The query is cancelled when the stream is dropped.
+    pub async fn get_value_stream(&mut self, key: Vec<u8>, quorum: Quorum) -> Result<QueryStream<PeerRecord>> {
+        let (stream, tx, cancel) = QueryStream::new();
+        let key = record::Key::from(key);
+        self.control_sender
+            .send(ControlCommand::GetValueStream(key, quorum, tx, cancel))
+            .await?;
+        Ok(stream)
+    }
+
+    /// Publishes the value under the name of the keypair, i.e. `/ipns/<peer-id>`,
+    /// as a signed [`IpnsRecord`].
+    ///
+    /// The sequence number is increased if the value differs from the current
+    /// record in the DHT. The name is republished periodically until unpublished.
+    pub async fn publish_name(&mut self, keypair: Keypair, value: Vec<u8>) -> Result<()> {
+        let (tx, rx) = oneshot::channel();
+        self.control_sender.send(ControlCommand::PublishName(keypair, value, tx)).await?;
+        rx.await?
+    }
+
+    /// Stops republishing the name of the peer. The records published remain in
+    /// the DHT until they expire.
+    pub async fn unpublish_name(&mut self, peer_id: &PeerId) -> Result<()> {
+        self.control_sender.send(ControlCommand::UnpublishName(*peer_id)).await?;
+        Ok(())
+    }
+
+    /// Resolves the name of the peer, returns the value of the best IPNS record.
+    pub async fn resolve_name(&mut self, peer_id: &PeerId) -> Result<Vec<u8>> {
+        let key = ipns_key(peer_id).to_vec();
+        let mut records = self.get_value_with_quorum(key, Quorum::Majority).await?;
+        let record = IpnsRecord::decode(&records.swap_remove(0).record.value)?;
+        Ok(record.value)
     }
 
     pub async fn provide(&mut self, key: Vec<u8>) -> Result<()> {
@@ -178,6 +268,23 @@ impl Control {
         rx.await?
     }
 
+    /// Adds the keys to the content routing system, and announces them in
+    /// batches, i.e. keys that share the closest peers cost one lookup.
+    ///
+    /// Returns the number of keys announced successfully.
+    pub async fn provide_many(&mut self, keys: Vec<Vec<u8>>) -> Result<usize> {
+        let (tx, rx) = oneshot::channel();
+        let keys = keys.into_iter().map(record::Key::from).collect();
+        self.control_sender.send(ControlCommand::ProvidingMany(keys, tx)).await?;
+        rx.await?
+    }
+
+    pub(crate) async fn provide_batch(&mut self, keys: Vec<record::Key>) -> Result<(usize, usize)> {
+        let (tx, rx) = oneshot::channel();
+        self.control_sender.send(ControlCommand::ProvideBatch(keys, tx)).await?;
+        rx.await?
+    }
+
     pub async fn unprovide(&mut self, key: Vec<u8>) -> Result<()> {
         let key = record::Key::from(key);
         self.control_sender.send(ControlCommand::Unprovide(key)).await?;
@@ -190,6 +297,19 @@ impl Control {
         self.control_sender.send(ControlCommand::FindProviders(key, count, tx)).await?;
         rx.await?
     }
+
+    /// Lookups the providers of the key, returns a stream of the providers as
+    /// soon as they are found.
+    ///
+    /// The query is cancelled when the stream is dropped.
+    pub async fn find_providers_stream(&mut self, key: Vec<u8>, count: usize) -> Result<QueryStream<KadPeer>> {
+        let (stream, tx, cancel) = QueryStream::new();
+        let key = record::Key::from(key);
+        self.control_sender
+            .send(ControlCommand::FindProvidersStream(key, count, tx, cancel))
+            .await?;
+        Ok(stream)
+    }
 }
 
 /// Implements `Routing` for Kad Control. Therefore, Kad control can be used
@@ -0,0 +1,320 @@
+// Copyright 2020 Netwarps Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! A crawler of the Kad DHT network.
+//!
+//! Starting from the bootstrap peers and the peers in the local routing table,
+//! the crawler asks every peer it finds for the peers in each of its buckets,
+//! by sending `FindNode` requests with keys falling into the buckets. The peers
+//! returned are crawled in turn, until no new peer is found.
+//!
+//! The key for a bucket is generated from a random distance in the bucket, XORed
+//! with the key of the peer. Note the key is sent as the `FindNode` key as it is,
+//! though the peers take the keys on the wire as preimages and hash them, so the
+//! peers returned are the closest ones to the hash of the key, which is a random
+//! position in the keyspace rather than a position in the bucket.
+//!
+//! The result can be exported as JSON, which includes the peer Ids, addresses,
+//! agent versions and reachability of the peers, and the peer graph.
+
+use fnv::FnvHashSet;
+use futures::StreamExt;
+use serde::Serialize;
+use std::time::Duration;
+
+use libp2prs_core::peerstore::TEMP_ADDR_TTL;
+use libp2prs_core::{Multiaddr, PeerId};
+use libp2prs_runtime::task;
+use libp2prs_swarm::Control as SwarmControl;
+
+use crate::protocol::{KadMessenger, KadPeer, KademliaProtocolConfig};
+use crate::{kbucket, record, Control, KadError};
+
+/// The configuration of the crawler.
+#[derive(Debug, Clone)]
+pub struct CrawlerConfig {
+    protocol_config: KademliaProtocolConfig,
+    parallelism: usize,
+    buckets: usize,
+    timeout: Duration,
+}
+
+impl Default for CrawlerConfig {
+    fn default() -> Self {
+        Self {
+            protocol_config: Default::default(),
+            parallelism: 16,
+            buckets: 16,
+            timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+impl CrawlerConfig {
+    /// Sets the protocol name of the DHT to be crawled.
+    pub fn with_protocol_name(mut self, name: libp2prs_core::ProtocolId) -> Self {
+        self.protocol_config.set_protocol_name(name);
+        self
+    }
+
+    /// Sets the number of peers crawled in parallel. The default is 16.
+    pub fn with_parallelism(mut self, parallelism: usize) -> Self {
+        self.parallelism = parallelism.max(1);
+        self
+    }
+
+    /// Sets the number of buckets queried for each peer, starting from the
+    /// farthest one. The default is 16, and at most 256 buckets are queried.
+    pub fn with_buckets(mut self, buckets: usize) -> Self {
+        self.buckets = buckets.min(256);
+        self
+    }
+
+    /// Sets the timeout of crawling a single peer. The default is 30 seconds.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+}
+
+/// A peer found by the crawler.
+#[derive(Debug, Clone)]
+pub struct CrawledPeer {
+    pub id: PeerId,
+    pub addrs: Vec<Multiaddr>,
+    /// The agent version reported by Identify, if the peer has been reached.
+    pub agent_version: Option<String>,
+    /// Whether the peer has answered the `FindNode` requests.
+    pub reachable: bool,
+    /// The peers in the routing table of this peer, as many as found.
+    pub neighbors: Vec<PeerId>,
+}
+
+/// The result of crawling.
+#[derive(Debug, Clone, Default)]
+pub struct CrawlResult {
+    pub peers: Vec<CrawledPeer>,
+}
+
+// The serializable form of CrawledPeer.
+#[derive(Serialize)]
+struct PeerJson {
+    id: String,
+    addrs: Vec<String>,
+    agent_version: Option<String>,
+    reachable: bool,
+    neighbors: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct ResultJson {
+    total: usize,
+    reachable: usize,
+    peers: Vec<PeerJson>,
+}
+
+impl CrawlResult {
+    /// Returns the number of reachable peers.
+    pub fn num_reachable(&self) -> usize {
+        self.peers.iter().filter(|p| p.reachable).count()
+    }
+
+    /// Exports the result as pretty printed JSON.
+    pub fn to_json(&self) -> std::result::Result<String, serde_json::Error> {
+        let peers = self
+            .peers
+            .iter()
+            .map(|p| PeerJson {
+                id: p.id.to_string(),
+                addrs: p.addrs.iter().map(|a| a.to_string()).collect(),
+                agent_version: p.agent_version.clone(),
+                reachable: p.reachable,
+                neighbors: p.neighbors.iter().map(|n| n.to_string()).collect(),
+            })
+            .collect();
+        let result = ResultJson {
+            total: self.peers.len(),
+            reachable: self.num_reachable(),
+            peers,
+        };
+        serde_json::to_string_pretty(&result)
+    }
+}
+
+/// The crawler of the Kad DHT network.
+pub struct Crawler {
+    swarm: SwarmControl,
+    kad: Control,
+    config: CrawlerConfig,
+}
+
+impl Crawler {
+    /// Creates a crawler with the Swarm and the Kad controllers.
+    pub fn new(swarm: SwarmControl, kad: Control, config: CrawlerConfig) -> Self {
+        Self { swarm, kad, config }
+    }
+
+    /// Crawls the network, starting from the bootstrap peers and the peers in
+    /// the local routing table.
+    pub async fn crawl(&mut self, bootstrap: Vec<(PeerId, Vec<Multiaddr>)>) -> CrawlResult {
+        let mut frontier = bootstrap;
+        if let Ok(buckets) = self.kad.dump_kbuckets().await {
+            for node in buckets.into_iter().flat_map(|b| b.bucket) {
+                frontier.push((node.id, node.addresses));
+            }
+        }
+
+        // never crawl the local peer
+        let mut seen = FnvHashSet::default();
+        if let Ok(info) = self.swarm.retrieve_identify_info().await {
+            seen.insert(info.public_key.into_peer_id());
+        }
+        frontier.retain(|(p, _)| seen.insert(*p));
+
+        let mut found = Vec::new();
+        while !frontier.is_empty() {
+            log::info!("crawling {} peers, {} crawled", frontier.len(), found.len());
+
+            let visits = std::mem::take(&mut frontier)
+                .into_iter()
+                .map(|(peer, addrs)| self.visit(peer, addrs));
+            let mut results = futures::stream::iter(visits).buffer_unordered(self.config.parallelism);
+            while let Some((crawled, neighbors)) = results.next().await {
+                for n in neighbors {
+                    if seen.insert(n.node_id) {
+                        frontier.push((n.node_id, n.multiaddrs));
+                    }
+                }
+                found.push(crawled);
+            }
+        }
+
+        CrawlResult { peers: found }
+    }
+
+    // Crawls a single peer, returns the peer and its neighbors.
+    async fn visit(&self, peer: PeerId, addrs: Vec<Multiaddr>) -> (CrawledPeer, Vec<KadPeer>) {
+        self.swarm.add_addrs(&peer, addrs.clone(), TEMP_ADDR_TTL);
+
+        let targets = keys_for_buckets(&peer, self.config.buckets);
+        let neighbors = match task::timeout(self.config.timeout, self.find_nodes(peer, targets)).await {
+            Ok(r) => r,
+            Err(_) => Err(KadError::Timeout),
+        };
+        let (reachable, neighbors) = match neighbors {
+            Ok(n) => (true, n),
+            Err(e) => {
+                log::debug!("failed to crawl {}: {:?}", peer, e);
+                (false, vec![])
+            }
+        };
+
+        let crawled = CrawledPeer {
+            id: peer,
+            addrs: self.swarm.get_addrs(&peer).unwrap_or(addrs),
+            agent_version: self.swarm.get_agent_version(&peer),
+            reachable,
+            neighbors: neighbors.iter().map(|n| n.node_id).collect(),
+        };
+        (crawled, neighbors)
+    }
+
+    // Sends the FindNode requests to the peer, returns the distinct peers found.
+    async fn find_nodes(&self, peer: PeerId, targets: Vec<record::Key>) -> Result<Vec<KadPeer>, KadError> {
+        let mut messenger = KadMessenger::build(self.swarm.clone(), peer, self.config.protocol_config.clone()).await?;
+        let mut seen = FnvHashSet::default();
+        let mut peers = Vec::new();
+        for target in targets {
+            for p in messenger.send_find_node(target).await? {
+                if p.node_id != peer && seen.insert(p.node_id) {
+                    peers.push(p);
+                }
+            }
+        }
+        Ok(peers)
+    }
+}
+
+/// Generates a key for each of the farthest `buckets` buckets of the peer,
+/// starting from the farthest one.
+fn keys_for_buckets(peer: &PeerId, buckets: usize) -> Vec<record::Key> {
+    let local: kbucket::KeyBytes = kbucket::Key::from(*peer).into();
+    let mut rng = rand::thread_rng();
+    (0..buckets.min(256))
+        .map(|i| record::Key::from(kbucket::rand_key_in_bucket(&local, 255 - i, &mut rng).to_vec()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keys_for_buckets() {
+        let peer = PeerId::random();
+        let local: kbucket::KeyBytes = kbucket::Key::from(peer).into();
+        let local = local.to_vec();
+
+        // every one of the default buckets gets a key, which is in the keyspace as it is,
+        // i.e. the i-th key shares the first i bits with the peer and differs in the next one
+        let buckets = CrawlerConfig::default().buckets;
+        let keys = keys_for_buckets(&peer, buckets);
+        assert_eq!(keys.len(), 16);
+        for (i, key) in keys.iter().enumerate() {
+            let xor = local.iter().zip(key.as_ref()).map(|(a, b)| a ^ b).collect::<Vec<_>>();
+            let first = xor.iter().position(|b| *b != 0).expect("different keys");
+            assert_eq!(first * 8 + xor[first].leading_zeros() as usize, i);
+        }
+
+        // all the buckets can be queried
+        assert_eq!(keys_for_buckets(&peer, 300).len(), 256);
+    }
+
+    #[test]
+    fn test_to_json() {
+        let (a, b) = (PeerId::random(), PeerId::random());
+        let result = CrawlResult {
+            peers: vec![
+                CrawledPeer {
+                    id: a,
+                    addrs: vec!["/ip4/1.2.3.4/tcp/4001".parse().unwrap()],
+                    agent_version: Some("libp2p-rs".to_string()),
+                    reachable: true,
+                    neighbors: vec![b],
+                },
+                CrawledPeer {
+                    id: b,
+                    addrs: vec![],
+                    agent_version: None,
+                    reachable: false,
+                    neighbors: vec![],
+                },
+            ],
+        };
+
+        let json: serde_json::Value = serde_json::from_str(&result.to_json().unwrap()).unwrap();
+        assert_eq!(json["total"], 2);
+        assert_eq!(json["reachable"], 1);
+        assert_eq!(json["peers"][0]["id"], a.to_string());
+        assert_eq!(json["peers"][0]["addrs"][0], "/ip4/1.2.3.4/tcp/4001");
+        assert_eq!(json["peers"][0]["neighbors"][0], b.to_string());
+        assert_eq!(json["peers"][1]["agent_version"], serde_json::Value::Null);
+    }
+}
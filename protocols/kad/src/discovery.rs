@@ -0,0 +1,191 @@
+// Copyright 2020 Netwarps Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Random-walk discovery of peers.
+//!
+//! The refresh of the routing table only looks up the buckets which are
+//! not recently queried, so the routing table stays small when there are few
+//! bootstrap peers. The discovery service complements it by looking up random
+//! keys periodically, as long as the number of connections is below a target.
+//! The peers found are added to the peer store of Swarm, and connected until
+//! the target is reached. Connected peers are then identified and added to the
+//! routing table as usual.
+//!
+//! Each round is limited by a budget of lookups and dials, so that a node which
+//! can't reach its target doesn't flood the network.
+
+use fnv::FnvHashSet;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use libp2prs_core::peerstore::PROVIDER_ADDR_TTL;
+use libp2prs_core::PeerId;
+use libp2prs_swarm::Control as SwarmControl;
+
+use crate::{record, Control, KadError};
+
+/// The configuration of the random-walk discovery.
+#[derive(Debug, Clone)]
+pub struct DiscoveryConfig {
+    interval: Duration,
+    target_connections: usize,
+    max_lookups: usize,
+    max_dials: usize,
+    address_ttl: Duration,
+}
+
+impl Default for DiscoveryConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(60),
+            target_connections: 32,
+            max_lookups: 4,
+            max_dials: 16,
+            address_ttl: PROVIDER_ADDR_TTL,
+        }
+    }
+}
+
+impl DiscoveryConfig {
+    /// Sets the interval between two rounds. The default is 1 minute.
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Sets the number of connections to keep. No lookup is made when there are
+    /// as many connections. The default is 32.
+    pub fn with_target_connections(mut self, target: usize) -> Self {
+        self.target_connections = target;
+        self
+    }
+
+    /// Sets the budget of a round, i.e. the maximum number of random lookups and
+    /// the maximum number of peers dialed. The default is 4 lookups and 16 dials.
+    pub fn with_budget(mut self, max_lookups: usize, max_dials: usize) -> Self {
+        self.max_lookups = max_lookups;
+        self.max_dials = max_dials;
+        self
+    }
+
+    /// Sets the TTL of the addresses of the peers found, in the peer store. The
+    /// default is 10 minutes, the same as the addresses of providers.
+    pub fn with_address_ttl(mut self, ttl: Duration) -> Self {
+        self.address_ttl = ttl;
+        self
+    }
+
+    /// Returns the interval between two rounds.
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Returns the number of connections to keep.
+    pub fn target_connections(&self) -> usize {
+        self.target_connections
+    }
+}
+
+/// The statistics of the random-walk discovery.
+#[derive(Debug, Clone, Default)]
+pub struct DiscoveryStats {
+    /// The number of rounds completed.
+    pub rounds: usize,
+    /// The number of rounds skipped, as the target had been reached.
+    pub skipped: usize,
+    /// Whether a round is ongoing.
+    pub running: bool,
+    /// The number of random lookups made.
+    pub lookups: usize,
+    /// The number of distinct peers found in the last round.
+    pub discovered: usize,
+    /// The number of peers dialed.
+    pub dials: usize,
+    /// The number of peers connected by dialing.
+    pub connected: usize,
+    /// When the last round finished.
+    pub finished: Option<SystemTime>,
+}
+
+/// Runs a round of random walks, trying to make `wanted` new connections.
+///
+/// `connected` is the set of the peers connected when the round starts, which
+/// are not dialed again. Returns the number of new connections made.
+pub(crate) async fn random_walk(
+    mut control: Control,
+    mut swarm: SwarmControl,
+    config: DiscoveryConfig,
+    connected: FnvHashSet<PeerId>,
+    wanted: usize,
+    stats: Arc<Mutex<DiscoveryStats>>,
+) -> usize {
+    let mut discovered = FnvHashSet::default();
+    let mut made = 0;
+    let mut dials = 0;
+
+    for _ in 0..config.max_lookups {
+        if made >= wanted || dials >= config.max_dials {
+            break;
+        }
+
+        let key = record::Key::from(PeerId::random());
+        stats.lock().unwrap().lookups += 1;
+        let peers = match control.lookup(key).await {
+            Ok(peers) => peers,
+            Err(KadError::Internal) => {
+                log::info!("Kad main loop closed, discovery aborted");
+                break;
+            }
+            Err(e) => {
+                log::debug!("random lookup failed: {:?}", e);
+                continue;
+            }
+        };
+
+        for peer in peers {
+            if connected.contains(&peer.node_id) || !discovered.insert(peer.node_id) {
+                continue;
+            }
+            if !peer.multiaddrs.is_empty() {
+                swarm.add_addrs(&peer.node_id, peer.multiaddrs, config.address_ttl);
+            }
+            if made >= wanted || dials >= config.max_dials {
+                continue;
+            }
+
+            dials += 1;
+            match swarm.new_connection(peer.node_id).await {
+                Ok(_) => made += 1,
+                Err(e) => log::debug!("failed to connect {}: {:?}", peer.node_id, e),
+            }
+        }
+    }
+
+    let mut stats = stats.lock().unwrap();
+    stats.running = false;
+    stats.rounds += 1;
+    stats.discovered = discovered.len();
+    stats.dials += dials;
+    stats.connected += made;
+    stats.finished = Some(SystemTime::now());
+    log::info!("{} peers discovered, {} of {} dials connected", discovered.len(), made, dials);
+
+    made
+}
@@ -0,0 +1,351 @@
+// Copyright 2020 Netwarps Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Dual DHT, i.e. a LAN DHT along with a WAN DHT.
+//!
+//! A node running in both private networks and the public internet should not
+//! mix private addresses into the public DHT. [`DualKademlia`] runs two
+//! `Kademlia` instances with different protocol names: the LAN DHT only admits
+//! peers with private addresses, and the WAN DHT only admits peers with public
+//! addresses. [`DualControl`] dispatches queries to both of them and merges the
+//! results.
+//!
+//! ```ignore
+//! let dual = DualKademlia::new(local_peer_id, MemoryStore::new(local_peer_id), MemoryStore::new(local_peer_id));
+//! let control = dual.control();
+//! let (lan, wan) = dual.into_inner();
+//! swarm = swarm.with_protocol(lan).with_protocol(wan).with_routing(Box::new(control));
+//! ```
+
+use async_trait::async_trait;
+
+use libp2prs_core::routing::{IRouting, Routing};
+use libp2prs_core::transport::TransportError;
+use libp2prs_core::{Multiaddr, PeerId};
+
+use crate::kad::{Kademlia, KademliaConfig};
+use crate::kbucket::{AddressScope, AddressScopeFilter, ChainedFilter};
+use crate::protocol::KadPeer;
+use crate::record::validator::{NamespacedValidator, Validator};
+use crate::store::RecordStore;
+use crate::{record, Control, KadError, PeerRecord, Quorum};
+
+type Result<T> = std::result::Result<T, KadError>;
+
+/// The protocol name of the LAN DHT.
+pub const LAN_PROTO_NAME: &[u8] = b"/ipfs/lan/kad/1.0.0";
+
+/// A LAN DHT along with a WAN DHT.
+pub struct DualKademlia<TStore> {
+    lan: Kademlia<TStore>,
+    wan: Kademlia<TStore>,
+}
+
+impl<TStore> DualKademlia<TStore>
+where
+    for<'a> TStore: RecordStore<'a> + Send + 'static,
+{
+    /// Creates a dual DHT with the default configurations.
+    pub fn new(id: PeerId, lan_store: TStore, wan_store: TStore) -> Self {
+        Self::with_config(id, lan_store, wan_store, KademliaConfig::default(), KademliaConfig::default())
+    }
+
+    /// Creates a dual DHT with the given configurations.
+    ///
+    /// The protocol name of the LAN DHT is set to [`LAN_PROTO_NAME`], and the
+    /// filters of the address scope are chained before the routing table filters
+    /// of both DHTs, if any. The WAN DHT admits only the peers with global
+    /// addresses, and sends only the global addresses of the peers in its responses.
+    pub fn with_config(
+        id: PeerId,
+        lan_store: TStore,
+        wan_store: TStore,
+        lan_config: KademliaConfig,
+        wan_config: KademliaConfig,
+    ) -> Self {
+        let lan_config = with_scope(lan_config.with_protocol_name(LAN_PROTO_NAME.into()), AddressScope::Private);
+        let wan_config = with_scope(wan_config, AddressScope::Public);
+
+        Self {
+            lan: Kademlia::with_config(id, lan_store, lan_config),
+            wan: Kademlia::with_config(id, wan_store, wan_config),
+        }
+    }
+
+    /// Returns the controller of the dual DHT.
+    pub fn control(&self) -> DualControl {
+        DualControl {
+            lan: self.lan.control(),
+            wan: self.wan.control(),
+            validator: self.wan.validator().clone(),
+        }
+    }
+
+    /// Splits into the LAN and WAN DHT, which are supposed to be registered to Swarm.
+    pub fn into_inner(self) -> (Kademlia<TStore>, Kademlia<TStore>) {
+        (self.lan, self.wan)
+    }
+}
+
+/// The controller of the dual DHT.
+///
+/// The queries are dispatched to both the LAN and WAN DHT. An operation fails only
+/// if it fails in both of them, and the error of the WAN DHT is returned.
+#[derive(Clone)]
+pub struct DualControl {
+    lan: Control,
+    wan: Control,
+    validator: NamespacedValidator,
+}
+
+impl DualControl {
+    /// Returns the controller of the LAN DHT.
+    pub fn lan(&mut self) -> &mut Control {
+        &mut self.lan
+    }
+
+    /// Returns the controller of the WAN DHT.
+    pub fn wan(&mut self) -> &mut Control {
+        &mut self.wan
+    }
+
+    /// Closes both DHTs.
+    pub fn close(&mut self) {
+        self.lan.close();
+        self.wan.close();
+    }
+
+    /// Searches for the peer, the addresses found in both DHTs are merged.
+    pub async fn find_peer(&mut self, peer_id: &PeerId) -> Result<KadPeer> {
+        let (lan, wan) = futures::join!(self.lan.find_peer(peer_id), self.wan.find_peer(peer_id));
+        merge(lan, wan, merge_peer)
+    }
+
+    /// Lookups the providers of the key, at most `count` of them if `count` is not 0.
+    pub async fn find_providers(&mut self, key: Vec<u8>, count: usize) -> Result<Vec<KadPeer>> {
+        let (lan, wan) = futures::join!(self.lan.find_providers(key.clone(), count), self.wan.find_providers(key, count));
+        merge(lan, wan, |w, l| merge_providers(w, l, count))
+    }
+
+    /// Announces the key as a provider in both DHTs.
+    pub async fn provide(&mut self, key: Vec<u8>) -> Result<()> {
+        let (lan, wan) = futures::join!(self.lan.provide(key.clone()), self.wan.provide(key));
+        merge(lan, wan, |_, _| ())
+    }
+
+    /// Stores the value in both DHTs, returns the total number of peers which
+    /// have stored it.
+    ///
+    /// The quorum is applied to each of the DHTs.
+    pub async fn put_value_with_quorum(&mut self, key: Vec<u8>, value: Vec<u8>, quorum: Quorum) -> Result<usize> {
+        let (lan, wan) = futures::join!(
+            self.lan.put_value_with_quorum(key.clone(), value.clone(), quorum),
+            self.wan.put_value_with_quorum(key, value, quorum)
+        );
+        merge(lan, wan, |w, l| w + l)
+    }
+
+    pub async fn put_value(&mut self, key: Vec<u8>, value: Vec<u8>) -> Result<()> {
+        self.put_value_with_quorum(key, value, Quorum::One).await.map(|_| ())
+    }
+
+    /// Searches the value of the key in both DHTs.
+    ///
+    /// All distinct records found are returned, the best one comes first.
+    pub async fn get_value_with_quorum(&mut self, key: Vec<u8>, quorum: Quorum) -> Result<Vec<PeerRecord>> {
+        let (lan, wan) = futures::join!(
+            self.lan.get_value_with_quorum(key.clone(), quorum),
+            self.wan.get_value_with_quorum(key.clone(), quorum)
+        );
+        let mut records = merge(lan, wan, |mut w, l| {
+            for r in l {
+                if w.iter().all(|o| o.record.value != r.record.value) {
+                    w.push(r);
+                }
+            }
+            w
+        })?;
+
+        // select the best one out of the records of both DHTs
+        let values = records.iter().map(|r| r.record.value.as_slice()).collect::<Vec<_>>();
+        let best = self.validator.select(&record::Key::from(key), &values)?;
+        let best = records.remove(best);
+        records.insert(0, best);
+
+        Ok(records)
+    }
+
+    pub async fn get_value(&mut self, key: Vec<u8>) -> Result<Vec<u8>> {
+        let mut records = self.get_value_with_quorum(key, Quorum::All).await?;
+        Ok(records.swap_remove(0).record.value)
+    }
+}
+
+// Merges the results of the LAN and WAN DHT, `f` is invoked with the result
+// of the WAN DHT first, if both succeeded.
+fn merge<T>(lan: Result<T>, wan: Result<T>, f: impl FnOnce(T, T) -> T) -> Result<T> {
+    match (lan, wan) {
+        (Ok(l), Ok(w)) => Ok(f(w, l)),
+        (Ok(l), Err(e)) => {
+            log::debug!("WAN DHT failed: {:?}", e);
+            Ok(l)
+        }
+        (Err(e), w) => {
+            log::debug!("LAN DHT failed: {:?}", e);
+            w
+        }
+    }
+}
+
+// Merges the addresses of the peer found in the LAN DHT into the WAN one.
+fn merge_peer(mut w: KadPeer, l: KadPeer) -> KadPeer {
+    for addr in l.multiaddrs {
+        if !w.multiaddrs.contains(&addr) {
+            w.multiaddrs.push(addr);
+        }
+    }
+    w
+}
+
+// Appends the providers found only in the LAN DHT, at most `count` providers
+// are kept if `count` is not 0.
+fn merge_providers(mut w: Vec<KadPeer>, l: Vec<KadPeer>, count: usize) -> Vec<KadPeer> {
+    for p in l {
+        if w.iter().all(|o| o.node_id != p.node_id) {
+            w.push(p);
+        }
+    }
+    if count != 0 {
+        w.truncate(count);
+    }
+    w
+}
+
+// Chains the filter of the address scope before the routing table filter of
+// the configuration. The WAN DHT also strips the non-global addresses of the
+// peers in its responses.
+fn with_scope(config: KademliaConfig, scope: AddressScope) -> KademliaConfig {
+    let config = config.with_global_addrs_only(scope == AddressScope::Public);
+    let scope_filter = AddressScopeFilter::new(scope);
+    match config.routing_table_filter().cloned() {
+        Some(filter) => config.with_routing_table_filter(ChainedFilter::new(Box::new(scope_filter), filter)),
+        None => config.with_routing_table_filter(scope_filter),
+    }
+}
+
+/// Implements `Routing` for the dual DHT control. Therefore, it can be used
+/// by Swarm to find peers.
+#[async_trait]
+impl Routing for DualControl {
+    async fn find_peer(&mut self, peer_id: &PeerId) -> std::result::Result<Vec<Multiaddr>, TransportError> {
+        let kad_peer = self.find_peer(peer_id).await.map_err(|e| TransportError::Routing(e.into()))?;
+        Ok(kad_peer.multiaddrs)
+    }
+
+    async fn find_providers(&mut self, key: Vec<u8>, count: usize) -> std::result::Result<Vec<PeerId>, TransportError> {
+        let providers = self
+            .find_providers(key, count)
+            .await
+            .map_err(|e| TransportError::Routing(e.into()))?;
+        Ok(providers.into_iter().map(|peer| peer.node_id).collect())
+    }
+
+    async fn provide(&mut self, key: Vec<u8>) -> std::result::Result<(), TransportError> {
+        self.provide(key).await.map_err(|e| TransportError::Routing(e.into()))
+    }
+
+    fn box_clone(&self) -> IRouting {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kbucket::IpGroupFilter;
+    use crate::protocol::KadConnectionType;
+
+    #[test]
+    fn test_merge() {
+        let ok = |v: Vec<u32>| -> Result<Vec<u32>> { Ok(v) };
+        let concat = |mut w: Vec<u32>, l: Vec<u32>| {
+            w.extend(l);
+            w
+        };
+
+        assert_eq!(merge(ok(vec![1]), ok(vec![2]), concat).unwrap(), vec![2, 1]);
+        assert_eq!(merge(ok(vec![1]), Err(KadError::NotFound), concat).unwrap(), vec![1]);
+        assert_eq!(merge(Err(KadError::NotFound), ok(vec![2]), concat).unwrap(), vec![2]);
+        assert!(matches!(
+            merge(Err(KadError::NoKnownPeers), Err(KadError::Timeout), concat),
+            Err(KadError::Timeout)
+        ));
+    }
+
+    fn addrs(v: &[&str]) -> Vec<Multiaddr> {
+        v.iter().map(|a| a.parse().unwrap()).collect()
+    }
+
+    fn peer(id: &PeerId, v: &[&str]) -> KadPeer {
+        KadPeer {
+            node_id: *id,
+            multiaddrs: addrs(v),
+            connection_ty: KadConnectionType::NotConnected,
+        }
+    }
+
+    #[test]
+    fn test_scope_filters() {
+        let lan = with_scope(KademliaConfig::default(), AddressScope::Private);
+        let wan = with_scope(
+            KademliaConfig::default().with_routing_table_filter(IpGroupFilter::new(1, 1)),
+            AddressScope::Public,
+        );
+        let (lan, mut wan) = (lan.routing_table_filter().unwrap(), wan.routing_table_filter().unwrap().clone());
+
+        let (a, b) = (PeerId::random(), PeerId::random());
+        let private = addrs(&["/ip4/192.168.1.1/tcp/4001"]);
+        let public = addrs(&["/ip4/1.2.3.4/tcp/4001"]);
+
+        // the LAN DHT admits only private peers, and the WAN DHT only public peers
+        assert!(lan.allow(&a, &private, 1) && !lan.allow(&a, &public, 1));
+        assert!(!wan.allow(&a, &private, 1) && wan.allow(&a, &public, 1));
+
+        // the IP group filter of the WAN configuration is still applied
+        wan.on_added(&a, &public, 1);
+        assert!(!wan.allow(&b, &addrs(&["/ip4/1.2.100.100/tcp/4001"]), 1));
+    }
+
+    #[test]
+    fn test_merge_results() {
+        let (a, b, c) = (PeerId::random(), PeerId::random(), PeerId::random());
+
+        let w = peer(&a, &["/ip4/1.2.3.4/tcp/4001"]);
+        let l = peer(&a, &["/ip4/192.168.1.1/tcp/4001", "/ip4/1.2.3.4/tcp/4001"]);
+        let merged = merge(Ok(l), Ok(w), merge_peer).unwrap();
+        assert_eq!(merged.multiaddrs, addrs(&["/ip4/1.2.3.4/tcp/4001", "/ip4/192.168.1.1/tcp/4001"]));
+
+        let w = vec![peer(&a, &[]), peer(&b, &[])];
+        let l = vec![peer(&b, &[]), peer(&c, &[])];
+        let ids = |v: Vec<KadPeer>| v.into_iter().map(|p| p.node_id).collect::<Vec<_>>();
+        assert_eq!(ids(merge_providers(w.clone(), l.clone(), 0)), vec![a, b, c]);
+        assert_eq!(ids(merge_providers(w, l, 2)), vec![a, b]);
+    }
+}
@@ -0,0 +1,214 @@
+// Copyright 2020 Netwarps Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Kademlia events.
+//!
+//! The activities of Kademlia, i.e. the changes of the routing table, the inbound
+//! requests, the iterative queries and the records stored or expired, are reported
+//! as [`KadEvent`]s to the subscribers, see [`Control::subscribe`](crate::Control::subscribe).
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures::channel::mpsc;
+
+use libp2prs_core::PeerId;
+
+use crate::protocol::KadRequestMsg;
+use crate::query::QueryType;
+use crate::record;
+
+/// The events of Kademlia.
+#[derive(Debug, Clone)]
+pub enum KadEvent {
+    /// A peer has been added to the bucket of the routing table.
+    PeerAdded { peer: PeerId, bucket: usize },
+    /// A peer has been evicted from the bucket of the routing table, either
+    /// replaced by a new peer or removed as dead.
+    PeerEvicted { peer: PeerId, bucket: usize },
+    /// The aliveness of a peer in the bucket has been refreshed.
+    PeerRefreshed { peer: PeerId, bucket: usize },
+    /// A Kad request has been received from the peer.
+    InboundRequest { peer: PeerId, request: RequestKind },
+    /// An iterative query has been started.
+    QueryStarted { kind: QueryKind, key: record::Key },
+    /// An iterative query has finished.
+    QueryFinished(QueryReport),
+    /// A record has been stored, as requested by the peer. The source is the
+    /// local peer for the records put locally.
+    RecordStored { key: record::Key, source: PeerId },
+    /// A record has expired and been removed.
+    RecordExpired { key: record::Key },
+    /// A provider record has been stored.
+    ProviderStored { key: record::Key, provider: PeerId },
+    /// A provider record has expired and been removed.
+    ProviderExpired { key: record::Key, provider: PeerId },
+}
+
+/// The type of inbound Kad requests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RequestKind {
+    Ping,
+    FindNode,
+    GetProviders,
+    AddProvider,
+    GetValue,
+    PutValue,
+}
+
+impl From<&KadRequestMsg> for RequestKind {
+    fn from(request: &KadRequestMsg) -> Self {
+        match request {
+            KadRequestMsg::Ping => RequestKind::Ping,
+            KadRequestMsg::FindNode { .. } => RequestKind::FindNode,
+            KadRequestMsg::GetProviders { .. } => RequestKind::GetProviders,
+            KadRequestMsg::AddProvider { .. } => RequestKind::AddProvider,
+            KadRequestMsg::GetValue { .. } => RequestKind::GetValue,
+            KadRequestMsg::PutValue { .. } => RequestKind::PutValue,
+        }
+    }
+}
+
+/// The type of queries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueryKind {
+    FindPeer,
+    GetClosestPeers,
+    GetProviders,
+    GetRecord,
+    AddProvider,
+    PutRecord,
+}
+
+impl From<&QueryType> for QueryKind {
+    fn from(qt: &QueryType) -> Self {
+        match qt {
+            QueryType::FindPeer => QueryKind::FindPeer,
+            QueryType::GetClosestPeers => QueryKind::GetClosestPeers,
+            QueryType::GetProviders { .. } => QueryKind::GetProviders,
+            QueryType::GetRecord { .. } => QueryKind::GetRecord,
+            QueryType::AddProvider { .. } => QueryKind::AddProvider,
+            QueryType::PutRecord { .. } => QueryKind::PutRecord,
+        }
+    }
+}
+
+/// How an iterative query ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueryOutcome {
+    /// The query has completed with a result.
+    Succeeded,
+    /// The query has completed without any result.
+    Failed,
+    /// The query has timed out.
+    Timeout,
+    /// The streaming query has been cancelled.
+    Cancelled,
+}
+
+/// The report of an iterative query.
+#[derive(Debug, Clone)]
+pub struct QueryReport {
+    pub kind: QueryKind,
+    pub key: record::Key,
+    pub outcome: QueryOutcome,
+    /// How long the query lasted.
+    pub duration: Duration,
+    /// The number of requests sent to the peers.
+    pub requests: u32,
+    /// The number of requests which succeeded.
+    pub success: u32,
+    /// The number of requests which failed.
+    pub failure: u32,
+    /// The statistics of each disjoint path, empty if the query has a single path.
+    pub paths: Vec<PathReport>,
+}
+
+/// The statistics of a disjoint path of an iterative query.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PathReport {
+    /// The number of requests sent to the peers of the path.
+    pub requests: u32,
+    /// The number of requests which succeeded.
+    pub success: u32,
+    /// The number of requests which failed.
+    pub failure: u32,
+}
+
+/// The sender of Kad events, which delivers the events to all the subscribers.
+///
+/// The subscribers are removed once they are dropped.
+#[derive(Clone, Default)]
+pub(crate) struct KadEventSender(Arc<Mutex<Vec<mpsc::UnboundedSender<KadEvent>>>>);
+
+impl KadEventSender {
+    /// Adds a subscriber, returns the receiving end of the events.
+    pub(crate) fn subscribe(&self) -> mpsc::UnboundedReceiver<KadEvent> {
+        let (tx, rx) = mpsc::unbounded();
+        self.0.lock().unwrap().push(tx);
+        rx
+    }
+
+    /// Delivers the event to the subscribers.
+    pub(crate) fn emit(&self, event: KadEvent) {
+        let mut subscribers = self.0.lock().unwrap();
+        subscribers.retain(|tx| tx.unbounded_send(event.clone()).is_ok());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+    use libp2prs_runtime::task;
+
+    #[test]
+    fn test_event_sender() {
+        let sender = KadEventSender::default();
+        // no subscriber
+        sender.emit(KadEvent::RecordExpired {
+            key: record::Key::new(&"a"),
+        });
+
+        let mut rx1 = sender.subscribe();
+        let rx2 = sender.subscribe();
+        sender.emit(KadEvent::RecordExpired {
+            key: record::Key::new(&"b"),
+        });
+        assert_eq!(sender.0.lock().unwrap().len(), 2);
+
+        // the dropped subscriber is removed
+        drop(rx2);
+        sender.emit(KadEvent::RecordExpired {
+            key: record::Key::new(&"c"),
+        });
+        assert_eq!(sender.0.lock().unwrap().len(), 1);
+
+        let keys = task::block_on(rx1.by_ref().take(2).collect::<Vec<_>>());
+        let keys = keys
+            .into_iter()
+            .map(|e| match e {
+                KadEvent::RecordExpired { key } => key,
+                _ => panic!("unexpected event"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(keys, vec![record::Key::new(&"b"), record::Key::new(&"c")]);
+    }
+}
@@ -22,8 +22,10 @@ use fnv::{FnvHashMap, FnvHashSet};
 use std::borrow::Borrow;
 use std::fmt;
 use std::num::NonZeroUsize;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime};
 
 use futures::{
     channel::{mpsc, oneshot},
@@ -31,20 +33,29 @@ use futures::{
     select,
 };
 
+use libp2prs_core::identity::Keypair;
 use libp2prs_core::{Multiaddr, PeerId, ProtocolId};
 use libp2prs_runtime::task;
 use libp2prs_swarm::Control as SwarmControl;
 
 use crate::control::{Control, ControlCommand, DumpCommand};
+use crate::events::{KadEvent, KadEventSender, RequestKind};
 use crate::protocol::{
     KadConnectionType, KadMessenger, KadMessengerView, KadPeer, KadProtocolHandler, KadRequestMsg, KadResponseMsg,
     KademliaProtocolConfig, ProtocolEvent, RefreshStage,
 };
+use crate::ratelimit::{RateLimitConfig, RateLimitStats, RateLimiter};
 
 use crate::addresses::PeerInfo;
-use crate::kbucket::KBucketsTable;
-use crate::query::{FixedQuery, IterativeQuery, PeerRecord, QueryConfig, QueryStats, QueryStatsAtomic, QueryType};
+use crate::discovery::{self, DiscoveryConfig, DiscoveryStats};
+use crate::kbucket::{IRoutingTableFilter, KBucketsTable, RoutingTableFilter};
+use crate::query::{FixedQuery, IterativeQuery, PeerRecord, QueryConfig, QuerySink, QueryStats, QueryStatsAtomic, QueryType, Quorum};
+use crate::record::ipns::{ipns_key, IpnsRecord};
+use crate::record::validator::{NamespacedValidator, Validator};
+use crate::reprovider::{self, ReproviderStats, ReproviderStrategy};
+use crate::snapshot::{load_snapshot, save_snapshot, SnapshotPeer};
 use crate::store::RecordStore;
+use crate::task_limit::TaskLimiter;
 use crate::{kbucket, record, KadError, ProviderRecord, Record};
 use libp2prs_core::peerstore::{ADDRESS_TTL, PROVIDER_ADDR_TTL};
 use libp2prs_swarm::protocol_handler::{IProtocolHandler, ProtocolImpl};
@@ -85,6 +96,21 @@ pub struct Kademlia<TStore> {
     /// The timer runtime handle of Provider cleanup job.
     refresh_timer_handle: Option<task::TaskHandle<()>>,
 
+    /// The timer runtime handle of routing table snapshot job.
+    snapshot_timer_handle: Option<task::TaskHandle<()>>,
+
+    /// The runtime handle of the latest snapshot saving job.
+    snapshot_save_handle: Option<task::TaskHandle<()>>,
+
+    /// The timer runtime handle of IPNS record republishing job.
+    republish_timer_handle: Option<task::TaskHandle<()>>,
+
+    /// The timer runtime handle of provider record reproviding job.
+    reprovide_timer_handle: Option<task::TaskHandle<()>>,
+
+    /// The timer runtime handle of random-walk discovery job.
+    discovery_timer_handle: Option<task::TaskHandle<()>>,
+
     /// The periodic interval to cleanup expired provider records.
     cleanup_interval: Duration,
 
@@ -112,6 +138,70 @@ pub struct Kademlia<TStore> {
     /// The record storage.
     store: TStore,
 
+    /// The validator of records, for both inbound and outbound.
+    validator: NamespacedValidator,
+
+    /// The mode of Kademlia, client, server or auto.
+    mode: KademliaMode,
+
+    /// If false, the inbound Kad requests are rejected, AKA. client mode. It is shared
+    /// with KadProtocolHandler.
+    allow_listening: Arc<AtomicBool>,
+
+    /// The filter which decides if a peer is allowed to join the routing table.
+    rt_filter: Option<IRoutingTableFilter>,
+
+    /// If true, only the global addresses of the peers are sent in the responses.
+    global_addrs_only: bool,
+
+    /// The file to save the snapshot of the routing table. `None` disables the snapshot.
+    snapshot_path: Option<PathBuf>,
+
+    /// The periodic interval to save the snapshot of the routing table.
+    snapshot_interval: Duration,
+
+    /// The names published, i.e. the keypair and value of the IPNS records.
+    published_names: FnvHashMap<PeerId, (Keypair, Vec<u8>)>,
+
+    /// How long the IPNS records are valid.
+    ipns_record_lifetime: Duration,
+
+    /// The periodic interval to republish the IPNS records.
+    /// `None` disables republishing.
+    ipns_republish_interval: Option<Duration>,
+
+    /// The periodic interval to reprovide the keys provided by the local node.
+    /// `None` disables reproviding.
+    provider_publication_interval: Option<Duration>,
+
+    /// The strategy to decide which keys are reprovided.
+    reprovider_strategy: ReproviderStrategy,
+
+    /// The maximum number of keys announced with one lookup.
+    reprovide_batch_size: usize,
+
+    /// The progress of reproviding.
+    reprovider_stats: Arc<Mutex<ReproviderStats>>,
+
+    /// The configuration of random-walk discovery. `None` disables discovery.
+    discovery: Option<DiscoveryConfig>,
+
+    /// The progress of random-walk discovery.
+    discovery_stats: Arc<Mutex<DiscoveryStats>>,
+
+    /// The sender of Kad events to the subscribers.
+    events: KadEventSender,
+
+    /// The rate limiter of the inbound requests.
+    rate_limiter: Arc<Mutex<RateLimiter>>,
+
+    /// The maximum number of provider records stored for a remote peer.
+    max_providers_per_peer: Option<usize>,
+
+    /// The number of provider records stored for each remote peer, only
+    /// accounted if `max_providers_per_peer` is set.
+    provider_counts: FnvHashMap<PeerId, usize>,
+
     // Used to communicate with Swarm.
     swarm: Option<SwarmControl>,
 
@@ -133,8 +223,13 @@ pub struct Kademlia<TStore> {
 #[derive(Debug, Clone, Default)]
 pub struct KademliaStats {
     pub total_refreshes: usize,
+    /// The number of peers rejected by the routing table filter.
+    pub rejected_peers: usize,
     pub query: QueryStats,
     pub message_rx: MessageStats,
+    pub reprovider: ReproviderStats,
+    pub rate_limit: RateLimitStats,
+    pub discovery: DiscoveryStats,
 }
 
 #[derive(Debug, Clone, Default)]
@@ -147,6 +242,21 @@ pub struct MessageStats {
     pub(crate) put_value: usize,
 }
 
+/// The mode of Kademlia.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KademliaMode {
+    /// Client mode. The local node sends Kad requests to others, but neither
+    /// serves the inbound Kad requests nor advertises the Kad protocol.
+    Client,
+    /// Server mode. The local node serves the inbound Kad requests.
+    Server,
+    /// Switches between the client and server mode, based on the reachability
+    /// of the local node. It is in server mode if any of the local addresses is
+    /// a globally reachable IP address, otherwise, client mode. Connected peers
+    /// learn about a switch by an identify push.
+    Auto,
+}
+
 /// The configuration for the `Kademlia` behaviour.
 ///
 /// The configuration is consumed by [`Kademlia::new`].
@@ -163,6 +273,18 @@ pub struct KademliaConfig {
     provider_publication_interval: Option<Duration>,
     connection_idle_timeout: Duration,
     check_kad_peer_interval: Duration,
+    validator: NamespacedValidator,
+    mode: KademliaMode,
+    rt_filter: Option<IRoutingTableFilter>,
+    global_addrs_only: bool,
+    snapshot_path: Option<PathBuf>,
+    snapshot_interval: Duration,
+    ipns_record_lifetime: Duration,
+    ipns_republish_interval: Option<Duration>,
+    reprovider_strategy: ReproviderStrategy,
+    reprovide_batch_size: usize,
+    rate_limit: RateLimitConfig,
+    discovery: Option<DiscoveryConfig>,
 }
 
 impl Default for KademliaConfig {
@@ -192,6 +314,18 @@ impl Default for KademliaConfig {
             provider_record_ttl: Some(Duration::from_secs(24 * 60 * 60)),
             connection_idle_timeout: Duration::from_secs(10),
             check_kad_peer_interval,
+            validator: Default::default(),
+            mode: KademliaMode::Server,
+            rt_filter: None,
+            global_addrs_only: false,
+            snapshot_path: None,
+            snapshot_interval: Duration::from_secs(10 * 60),
+            ipns_record_lifetime: Duration::from_secs(24 * 60 * 60),
+            ipns_republish_interval: Some(Duration::from_secs(4 * 60 * 60)),
+            reprovider_strategy: ReproviderStrategy::All,
+            reprovide_batch_size: 256,
+            rate_limit: Default::default(),
+            discovery: None,
         }
     }
 }
@@ -244,6 +378,17 @@ impl KademliaConfig {
         self
     }
 
+    /// Sets the number of disjoint paths for iterative queries.
+    ///
+    /// With `d` disjoint paths, as described in S/Kademlia, the lookups of
+    /// `find_peer`, `get_value` and `find_providers` run on `d` independent
+    /// paths, and no peer is queried by more than one path. It makes the lookup
+    /// harder to be steered by a malicious peer. Defaults to `None`, a single path.
+    pub fn with_disjoint_paths(mut self, d: Option<NonZeroUsize>) -> Self {
+        self.query_config.disjoint_paths = d;
+        self
+    }
+
     /// Sets the interval for routing table refresh.
     ///
     /// The Kad routing table will be refreshed automatically per the interval.
@@ -331,6 +476,7 @@ impl KademliaConfig {
     /// Sets the interval at which provider records for keys provided
     /// by the local node are re-published.
     ///
+    /// The keys are re-published in batches spread across the interval.
     /// `None` means that stored provider records are never automatically
     /// re-published.
     ///
@@ -354,6 +500,114 @@ impl KademliaConfig {
         self.protocol_config.set_max_packet_size(size);
         self
     }
+
+    /// Registers a record validator for the given namespace.
+    ///
+    /// The validator applies to the records whose key is in the form of
+    /// `/namespace/...`, e.g. `/ipns/<peer-id>` for the namespace "ipns". Records
+    /// in namespaces without a validator are accepted as they are. A validator
+    /// for "pk" is registered by default.
+    pub fn with_validator<V: Validator + 'static>(mut self, namespace: &str, validator: V) -> Self {
+        self.validator.insert(namespace, validator);
+        self
+    }
+
+    /// Sets the mode of Kademlia.
+    ///
+    /// In client mode, the Kad protocol is not advertised via Identify and the
+    /// inbound Kad requests are rejected. The default is server mode.
+    pub fn with_mode(mut self, mode: KademliaMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Sets the filter of the routing table, e.g. [`IpGroupFilter`](crate::kbucket::IpGroupFilter).
+    ///
+    /// Peers rejected by the filter are not added to the routing table. There is
+    /// no filter by default.
+    pub fn with_routing_table_filter<F: RoutingTableFilter + 'static>(mut self, filter: F) -> Self {
+        self.rt_filter = Some(Box::new(filter));
+        self
+    }
+
+    /// Returns the filter of the routing table, if any.
+    pub(crate) fn routing_table_filter(&self) -> Option<&IRoutingTableFilter> {
+        self.rt_filter.as_ref()
+    }
+
+    // Sets whether only the global addresses of the peers are sent in the responses,
+    // as required by the WAN DHT.
+    pub(crate) fn with_global_addrs_only(mut self, global_addrs_only: bool) -> Self {
+        self.global_addrs_only = global_addrs_only;
+        self
+    }
+
+    /// Sets the file to save the snapshot of the routing table.
+    ///
+    /// The routing table is saved periodically and when Kademlia is closed, and it
+    /// is reloaded when Kademlia starts. The reloaded peers are validated with Kad
+    /// pings in the background, and serve as extra candidates for bootstrapping.
+    /// The default is `None`, which disables the snapshot.
+    pub fn with_snapshot_path(mut self, path: Option<PathBuf>) -> Self {
+        self.snapshot_path = path;
+        self
+    }
+
+    /// Sets the interval for saving the snapshot of the routing table.
+    ///
+    /// The default is 10 minutes.
+    pub fn with_snapshot_interval(mut self, interval: Duration) -> Self {
+        self.snapshot_interval = interval;
+        self
+    }
+
+    /// Sets how long the IPNS records published are valid.
+    ///
+    /// The default is 24 hours.
+    pub fn with_ipns_record_lifetime(mut self, lifetime: Duration) -> Self {
+        self.ipns_record_lifetime = lifetime;
+        self
+    }
+
+    /// Sets the interval for republishing the IPNS records.
+    ///
+    /// It should be well below the lifetime of the records. The default is 4
+    /// hours. Sets to `None` to disable republishing.
+    pub fn with_ipns_republish_interval(mut self, interval: Option<Duration>) -> Self {
+        self.ipns_republish_interval = interval;
+        self
+    }
+
+    /// Sets the strategy to decide which keys are re-published. The default is
+    /// [`ReproviderStrategy::All`].
+    pub fn with_reprovider_strategy(mut self, strategy: ReproviderStrategy) -> Self {
+        self.reprovider_strategy = strategy;
+        self
+    }
+
+    /// Sets the maximum number of keys which are announced to the closest peers
+    /// found by one lookup, when they are close enough in the keyspace.
+    ///
+    /// The default is 256.
+    pub fn with_reprovide_batch_size(mut self, size: usize) -> Self {
+        self.reprovide_batch_size = size;
+        self
+    }
+
+    /// Sets the rate limits of the inbound requests, and the cap of provider
+    /// records per peer. Nothing is limited by default.
+    pub fn with_rate_limit(mut self, config: RateLimitConfig) -> Self {
+        self.rate_limit = config;
+        self
+    }
+
+    /// Enables random-walk discovery, which looks up random keys and connects
+    /// to the peers found, until there are enough connections. It is disabled
+    /// by default.
+    pub fn with_discovery(mut self, config: DiscoveryConfig) -> Self {
+        self.discovery = Some(config);
+        self
+    }
 }
 
 /// KadPoster is used to generate ProtocolEvent to Kad main loop.
@@ -398,6 +652,27 @@ where
 
         Kademlia {
             store,
+            validator: config.validator,
+            mode: config.mode,
+            // auto mode starts as a client, until we know we are reachable
+            allow_listening: Arc::new(AtomicBool::new(config.mode == KademliaMode::Server)),
+            rt_filter: config.rt_filter,
+            global_addrs_only: config.global_addrs_only,
+            snapshot_path: config.snapshot_path,
+            snapshot_interval: config.snapshot_interval,
+            published_names: Default::default(),
+            ipns_record_lifetime: config.ipns_record_lifetime,
+            ipns_republish_interval: config.ipns_republish_interval,
+            provider_publication_interval: config.provider_publication_interval,
+            reprovider_strategy: config.reprovider_strategy,
+            reprovide_batch_size: config.reprovide_batch_size,
+            reprovider_stats: Default::default(),
+            discovery: config.discovery,
+            discovery_stats: Default::default(),
+            events: Default::default(),
+            max_providers_per_peer: config.rate_limit.max_providers_per_peer(),
+            rate_limiter: Arc::new(Mutex::new(RateLimiter::new(config.rate_limit))),
+            provider_counts: Default::default(),
             swarm: None,
             event_rx,
             event_tx,
@@ -413,6 +688,11 @@ where
             connected_peers: Default::default(),
             provider_timer_handle: None,
             refresh_timer_handle: None,
+            snapshot_timer_handle: None,
+            snapshot_save_handle: None,
+            republish_timer_handle: None,
+            reprovide_timer_handle: None,
+            discovery_timer_handle: None,
             cleanup_interval: config.cleanup_interval,
             refresh_interval: config.refresh_interval,
             record_ttl: config.record_ttl,
@@ -557,26 +837,52 @@ where
         let now = Instant::now();
         let key = kbucket::Key::from(peer);
 
+        let bucket_index = self.kbuckets.bucket_index(&key);
+
         log::debug!(
             "trying to add a peer: {:?} bucket-index={:?}, query={}, permanent={}",
             peer,
-            self.kbuckets.bucket_index(&key),
+            bucket_index,
             queried,
             permanent
         );
 
+        let bucket_index = bucket_index.unwrap_or_default() as usize;
+        let mut present = None;
         match self.kbuckets.entry(&key) {
             kbucket::Entry::Present(mut entry) => {
+                present = Some(entry.value().is_permanent());
                 // already in RT, update the node's aliveness if queried is true
                 if queried {
                     entry.value().set_aliveness(Some(Instant::now()));
                     log::debug!("{:?} updated: {:?}", peer, entry.value());
+                    self.events.emit(KadEvent::PeerRefreshed {
+                        peer,
+                        bucket: bucket_index,
+                    });
                 }
             }
             kbucket::Entry::Absent(mut entry) => {
+                // check the routing table filter with the addresses of the peer
+                let addrs = self.swarm.as_ref().and_then(|s| s.get_addrs(&peer)).unwrap_or_default();
+                if let Some(filter) = self.rt_filter.as_ref() {
+                    if !filter.allow(&peer, &addrs, bucket_index) {
+                        log::debug!("Peer rejected by routing table filter: {} {:?}", peer, addrs);
+                        self.stats.rejected_peers += 1;
+                        return;
+                    }
+                }
+
                 let info = PeerInfo::new(queried, permanent);
                 if entry.insert(info.clone()) {
                     log::debug!("Peer added to routing table: {} {:?}", peer, info);
+                    if let Some(filter) = self.rt_filter.as_mut() {
+                        filter.on_added(&peer, &addrs, bucket_index);
+                    }
+                    self.events.emit(KadEvent::PeerAdded {
+                        peer,
+                        bucket: bucket_index,
+                    });
                     // pin this peer in PeerStore to prevent GC from recycling multiaddr
                     if let Some(s) = self.swarm.as_ref() {
                         s.pin(&peer)
@@ -600,6 +906,18 @@ where
                         }
                         // now try to insert the value again
                         let _ = entry.insert(info);
+                        if let Some(filter) = self.rt_filter.as_mut() {
+                            filter.on_removed(key.preimage());
+                            filter.on_added(&peer, &addrs, bucket_index);
+                        }
+                        self.events.emit(KadEvent::PeerEvicted {
+                            peer: key.into_preimage(),
+                            bucket: bucket_index,
+                        });
+                        self.events.emit(KadEvent::PeerAdded {
+                            peer,
+                            bucket: bucket_index,
+                        });
                         // pin this peer in PeerStore to prevent GC from recycling multiaddr
                         if let Some(s) = self.swarm.as_ref() {
                             s.pin(&peer)
@@ -611,6 +929,32 @@ where
             }
             _ => {}
         }
+
+        if let Some(permanent) = present {
+            self.refilter_peer(peer, bucket_index, permanent);
+        }
+    }
+
+    // Applies the routing table filter again to the peer in the routing table, since
+    // its addresses might have been updated, e.g. by Identify. The peer is removed if
+    // it is rejected, unless it is permanent.
+    fn refilter_peer(&mut self, peer: PeerId, bucket: usize, permanent: bool) {
+        let filter = match self.rt_filter.as_mut() {
+            Some(filter) => filter,
+            None => return,
+        };
+        let addrs = self.swarm.as_ref().and_then(|s| s.get_addrs(&peer)).unwrap_or_default();
+        if filter.on_updated(&peer, &addrs, bucket) {
+            return;
+        }
+        if permanent {
+            filter.on_added(&peer, &addrs, bucket);
+            return;
+        }
+
+        log::debug!("Peer rejected by routing table filter after update: {} {:?}", peer, addrs);
+        self.stats.rejected_peers += 1;
+        self.try_remove_peer(peer, false);
     }
 
     /// Tries to remove a peer from the routing table.
@@ -619,13 +963,9 @@ where
     /// not even pending insertion.
     fn try_remove_peer(&mut self, peer: PeerId, forced: bool) -> Option<kbucket::EntryView<kbucket::Key<PeerId>, PeerInfo>> {
         let key = kbucket::Key::from(peer);
+        let bucket = self.kbuckets.bucket_index(&key).unwrap_or_default() as usize;
 
-        log::debug!(
-            "trying to remove a peer: {:?} bucket-index={:?}, forced={}",
-            peer,
-            self.kbuckets.bucket_index(&key),
-            forced
-        );
+        log::debug!("trying to remove a peer: {:?} bucket-index={:?}, forced={}", peer, bucket, forced);
 
         match self.kbuckets.entry(&key) {
             kbucket::Entry::Present(mut entry) => {
@@ -634,6 +974,10 @@ where
                     if let Some(s) = self.swarm.as_ref() {
                         s.unpin(&peer)
                     }
+                    if let Some(filter) = self.rt_filter.as_mut() {
+                        filter.on_removed(&peer);
+                    }
+                    self.events.emit(KadEvent::PeerEvicted { peer, bucket });
                     Some(entry.remove())
                 } else {
                     entry.value().set_aliveness(None);
@@ -670,6 +1014,11 @@ where
         self.kbuckets.iter().filter(|b| !b.is_empty())
     }
 
+    /// Returns the validator of records.
+    pub(crate) fn validator(&self) -> &NamespacedValidator {
+        &self.validator
+    }
+
     /// Returns the k-bucket for the distance to the given key.
     ///
     /// Returns `None` if the given key refers to the local key.
@@ -705,7 +1054,9 @@ where
             self.query_config.clone(),
             seeds,
             self.poster(),
+            self.validator.clone(),
             self.query_stats.clone(),
+            self.events.clone(),
         )
     }
 
@@ -741,9 +1092,12 @@ where
     /// count: How many providers are needed. 0 means to lookup the DHT to find as
     /// many as possible.
     ///
+    /// sink: If specified, the providers are delivered into the sink as soon as
+    /// they are found.
+    ///
     /// The result of this operation is delivered into the callback
     /// Fn(Result<Vec<KadPeer>>).
-    fn get_providers<F>(&mut self, key: record::Key, count: usize, f: F)
+    fn get_providers<F>(&mut self, key: record::Key, count: usize, sink: Option<(QuerySink, oneshot::Receiver<()>)>, f: F)
     where
         F: FnOnce(Result<Vec<KadPeer>>) + Send + 'static,
     {
@@ -751,10 +1105,16 @@ where
 
         if count != 0 && provider_peers.len() >= count {
             // ok, we have enough providers for this key, simply return
+            if let Some((sink, _)) = sink {
+                sink.send_providers(&provider_peers);
+            }
             f(Ok(provider_peers));
         } else {
             let local = if provider_peers.is_empty() { None } else { Some(provider_peers) };
-            let q = self.prepare_iterative_query(QueryType::GetProviders { count, local }, key);
+            let mut q = self.prepare_iterative_query(QueryType::GetProviders { count, local }, key);
+            if let Some((sink, cancel)) = sink {
+                q = q.with_sink(sink, cancel);
+            }
 
             q.run(|r| {
                 f(r.and_then(|r| r.providers.ok_or(KadError::NotFound)));
@@ -764,18 +1124,26 @@ where
 
     /// Performs a lookup of a record to the given key.
     ///
+    /// The lookup terminates as soon as `quorum` records are found, evaluated
+    /// w.r.t. the K value. All distinct records are returned, the best record
+    /// as selected by the validator comes first.
+    ///
+    /// If `sink` is specified, the records are delivered into the sink as soon
+    /// as they are found.
+    ///
     /// The result of this operation is delivered into the callback
     /// Fn(Result<Vec<PeerRecord>>).
-    fn get_record<F>(&mut self, key: record::Key, f: F)
+    fn get_record<F>(&mut self, key: record::Key, quorum: Quorum, sink: Option<(QuerySink, oneshot::Receiver<()>)>, f: F)
     where
-        F: FnOnce(Result<PeerRecord>) + Send + 'static,
+        F: FnOnce(Result<Vec<PeerRecord>>) + Send + 'static,
     {
-        let quorum = self.query_config.k_value.get();
+        let quorum = quorum.eval(self.query_config.k_value).get();
         let mut records = Vec::with_capacity(quorum);
 
         if let Some(record) = self.store.get(&key) {
             if record.is_expired(Instant::now()) {
-                self.store.remove(&key)
+                self.store.remove(&key);
+                self.events.emit(KadEvent::RecordExpired { key: key.clone() });
             } else {
                 records.push(PeerRecord {
                     peer: None,
@@ -785,30 +1153,54 @@ where
         }
 
         if records.len() >= quorum {
-            // ok, we have enough, simply return first item
-            let record = records.first().cloned().map_or(Err(KadError::NotFound), Ok);
-            f(record);
+            // ok, we have enough, the local record is good enough
+            if let Some((sink, _)) = sink {
+                sink.send_records(&records);
+            }
+            f(Ok(records));
         } else {
             let config = self.query_config.clone();
             let messengers = self.messengers.clone().expect("must be Some");
 
             let local = if records.is_empty() { None } else { Some(records) };
-            let q = self.prepare_iterative_query(QueryType::GetRecord { quorum, local }, key);
+            let validator = self.validator.clone();
+            let mut q = self.prepare_iterative_query(QueryType::GetRecord { quorum, local }, key.clone());
+            if let Some((sink, cancel)) = sink {
+                q = q.with_sink(sink, cancel);
+            }
             let stats = self.query_stats.clone();
-            q.run(|r| {
+            q.run(move |r| {
                 f(r.and_then(|r| {
-                    let record = r.records.as_ref().map(|r| r.first().cloned());
-                    if let Some(Some(record)) = record.clone() {
-                        if let Some(cache_peers) = r.cache_peers {
-                            let record = record.record;
-                            let fixed_query = FixedQuery::new(QueryType::PutRecord { record }, messengers, config, cache_peers, stats);
-                            fixed_query.run(|_| {});
+                    let mut records = r.records.ok_or(KadError::NotFound)?;
+
+                    // select the best record out of all records found
+                    let values = records.iter().map(|r| r.record.value.as_slice()).collect::<Vec<_>>();
+                    let best = validator.select(&key, &values)?;
+                    let best = records.remove(best);
+
+                    // correct the peers which have an outdated record, or don't have any
+                    let mut outdated = records
+                        .iter()
+                        .filter(|r| r.record.value != best.record.value)
+                        .filter_map(|r| r.peer)
+                        .collect::<Vec<_>>();
+                    outdated.extend(r.cache_peers.unwrap_or_default());
+                    if !outdated.is_empty() {
+                        log::debug!("GetValue, correcting outdated peers {:?}", outdated);
+                        let record = best.record.clone();
+                        let fixed_query = FixedQuery::new(QueryType::PutRecord { record }, messengers, config, outdated, stats);
+                        fixed_query.run(|_| {});
+                    }
+
+                    // only one record for each distinct value, the best comes first
+                    let mut distinct = vec![best];
+                    for r in records {
+                        if distinct.iter().all(|d| d.record.value != r.record.value) {
+                            distinct.push(r);
                         }
                     }
 
-                    // only need 1 item, take the first item
-                    // r.records.ok_or(KadError::NotFound)
-                    record.map_or(Err(KadError::NotFound), |r| r.map_or(Err(KadError::NotFound), Ok))
+                    Ok(distinct)
                 }));
             });
         }
@@ -827,14 +1219,23 @@ where
     /// with an explicit expiration will always expire at that instant and until then
     /// is subject to regular (re-)replication and (re-)publication.
     ///
-    /// The result of this operation is delivered into the callback
-    /// Fn(Result<()>).
-    fn put_record<F>(&mut self, key: record::Key, value: Vec<u8>, f: F)
+    /// The record is sent to the closest peers of the key, and the operation
+    /// fails with [`KadError::QuorumFailed`] if less than `quorum` of them,
+    /// evaluated w.r.t. the number of the closest peers, have stored it.
+    ///
+    /// The result of this operation, the number of peers which have stored the
+    /// record, is delivered into the callback Fn(Result<usize>).
+    fn put_record<F>(&mut self, key: record::Key, value: Vec<u8>, quorum: Quorum, f: F)
     where
-        F: FnOnce(Result<()>) + Send + 'static,
+        F: FnOnce(Result<usize>) + Send + 'static,
     {
         // TODO: probably we should check if there is a old record with the same key?
 
+        if let Err(e) = self.validator.validate(&key, &value) {
+            f(Err(e));
+            return;
+        }
+
         let mut record = Record {
             key,
             value,
@@ -842,11 +1243,16 @@ where
             expires: None,
         };
 
-        record.publisher = Some(*self.kbuckets.self_key().preimage());
+        let local_id = *self.kbuckets.self_key().preimage();
+        record.publisher = Some(local_id);
         if let Err(e) = self.store.put(record.clone()) {
             f(Err(e));
             return;
         }
+        self.events.emit(KadEvent::RecordStored {
+            key: record.key.clone(),
+            source: local_id,
+        });
         record.expires = record.expires.or_else(|| self.record_ttl.map(|ttl| Instant::now() + ttl));
 
         let config = self.query_config.clone();
@@ -858,8 +1264,18 @@ where
                 f(Err(e));
             } else {
                 let peers = peers.unwrap().into_iter().map(KadPeer::into).collect::<Vec<_>>();
+                let quorum = NonZeroUsize::new(peers.len()).map_or(0, |total| quorum.eval(total).get());
                 let fixed_query = FixedQuery::new(QueryType::PutRecord { record }, messengers, config, peers, stats);
-                fixed_query.run(f);
+                fixed_query.run(move |r| {
+                    f(r.and_then(|success| {
+                        if success < quorum {
+                            log::info!("PutValue, quorum failed, {} of {} succeeded", success, quorum);
+                            Err(KadError::QuorumFailed { success, quorum })
+                        } else {
+                            Ok(success)
+                        }
+                    }));
+                });
             }
         });
     }
@@ -923,6 +1339,9 @@ where
 
     fn dump_statistics(&mut self) -> KademliaStats {
         self.stats.query = self.query_stats.to_view();
+        self.stats.reprovider = self.reprovider_stats.lock().unwrap().clone();
+        self.stats.rate_limit = self.rate_limiter.lock().unwrap().stats.clone();
+        self.stats.discovery = self.discovery_stats.lock().unwrap().clone();
         self.stats.clone()
     }
 
@@ -1003,8 +1422,88 @@ where
             } else {
                 let peers = peers.unwrap().into_iter().map(KadPeer::into).collect();
                 let fixed_query = FixedQuery::new(QueryType::AddProvider { provider, addresses }, messengers, config, peers, stats);
-                fixed_query.run(f);
+                fixed_query.run(|r| f(r.map(|_| ())));
+            }
+        });
+    }
+
+    /// Establishes the local node as a provider of the given keys, and announces
+    /// them in batches.
+    ///
+    /// The number of keys announced is delivered into the callback
+    /// Fn(Result<usize>).
+    fn start_providing_many<F>(&mut self, keys: Vec<record::Key>, f: F)
+    where
+        F: FnOnce(Result<usize>) + Send + 'static,
+    {
+        let local_id = *self.kbuckets.self_key().preimage();
+        for key in keys.iter() {
+            if let Err(e) = self.store.add_provider(ProviderRecord::new(key.clone(), local_id, None)) {
+                f(Err(e));
+                return;
+            }
+        }
+
+        let mut keys = keys;
+        reprovider::sort_keys(&mut keys, self.kbuckets.self_key());
+        let control = self.control();
+        let batch_size = self.reprovide_batch_size;
+        task::spawn(async move {
+            let n = reprovider::provide_keys(control, keys, batch_size, None, Default::default()).await;
+            f(Ok(n));
+        });
+    }
+
+    /// Announces the sorted keys to the closest peers of the first key. The
+    /// following keys are announced to the same peers, as long as they are
+    /// covered by the peers, see [`reprovider::batch_len`].
+    ///
+    /// The number of keys covered and announced is delivered into the callback
+    /// Fn(Result<(usize, usize)>).
+    fn provide_batch<F>(&mut self, keys: Vec<record::Key>, f: F)
+    where
+        F: FnOnce(Result<(usize, usize)>) + Send + 'static,
+    {
+        let first = match keys.first() {
+            Some(k) => k.clone(),
+            None => {
+                f(Ok((0, 0)));
+                return;
             }
+        };
+        let local_id = *self.kbuckets.self_key().preimage();
+        let config = self.query_config.clone();
+        let messengers = self.messengers.clone().expect("must be Some");
+        let addresses = self.local_addrs.clone();
+        let stats = self.query_stats.clone();
+        self.get_closest_peers(first, move |peers| {
+            let peers: Vec<PeerId> = match peers {
+                Ok(peers) => peers.into_iter().map(KadPeer::into).collect(),
+                Err(e) => {
+                    f(Err(e));
+                    return;
+                }
+            };
+            let n = reprovider::batch_len(&keys, &peers);
+            task::spawn(async move {
+                let mut succeeded = 0;
+                for key in keys.into_iter().take(n) {
+                    let provider = ProviderRecord::new(key, local_id, None);
+                    let qt = QueryType::AddProvider {
+                        provider,
+                        addresses: addresses.clone(),
+                    };
+                    let fixed_query = FixedQuery::new(qt, messengers.clone(), config.clone(), peers.clone(), stats.clone());
+                    let (tx, rx) = oneshot::channel();
+                    fixed_query.run(|r| {
+                        let _ = tx.send(r);
+                    });
+                    if let Ok(Ok(_)) = rx.await {
+                        succeeded += 1;
+                    }
+                }
+                f(Ok((n, succeeded)));
+            });
         });
     }
 
@@ -1016,6 +1515,30 @@ where
         self.store.remove_provider(key, self.kbuckets.self_key().preimage());
     }
 
+    /// Publishes the value as the IPNS record of the keypair, and keeps it for
+    /// republishing.
+    ///
+    /// The result of this operation is delivered into the callback
+    /// Fn(Result<()>).
+    fn publish_name<F>(&mut self, keypair: Keypair, value: Vec<u8>, f: F)
+    where
+        F: FnOnce(Result<()>) + Send + 'static,
+    {
+        let peer_id = keypair.public().into_peer_id();
+        self.published_names.insert(peer_id, (keypair.clone(), value.clone()));
+
+        let mut control = self.control();
+        let lifetime = self.ipns_record_lifetime;
+        task::spawn(async move {
+            f(publish_ipns_record(&mut control, &keypair, value, lifetime).await);
+        });
+    }
+
+    /// Stops republishing the IPNS record of the peer.
+    fn unpublish_name(&mut self, peer_id: &PeerId) {
+        self.published_names.remove(peer_id);
+    }
+
     /// Finds the closest peers to a `target` in the context of a request by
     /// the `source` peer, such that the `source` peer is never included in the
     /// result.
@@ -1055,6 +1578,16 @@ where
     }
 
     /// Collects all peers who are known to be providers of the value for a given `Multihash`.
+    // Strips the non-global addresses of the peers sent in a response, if required.
+    fn scoped_peers(&self, mut peers: Vec<KadPeer>) -> Vec<KadPeer> {
+        if self.global_addrs_only {
+            for peer in peers.iter_mut() {
+                peer.multiaddrs.retain(|addr| addr.is_global_addr());
+            }
+        }
+        peers
+    }
+
     fn provider_peers(&mut self, key: &record::Key, source: Option<&PeerId>) -> Vec<KadPeer> {
         let kbuckets = &mut self.kbuckets;
         let connected = &self.connected_peers;
@@ -1126,7 +1659,7 @@ where
         }
     */
     /// Processes a record received from a peer.
-    fn handle_put_record(&mut self, _source: PeerId, mut record: Record) -> Result<KadResponseMsg> {
+    fn handle_put_record(&mut self, source: PeerId, mut record: Record) -> Result<KadResponseMsg> {
         if record.publisher.as_ref() == Some(self.kbuckets.self_key().preimage()) {
             // If the (alleged) publisher is the local node, do nothing. The record of
             // the original publisher should never change as a result of replication
@@ -1156,10 +1689,18 @@ where
         // not exist locally should always (attempted to) be stored, there is a
         // choice here w.r.t. the handling of replicated records whose keys refer
         // to records that exist locally: The value and / or the publisher may
-        // either be overridden or left unchanged. At the moment and in the
-        // absence of a decisive argument for another option, both are always
-        // overridden as it avoids having to load the existing record in the
-        // first place.
+        // either be overridden or left unchanged. The validator decides which
+        // one is better, the new record is rejected if the existing one wins.
+        // Note the new record has been validated by KadProtocolHandler.
+        if let Some(existing) = self.store.get(&record.key) {
+            if !existing.is_expired(now) && existing.value != record.value {
+                let values = [record.value.as_slice(), existing.value.as_slice()];
+                if self.validator.select(&record.key, &values)? != 0 {
+                    log::debug!("Record not stored, the existing one is better: {:?}", record.key);
+                    return Err(KadError::InvalidRecord("existing record is better"));
+                }
+            }
+        }
 
         log::debug!("adding record to store: {:?}", record);
 
@@ -1168,7 +1709,13 @@ where
             // requirement to send back the value in the response, although this
             // is a waste of resources.
             match self.store.put(record.clone()) {
-                Ok(()) => log::debug!("Record stored: {:?}; {} bytes", record.key, record.value.len()),
+                Ok(()) => {
+                    log::debug!("Record stored: {:?}; {} bytes", record.key, record.value.len());
+                    self.events.emit(KadEvent::RecordStored {
+                        key: record.key.clone(),
+                        source,
+                    });
+                }
                 Err(e) => {
                     log::debug!("Record not stored: {:?}", e);
                     return Err(e);
@@ -1190,8 +1737,20 @@ where
     }
 
     /// Processes a provider record received from a peer.
-    fn handle_add_provider(&mut self, key: record::Key, provider: KadPeer) {
+    ///
+    /// The record is rejected if the peer has reached its cap of provider records.
+    fn handle_add_provider(&mut self, key: record::Key, provider: KadPeer) -> Result<()> {
         if &provider.node_id != self.kbuckets.self_key().preimage() {
+            let mut is_new = false;
+            if let Some(max) = self.max_providers_per_peer {
+                is_new = self.store.providers(&key).iter().all(|r| r.provider != provider.node_id);
+                if is_new && self.provider_counts.get(&provider.node_id).copied().unwrap_or_default() >= max {
+                    log::debug!("Provider record rejected, too many records from {}", provider.node_id);
+                    self.rate_limiter.lock().unwrap().stats.provider_rejected += 1;
+                    return Err(KadError::RateLimited);
+                }
+            }
+
             log::debug!("adding provider to store: {:?}", provider);
             // add provider's addresses to peerstore
             self.swarm
@@ -1200,10 +1759,20 @@ where
                 .add_addrs(&provider.node_id, provider.multiaddrs, PROVIDER_ADDR_TTL);
 
             let record = ProviderRecord::new(key, provider.node_id, self.provider_record_ttl.map(|ttl| Instant::now() + ttl));
-            if let Err(e) = self.store.add_provider(record) {
-                log::debug!("Provider record not stored: {:?}", e);
+            match self.store.add_provider(record.clone()) {
+                Ok(()) => {
+                    if is_new {
+                        *self.provider_counts.entry(record.provider).or_default() += 1;
+                    }
+                    self.events.emit(KadEvent::ProviderStored {
+                        key: record.key,
+                        provider: record.provider,
+                    })
+                }
+                Err(e) => log::debug!("Provider record not stored: {:?}", e),
             }
         }
+        Ok(())
     }
 
     /// Get the controller of Kademlia, which can be used to manipulate the Kad-DHT.
@@ -1242,6 +1811,263 @@ where
         }
     }
 
+    fn start_snapshot_timer(&mut self) {
+        if self.snapshot_path.is_some() {
+            // start timer runtime, which would generate ProtocolEvent::SnapshotTimer to kad main loop
+            log::info!("starting snapshot timer runtime...");
+            let interval = self.snapshot_interval;
+            let mut poster = self.poster();
+            let h = task::spawn(async move {
+                loop {
+                    task::sleep(interval).await;
+                    let _ = poster.post(ProtocolEvent::SnapshotTimer).await;
+                }
+            });
+
+            self.snapshot_timer_handle = Some(h);
+        }
+    }
+
+    // Saves the peers in the routing table to the snapshot file, if enabled.
+    //
+    // The file is written by a blocking runtime, after the previous saving job completes,
+    // so that the main loop is never blocked and the jobs never write the file concurrently.
+    fn save_snapshot(&mut self) {
+        let path = match self.snapshot_path.clone() {
+            Some(path) => path,
+            None => return,
+        };
+        let swarm = self.swarm.as_ref().expect("must be Some");
+        let now = Instant::now();
+        let sys_now = SystemTime::now();
+
+        let peers = self
+            .kbuckets
+            .iter()
+            .flat_map(|k| {
+                k.iter()
+                    .map(|n| {
+                        let id = *n.node.key.preimage();
+                        SnapshotPeer {
+                            id,
+                            addrs: swarm.get_addrs(&id).unwrap_or_default(),
+                            last_seen: n
+                                .node
+                                .value
+                                .get_aliveness()
+                                .and_then(|a| sys_now.checked_sub(now.duration_since(a))),
+                        }
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        let previous = self.snapshot_save_handle.take();
+        let h = task::spawn(async move {
+            if let Some(h) = previous {
+                h.await;
+            }
+            let count = peers.len();
+            let r = task::spawn_blocking(move || save_snapshot(&path, peers)).await;
+            match r {
+                Some(Ok(())) => log::debug!("routing table snapshot saved, {} peers", count),
+                Some(Err(e)) => log::info!("failed to save routing table snapshot: {}", e),
+                None => log::info!("routing table snapshot saving job cancelled"),
+            }
+        });
+        self.snapshot_save_handle = Some(h);
+    }
+
+    // Loads the peers from the snapshot file by a blocking runtime, if enabled. The peers
+    // are posted to the main loop as `SnapshotLoaded`.
+    fn load_snapshot(&mut self) {
+        let path = match self.snapshot_path.clone() {
+            Some(path) => path,
+            None => return,
+        };
+        let mut poster = self.poster();
+        task::spawn(async move {
+            match task::spawn_blocking(move || load_snapshot(&path)).await {
+                Some(Ok(peers)) => {
+                    let _ = poster.post(ProtocolEvent::SnapshotLoaded(peers)).await;
+                }
+                Some(Err(e)) => log::info!("failed to load routing table snapshot: {}", e),
+                None => log::info!("routing table snapshot loading job cancelled"),
+            }
+        });
+    }
+
+    // Reloads the peers loaded from the snapshot file into the routing table.
+    //
+    // The reloaded peers are not deemed alive until they respond to a Kad ping, which
+    // is sent in the background. Those failed to respond are reported by `KadPeerStopped`,
+    // which removes them from the routing table, as the reloaded peers are never permanent.
+    fn handle_snapshot_loaded(&mut self, mut peers: Vec<SnapshotPeer>) {
+        log::info!("reloading {} peers from routing table snapshot", peers.len());
+
+        // the most recently seen peers go last, so that they are not replaced by the older ones
+        peers.sort_by_key(|p| p.last_seen);
+
+        let local_id = *self.kbuckets.self_key().preimage();
+        let mut reloaded = vec![];
+        for peer in peers {
+            if peer.id == local_id || peer.addrs.is_empty() {
+                continue;
+            }
+            if let Some(s) = self.swarm.as_ref() {
+                s.add_addrs(&peer.id, peer.addrs, ADDRESS_TTL);
+            }
+            self.try_add_peer(peer.id, false, false);
+            reloaded.push(peer.id);
+        }
+
+        let messengers = self.messengers.clone().expect("must be Some");
+        let poster = self.poster();
+        let stats = self.query_stats.clone();
+        let mut limiter = TaskLimiter::new(self.query_config.alpha_value);
+        task::spawn(async move {
+            for peer in reloaded {
+                let mut messengers = messengers.clone();
+                let mut poster = poster.clone();
+                let stats = stats.clone();
+                limiter
+                    .run(async move {
+                        stats.message_tx.ping.fetch_add(1, Ordering::SeqCst);
+                        let r = match messengers.get_messenger(&peer).await {
+                            Ok(mut ms) => ms.send_ping().await.map(|_| messengers.put_messenger(ms)),
+                            Err(e) => Err(e),
+                        };
+                        let event = match r {
+                            Ok(()) => ProtocolEvent::KadPeerFound(peer, true),
+                            Err(e) => {
+                                log::debug!("reloaded peer {} failed to respond: {:?}", peer, e);
+                                ProtocolEvent::KadPeerStopped(peer)
+                            }
+                        };
+                        let _ = poster.post(event).await;
+                    })
+                    .await;
+            }
+            let count = limiter.wait().await;
+            log::info!("{} peers reloaded from routing table snapshot have been checked", count);
+        });
+    }
+
+    fn start_republish_timer(&mut self) {
+        if let Some(interval) = self.ipns_republish_interval {
+            // start timer runtime, which would generate ProtocolEvent::RepublishTimer to kad main loop
+            log::info!("starting republish timer runtime...");
+            let mut poster = self.poster();
+            let h = task::spawn(async move {
+                loop {
+                    task::sleep(interval).await;
+                    let _ = poster.post(ProtocolEvent::RepublishTimer).await;
+                }
+            });
+
+            self.republish_timer_handle = Some(h);
+        }
+    }
+
+    fn start_reprovide_timer(&mut self) {
+        if let Some(interval) = self.provider_publication_interval {
+            // start timer runtime, which would generate ProtocolEvent::ReprovideTimer to kad main loop
+            log::info!("starting reprovide timer runtime...");
+            let mut poster = self.poster();
+            let h = task::spawn(async move {
+                loop {
+                    task::sleep(interval).await;
+                    let _ = poster.post(ProtocolEvent::ReprovideTimer).await;
+                }
+            });
+
+            self.reprovide_timer_handle = Some(h);
+        }
+    }
+
+    // Reprovides the keys chosen by the reprovider strategy. The batches are
+    // spread across the reprovide interval.
+    fn handle_reprovide_timer(&mut self) {
+        let interval = self.provider_publication_interval.expect("must be Some");
+        {
+            let mut stats = self.reprovider_stats.lock().unwrap();
+            if stats.running {
+                log::info!("the last reprovide round is still running, skipped");
+                return;
+            }
+            stats.running = true;
+        }
+
+        let provided = self.store.provided().map(|r| r.into_owned().key);
+        let mut keys = self.reprovider_strategy.keys(provided);
+        reprovider::sort_keys(&mut keys, self.kbuckets.self_key());
+        log::debug!("reproviding {} keys", keys.len());
+
+        let control = self.control();
+        let batch_size = self.reprovide_batch_size;
+        let stats = self.reprovider_stats.clone();
+        task::spawn(async move {
+            reprovider::provide_keys(control, keys, batch_size, Some(interval), stats).await;
+        });
+    }
+
+    fn start_discovery_timer(&mut self) {
+        if let Some(interval) = self.discovery.as_ref().map(|c| c.interval()) {
+            // start timer runtime, which would generate ProtocolEvent::DiscoveryTimer to kad main loop
+            log::info!("starting discovery timer runtime...");
+            let mut poster = self.poster();
+            let h = task::spawn(async move {
+                loop {
+                    task::sleep(interval).await;
+                    let _ = poster.post(ProtocolEvent::DiscoveryTimer).await;
+                }
+            });
+
+            self.discovery_timer_handle = Some(h);
+        }
+    }
+
+    // Runs a round of random walks if there are not enough connections.
+    fn handle_discovery_timer(&mut self) {
+        let config = self.discovery.clone().expect("must be Some");
+        let connected = self.connected_peers.clone();
+        {
+            let mut stats = self.discovery_stats.lock().unwrap();
+            if stats.running {
+                log::debug!("the last discovery round is still running, skipped");
+                return;
+            }
+            if connected.len() >= config.target_connections() {
+                stats.skipped += 1;
+                return;
+            }
+            stats.running = true;
+        }
+
+        let wanted = config.target_connections() - connected.len();
+        log::debug!("{} connections, discovering {} more peers", connected.len(), wanted);
+
+        let control = self.control();
+        let swarm = self.swarm.clone().expect("must be Some");
+        let stats = self.discovery_stats.clone();
+        task::spawn(async move {
+            discovery::random_walk(control, swarm, config, connected, wanted, stats).await;
+        });
+    }
+
+    // Republishes all the names published, with a renewed validity.
+    fn handle_republish_timer(&mut self) {
+        log::debug!("republishing {} names", self.published_names.len());
+        let names = self.published_names.values().cloned().collect::<Vec<_>>();
+        for (keypair, value) in names {
+            self.publish_name(keypair, value, |r| {
+                if let Err(e) = r {
+                    log::info!("failed to republish name: {:?}", e);
+                }
+            });
+        }
+    }
+
     /// Message Process Loop.
     async fn process_loop(&mut self) -> Result<()> {
         loop {
@@ -1276,16 +2102,37 @@ where
         }
     }
 
+    // Checks if the peer supports Kad protocol, as reported by Identify. Peers in
+    // client mode don't advertise Kad protocol, so that they are not qualified.
+    fn is_kad_peer(&self, peer_id: &PeerId) -> bool {
+        self.swarm.as_ref().map_or(false, |swarm| {
+            swarm
+                .first_supported_protocol(peer_id, vec![self.protocol_config.protocol_name().to_string()])
+                .is_some()
+        })
+    }
+
     // Called when a peer is identified.
     fn handle_peer_identified(&mut self, peer_id: PeerId) {
         // check if the peer is a eligible Kad peer, try add to Kad if it is
-        if let Some(swarm) = &mut self.swarm {
-            if swarm
-                .first_supported_protocol(&peer_id, vec![self.protocol_config.protocol_name().to_string()])
-                .is_some()
-            {
-                log::debug!("A peer identified as a qualified Kad peer: {:}", peer_id);
-                self.try_add_peer(peer_id, false, false);
+        if self.is_kad_peer(&peer_id) {
+            log::debug!("A peer identified as a qualified Kad peer: {:}", peer_id);
+            self.try_add_peer(peer_id, false, false);
+        }
+    }
+
+    // Switches between client and server mode per the local addresses, if in auto mode.
+    // Peers are told about the changed protocol list by an identify push.
+    fn update_mode(&mut self) {
+        if self.mode == KademliaMode::Auto {
+            let reachable = self.local_addrs.iter().any(|addr| addr.is_global_addr());
+            if self.allow_listening.swap(reachable, Ordering::SeqCst) != reachable {
+                log::info!("Kad switched to {} mode", if reachable { "server" } else { "client" });
+                if let Some(mut swarm) = self.swarm.clone() {
+                    task::spawn(async move {
+                        let _ = swarm.identify_push().await;
+                    });
+                }
             }
         }
     }
@@ -1294,6 +2141,7 @@ where
     fn handle_address_changed(&mut self, addrs: Vec<Multiaddr>) {
         log::debug!("address changed: {:?}, starting refresh...", addrs);
         self.local_addrs = addrs;
+        self.update_mode();
         // TODO: probably we should start a timer to trigger refreshing, to avoid refreshing too often
         self.handle_refresh_stage(RefreshStage::Start(None));
     }
@@ -1340,6 +2188,21 @@ where
             Some(ProtocolEvent::RefreshTimer) => {
                 self.handle_refresh_timer();
             }
+            Some(ProtocolEvent::SnapshotTimer) => {
+                self.save_snapshot();
+            }
+            Some(ProtocolEvent::SnapshotLoaded(peers)) => {
+                self.handle_snapshot_loaded(peers);
+            }
+            Some(ProtocolEvent::RepublishTimer) => {
+                self.handle_republish_timer();
+            }
+            Some(ProtocolEvent::ReprovideTimer) => {
+                self.handle_reprovide_timer();
+            }
+            Some(ProtocolEvent::DiscoveryTimer) => {
+                self.handle_discovery_timer();
+            }
             Some(ProtocolEvent::Refresh(stage)) => {
                 self.handle_refresh_stage(stage);
             }
@@ -1353,9 +2216,15 @@ where
     // Handles Kad request messages. ProtoBuf message decoded by handler.
     fn handle_kad_request(&mut self, request: KadRequestMsg, source: PeerId, reply: oneshot::Sender<Result<Option<KadResponseMsg>>>) {
         log::debug!("handle Kad request message from {:?}, {:?} ", source, request);
+        self.events.emit(KadEvent::InboundRequest {
+            peer: source,
+            request: RequestKind::from(&request),
+        });
 
-        // Obviously we found a Kad peer
-        self.try_add_peer(source, true, false);
+        // The source might be a Kad client, which should not be added to the routing table
+        if self.is_kad_peer(&source) {
+            self.try_add_peer(source, true, false);
+        }
 
         let response = match request {
             KadRequestMsg::Ping => {
@@ -1366,6 +2235,7 @@ where
             KadRequestMsg::FindNode { key } => {
                 self.stats.message_rx.find_node += 1;
                 let closer_peers = self.find_closest(&kbucket::Key::new(key), &source);
+                let closer_peers = self.scoped_peers(closer_peers);
                 Ok(Some(KadResponseMsg::FindNode { closer_peers }))
             }
             KadRequestMsg::AddProvider { key, provider } => {
@@ -1375,15 +2245,16 @@ where
                     log::info!("received provider from wrong peer {:?}", source);
                     Err(KadError::InvalidSource(source))
                 } else {
-                    self.handle_add_provider(key, provider);
                     // AddProvider doesn't require a response
-                    Ok(None)
+                    self.handle_add_provider(key, provider).map(|_| None)
                 }
             }
             KadRequestMsg::GetProviders { key } => {
                 self.stats.message_rx.get_provider += 1;
                 let provider_peers = self.provider_peers(&key, Some(&source));
+                let provider_peers = self.scoped_peers(provider_peers);
                 let closer_peers = self.find_closest(&kbucket::Key::new(key), &source);
+                let closer_peers = self.scoped_peers(closer_peers);
                 Ok(Some(KadResponseMsg::GetProviders {
                     closer_peers,
                     provider_peers,
@@ -1396,6 +2267,7 @@ where
                     Some(record) => {
                         if record.is_expired(Instant::now()) {
                             self.store.remove(&key);
+                            self.events.emit(KadEvent::RecordExpired { key: key.clone() });
                             None
                         } else {
                             Some(record.into_owned())
@@ -1405,6 +2277,7 @@ where
                 };
 
                 let closer_peers = self.find_closest(&kbucket::Key::new(key), &source);
+                let closer_peers = self.scoped_peers(closer_peers);
                 Ok(Some(KadResponseMsg::GetValue { record, closer_peers }))
             }
             KadRequestMsg::PutValue { record } => {
@@ -1433,7 +2306,22 @@ where
 
         provider_records.into_iter().for_each(|r| {
             self.store.remove_provider(&r.key, &r.provider);
+            self.events.emit(KadEvent::ProviderExpired {
+                key: r.key,
+                provider: r.provider,
+            });
         });
+
+        // recount the provider records of each peer, as the store might have
+        // dropped some records by itself
+        if self.max_providers_per_peer.is_some() {
+            let local_id = *self.kbuckets.self_key().preimage();
+            let mut counts = FnvHashMap::default();
+            for r in self.store.all_providers().filter(|r| r.provider != local_id) {
+                *counts.entry(r.provider).or_default() += 1;
+            }
+            self.provider_counts = counts;
+        }
     }
 
     // handle the timer to refresh the routing table. Actually it will trigger the
@@ -1615,28 +2503,55 @@ where
                 });
             }
             Some(ControlCommand::FindProviders(key, count, reply)) => {
-                self.get_providers(key, count, |r| {
+                self.get_providers(key, count, None, |r| {
                     let _ = reply.send(r);
                 });
             }
+            Some(ControlCommand::FindProvidersStream(key, count, tx, cancel)) => {
+                self.get_providers(key, count, Some((QuerySink::Providers(tx), cancel)), |_| {});
+            }
             Some(ControlCommand::Providing(key, reply)) => {
                 self.start_providing(key, |r| {
                     let _ = reply.send(r);
                 });
             }
+            Some(ControlCommand::ProvidingMany(keys, reply)) => {
+                self.start_providing_many(keys, |r| {
+                    let _ = reply.send(r);
+                });
+            }
+            Some(ControlCommand::ProvideBatch(keys, reply)) => {
+                self.provide_batch(keys, |r| {
+                    let _ = reply.send(r);
+                });
+            }
+            Some(ControlCommand::Subscribe(reply)) => {
+                let _ = reply.send(self.events.subscribe());
+            }
             Some(ControlCommand::Unprovide(key)) => {
                 self.stop_providing(&key);
             }
-            Some(ControlCommand::PutValue(key, value, reply)) => {
-                self.put_record(key, value, |r| {
+            Some(ControlCommand::PutValue(key, value, quorum, reply)) => {
+                self.put_record(key, value, quorum, |r| {
                     let _ = reply.send(r);
                 });
             }
-            Some(ControlCommand::GetValue(key, reply)) => {
-                self.get_record(key, |r| {
+            Some(ControlCommand::GetValue(key, quorum, reply)) => {
+                self.get_record(key, quorum, None, |r| {
                     let _ = reply.send(r);
                 });
             }
+            Some(ControlCommand::GetValueStream(key, quorum, tx, cancel)) => {
+                self.get_record(key, quorum, Some((QuerySink::Records(tx), cancel)), |_| {});
+            }
+            Some(ControlCommand::PublishName(keypair, value, reply)) => {
+                self.publish_name(keypair, value, |r| {
+                    let _ = reply.send(r);
+                });
+            }
+            Some(ControlCommand::UnpublishName(peer_id)) => {
+                self.unpublish_name(&peer_id);
+            }
             Some(ControlCommand::Dump(cmd)) => match cmd {
                 DumpCommand::Storage(reply) => {
                     let _ = reply.send(self.dump_storage());
@@ -1664,7 +2579,13 @@ where
     for<'a> TStore: RecordStore<'a> + Send + 'static,
 {
     fn handler(&self) -> IProtocolHandler {
-        Box::new(KadProtocolHandler::new(self.protocol_config.clone(), self.poster()))
+        Box::new(KadProtocolHandler::new(
+            self.protocol_config.clone(),
+            self.validator.clone(),
+            self.allow_listening.clone(),
+            self.rate_limiter.clone(),
+            self.poster(),
+        ))
     }
 
     fn start(mut self, swarm: SwarmControl) -> Option<task::TaskHandle<()>>
@@ -1678,6 +2599,15 @@ where
         self.start_provider_gc_timer();
         // start refresh timer
         self.start_refresh_timer();
+        // reload the routing table and start snapshot timer
+        self.load_snapshot();
+        self.start_snapshot_timer();
+        // start republish timer
+        self.start_republish_timer();
+        // start reprovide timer
+        self.start_reprovide_timer();
+        // start discovery timer
+        self.start_discovery_timer();
 
         // well, self 'move' explicitly,
         let mut kad = self;
@@ -1695,12 +2625,50 @@ where
             if let Some(h) = kad.provider_timer_handle.take() {
                 h.cancel().await;
             }
+            if let Some(h) = kad.snapshot_timer_handle.take() {
+                h.cancel().await;
+            }
+            if let Some(h) = kad.republish_timer_handle.take() {
+                h.cancel().await;
+            }
+            if let Some(h) = kad.reprovide_timer_handle.take() {
+                h.cancel().await;
+            }
+            if let Some(h) = kad.discovery_timer_handle.take() {
+                h.cancel().await;
+            }
+            kad.save_snapshot();
+            if let Some(h) = kad.snapshot_save_handle.take() {
+                h.await;
+            }
 
             log::info!("Kad main loop exited");
         }))
     }
 }
 
+/// Publishes the value as the IPNS record of the keypair, which is valid for `lifetime`.
+///
+/// The current record is looked up first, the sequence number is increased only if
+/// the value changes.
+async fn publish_ipns_record(control: &mut Control, keypair: &Keypair, value: Vec<u8>, lifetime: Duration) -> Result<()> {
+    let key = ipns_key(&keypair.public().into_peer_id()).to_vec();
+    let current = control
+        .get_value_with_quorum(key.clone(), Quorum::Majority)
+        .await
+        .ok()
+        .and_then(|records| records.into_iter().next())
+        .and_then(|r| IpnsRecord::decode(&r.record.value).ok());
+    let sequence = match current {
+        Some(r) if r.value == value => r.sequence,
+        Some(r) => r.sequence + 1,
+        None => 0,
+    };
+
+    let record = IpnsRecord::new(keypair, value, sequence, SystemTime::now() + lifetime)?;
+    control.put_value_with_quorum(key, record.encode(), Quorum::One).await.map(|_| ())
+}
+
 /// Exponentially decrease the given duration (base 2).
 fn exp_decrease(ttl: Duration, exp: u32) -> Duration {
     Duration::from_secs(ttl.as_secs().checked_shr(exp).unwrap_or(0))
@@ -69,10 +69,12 @@
 
 mod bucket;
 mod entry;
+mod filter;
 mod key;
 use primitive_types::U256;
 
 pub use entry::*;
+pub use filter::{AddressScope, AddressScopeFilter, ChainedFilter, IRoutingTableFilter, IpGroupFilter, RoutingTableFilter};
 
 use arrayvec::{self, ArrayVec};
 use bucket::KBucket;
@@ -138,6 +140,13 @@ impl BucketIndex {
     }
 }
 
+/// Generates a random key falling into the bucket of the index, as seen from
+/// the local key. The index must be less than `NUM_BUCKETS`.
+pub(crate) fn rand_key_in_bucket(local: &KeyBytes, index: usize, rng: &mut impl rand::Rng) -> KeyBytes {
+    assert!(index < NUM_BUCKETS);
+    local.for_distance(BucketIndex(index).rand_distance(rng))
+}
+
 impl<TKey, TVal> KBucketsTable<TKey, TVal>
 where
     TKey: Clone + AsRef<KeyBytes>,
@@ -0,0 +1,408 @@
+// Copyright 2020 Netwarps Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Routing table filters.
+//!
+//! A [`RoutingTableFilter`] decides whether a peer is allowed to join a bucket
+//! of the routing table. The [`IpGroupFilter`] limits the number of peers which
+//! share the same IP group, i.e. /16 for IPv4 and /32 for IPv6, per bucket and
+//! across the whole table, so that an attacker who controls a lot of peer Ids
+//! in a few subnets can't take over the routing table. The [`AddressScopeFilter`]
+//! admits peers per the scope of their addresses, which separates a LAN DHT from
+//! a WAN DHT. Filters can be combined with a [`ChainedFilter`].
+
+use fnv::FnvHashMap;
+use std::fmt;
+
+use libp2prs_core::multiaddr::protocol::Protocol;
+use libp2prs_core::{Multiaddr, PeerId};
+
+/// Trait for the filters of the routing table.
+pub trait RoutingTableFilter: fmt::Debug + Send {
+    /// Checks if the peer with the addresses is allowed to join the bucket.
+    fn allow(&self, peer: &PeerId, addrs: &[Multiaddr], bucket: usize) -> bool;
+    /// Notifies that the peer has been added to the bucket.
+    fn on_added(&mut self, peer: &PeerId, addrs: &[Multiaddr], bucket: usize);
+    /// Notifies that the peer has been removed from the routing table.
+    fn on_removed(&mut self, peer: &PeerId);
+    /// Notifies that the addresses of the peer in the bucket have been updated.
+    ///
+    /// Returns whether the peer is still allowed, in which case it is accounted
+    /// with the new addresses. Otherwise it is supposed to be removed.
+    fn on_updated(&mut self, peer: &PeerId, addrs: &[Multiaddr], bucket: usize) -> bool {
+        self.on_removed(peer);
+        if self.allow(peer, addrs, bucket) {
+            self.on_added(peer, addrs, bucket);
+            true
+        } else {
+            false
+        }
+    }
+    /// This is to provide a clone method for the trait object.
+    fn box_clone(&self) -> IRoutingTableFilter;
+}
+
+pub type IRoutingTableFilter = Box<dyn RoutingTableFilter>;
+
+impl Clone for IRoutingTableFilter {
+    fn clone(&self) -> Self {
+        self.box_clone()
+    }
+}
+
+/// The IP group of an address, /16 for IPv4 and /32 for IPv6.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+enum IpGroup {
+    V4([u8; 2]),
+    V6([u8; 4]),
+}
+
+impl IpGroup {
+    /// Returns the IP group of the address, or `None` if the address is not a
+    /// global IP address. Non-global addresses are always allowed.
+    fn from_multiaddr(addr: &Multiaddr) -> Option<Self> {
+        if !addr.is_global_addr() {
+            return None;
+        }
+        match addr.iter().next()? {
+            Protocol::Ip4(ip) => {
+                let o = ip.octets();
+                Some(IpGroup::V4([o[0], o[1]]))
+            }
+            Protocol::Ip6(ip) => {
+                let o = ip.octets();
+                Some(IpGroup::V6([o[0], o[1], o[2], o[3]]))
+            }
+            _ => None,
+        }
+    }
+
+    fn from_multiaddrs(addrs: &[Multiaddr]) -> Vec<Self> {
+        let mut groups = addrs.iter().filter_map(IpGroup::from_multiaddr).collect::<Vec<_>>();
+        groups.sort();
+        groups.dedup();
+        groups
+    }
+}
+
+/// A filter limiting the number of peers in the same IP group, per bucket and
+/// across the table.
+///
+/// Peers without any global IP address are not limited, e.g. the peers whose
+/// addresses are not known yet. They are checked again once their addresses
+/// are updated, see [`RoutingTableFilter::on_updated`].
+#[derive(Debug, Clone)]
+pub struct IpGroupFilter {
+    /// The maximum number of peers of an IP group in a bucket.
+    max_per_bucket: usize,
+    /// The maximum number of peers of an IP group in the table.
+    max_per_table: usize,
+    /// The number of peers for each IP group, in the table.
+    table: FnvHashMap<IpGroup, usize>,
+    /// The number of peers for each IP group, per bucket.
+    buckets: FnvHashMap<usize, FnvHashMap<IpGroup, usize>>,
+    /// The bucket and IP groups of the peers, which are accounted.
+    peers: FnvHashMap<PeerId, (usize, Vec<IpGroup>)>,
+}
+
+impl IpGroupFilter {
+    /// Creates a filter with the given limits.
+    pub fn new(max_per_bucket: usize, max_per_table: usize) -> Self {
+        Self {
+            max_per_bucket,
+            max_per_table,
+            table: Default::default(),
+            buckets: Default::default(),
+            peers: Default::default(),
+        }
+    }
+}
+
+impl Default for IpGroupFilter {
+    /// Two peers of an IP group per bucket, and three for the table, the same as go-libp2p-kad-dht.
+    fn default() -> Self {
+        IpGroupFilter::new(2, 3)
+    }
+}
+
+impl RoutingTableFilter for IpGroupFilter {
+    fn allow(&self, _peer: &PeerId, addrs: &[Multiaddr], bucket: usize) -> bool {
+        let in_bucket = self.buckets.get(&bucket);
+        IpGroup::from_multiaddrs(addrs).iter().all(|g| {
+            self.table.get(g).map_or(true, |n| *n < self.max_per_table)
+                && in_bucket.and_then(|b| b.get(g)).map_or(true, |n| *n < self.max_per_bucket)
+        })
+    }
+
+    fn on_added(&mut self, peer: &PeerId, addrs: &[Multiaddr], bucket: usize) {
+        // in case it has been accounted
+        self.on_removed(peer);
+
+        let groups = IpGroup::from_multiaddrs(addrs);
+        if groups.is_empty() {
+            return;
+        }
+        let in_bucket = self.buckets.entry(bucket).or_default();
+        for g in groups.iter() {
+            *self.table.entry(*g).or_default() += 1;
+            *in_bucket.entry(*g).or_default() += 1;
+        }
+        self.peers.insert(*peer, (bucket, groups));
+    }
+
+    fn on_removed(&mut self, peer: &PeerId) {
+        if let Some((bucket, groups)) = self.peers.remove(peer) {
+            for g in groups.iter() {
+                decrease(&mut self.table, g);
+                if let Some(in_bucket) = self.buckets.get_mut(&bucket) {
+                    decrease(in_bucket, g);
+                }
+            }
+        }
+    }
+
+    fn box_clone(&self) -> IRoutingTableFilter {
+        Box::new(self.clone())
+    }
+}
+
+/// The scope of addresses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressScope {
+    /// Private IP addresses, i.e. the IP addresses which are not global, e.g.
+    /// RFC1918, loopback, link-local and unique local addresses.
+    Private,
+    /// Global IP addresses, see [`Multiaddr::is_global_addr`].
+    Public,
+}
+
+/// A filter admitting the peers which have at least one address in the scope.
+///
+/// Addresses without an IP component, e.g. `/dns` or `/memory`, are in neither scope.
+#[derive(Debug, Clone)]
+pub struct AddressScopeFilter {
+    scope: AddressScope,
+}
+
+impl AddressScopeFilter {
+    /// Creates a filter with the given scope.
+    pub fn new(scope: AddressScope) -> Self {
+        Self { scope }
+    }
+}
+
+impl RoutingTableFilter for AddressScopeFilter {
+    fn allow(&self, _peer: &PeerId, addrs: &[Multiaddr], _bucket: usize) -> bool {
+        match self.scope {
+            AddressScope::Private => addrs.iter().any(|addr| is_ip_addr(addr) && !addr.is_global_addr()),
+            AddressScope::Public => addrs.iter().any(|addr| addr.is_global_addr()),
+        }
+    }
+
+    fn on_added(&mut self, _peer: &PeerId, _addrs: &[Multiaddr], _bucket: usize) {}
+
+    fn on_removed(&mut self, _peer: &PeerId) {}
+
+    fn box_clone(&self) -> IRoutingTableFilter {
+        Box::new(self.clone())
+    }
+}
+
+fn is_ip_addr(addr: &Multiaddr) -> bool {
+    matches!(addr.iter().next(), Some(Protocol::Ip4(_)) | Some(Protocol::Ip6(_)))
+}
+
+/// A filter made of two filters, a peer is admitted only if both of them allow it.
+#[derive(Debug, Clone)]
+pub struct ChainedFilter {
+    first: IRoutingTableFilter,
+    second: IRoutingTableFilter,
+}
+
+impl ChainedFilter {
+    /// Creates a filter chaining the given filters.
+    pub fn new(first: IRoutingTableFilter, second: IRoutingTableFilter) -> Self {
+        Self { first, second }
+    }
+}
+
+impl RoutingTableFilter for ChainedFilter {
+    fn allow(&self, peer: &PeerId, addrs: &[Multiaddr], bucket: usize) -> bool {
+        self.first.allow(peer, addrs, bucket) && self.second.allow(peer, addrs, bucket)
+    }
+
+    fn on_added(&mut self, peer: &PeerId, addrs: &[Multiaddr], bucket: usize) {
+        self.first.on_added(peer, addrs, bucket);
+        self.second.on_added(peer, addrs, bucket);
+    }
+
+    fn on_removed(&mut self, peer: &PeerId) {
+        self.first.on_removed(peer);
+        self.second.on_removed(peer);
+    }
+
+    fn box_clone(&self) -> IRoutingTableFilter {
+        Box::new(self.clone())
+    }
+}
+
+fn decrease(counters: &mut FnvHashMap<IpGroup, usize>, g: &IpGroup) {
+    if let Some(n) = counters.get_mut(g) {
+        *n -= 1;
+        if *n == 0 {
+            counters.remove(g);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(s: &str) -> Vec<Multiaddr> {
+        vec![s.parse().unwrap()]
+    }
+
+    #[test]
+    fn ip_group_per_bucket() {
+        let mut filter = IpGroupFilter::new(1, 10);
+
+        let (a, b) = (PeerId::random(), PeerId::random());
+        assert!(filter.allow(&a, &addr("/ip4/1.2.3.4/tcp/4001"), 1));
+        filter.on_added(&a, &addr("/ip4/1.2.3.4/tcp/4001"), 1);
+
+        // same /16 in the same bucket
+        assert!(!filter.allow(&b, &addr("/ip4/1.2.100.100/tcp/4001"), 1));
+        // same /16 in another bucket
+        assert!(filter.allow(&b, &addr("/ip4/1.2.100.100/tcp/4001"), 2));
+        // another /16
+        assert!(filter.allow(&b, &addr("/ip4/1.3.3.4/tcp/4001"), 1));
+
+        filter.on_removed(&a);
+        assert!(filter.allow(&b, &addr("/ip4/1.2.100.100/tcp/4001"), 1));
+    }
+
+    #[test]
+    fn ip_group_per_table() {
+        let mut filter = IpGroupFilter::new(10, 2);
+
+        for i in 0..2 {
+            let addrs = addr(&format!("/ip6/2600:1::{}/tcp/4001", i + 1));
+            filter.on_added(&PeerId::random(), &addrs, i);
+        }
+        let peer = PeerId::random();
+        assert!(!filter.allow(&peer, &addr("/ip6/2600:1:1::1/tcp/4001"), 5));
+        assert!(filter.allow(&peer, &addr("/ip6/2600:2::1/tcp/4001"), 5));
+    }
+
+    #[test]
+    fn private_addresses_allowed() {
+        let mut filter = IpGroupFilter::new(1, 1);
+
+        for _ in 0..3 {
+            let peer = PeerId::random();
+            let addrs = addr("/ip4/192.168.1.1/tcp/4001");
+            assert!(filter.allow(&peer, &addrs, 1));
+            filter.on_added(&peer, &addrs, 1);
+        }
+        assert!(filter.allow(&PeerId::random(), &addr("/memory/1234"), 1));
+    }
+
+    #[test]
+    fn non_global_ipv6_addresses_allowed() {
+        let mut filter = IpGroupFilter::new(1, 1);
+
+        for a in [
+            "/ip6/fd00::1/tcp/4001",
+            "/ip6/fd00::2/tcp/4001",
+            "/ip6/fe80::1/tcp/4001",
+            "/ip6/fe80::2/tcp/4001",
+        ]
+        .iter()
+        {
+            let peer = PeerId::random();
+            assert!(filter.allow(&peer, &addr(a), 1));
+            filter.on_added(&peer, &addr(a), 1);
+        }
+        // the global addresses are still limited
+        filter.on_added(&PeerId::random(), &addr("/ip6/2600::1/tcp/4001"), 1);
+        assert!(!filter.allow(&PeerId::random(), &addr("/ip6/2600::2/tcp/4001"), 1));
+    }
+
+    #[test]
+    fn updated_addresses() {
+        let mut filter = IpGroupFilter::new(1, 10);
+        let (a, b) = (PeerId::random(), PeerId::random());
+
+        // the peer is not limited before its addresses are known
+        assert!(filter.allow(&a, &[], 1));
+        filter.on_added(&a, &[], 1);
+        filter.on_added(&b, &addr("/ip4/1.2.3.4/tcp/4001"), 1);
+
+        // but it is once they are
+        assert!(!filter.on_updated(&a, &addr("/ip4/1.2.100.100/tcp/4001"), 1));
+        assert!(filter.on_updated(&a, &addr("/ip4/1.3.3.4/tcp/4001"), 1));
+        // the peer is accounted with the new addresses
+        assert!(!filter.allow(&PeerId::random(), &addr("/ip4/1.3.100.100/tcp/4001"), 1));
+        assert!(filter.on_updated(&b, &addr("/ip4/1.2.3.4/tcp/4001"), 1));
+    }
+
+    #[test]
+    fn address_scope() {
+        let lan = AddressScopeFilter::new(AddressScope::Private);
+        let wan = AddressScopeFilter::new(AddressScope::Public);
+        let peer = PeerId::random();
+
+        let private = addr("/ip4/10.0.0.1/tcp/4001");
+        let public = addr("/ip4/1.2.3.4/tcp/4001");
+        let both = vec![private[0].clone(), public[0].clone()];
+
+        assert!(lan.allow(&peer, &private, 1) && !wan.allow(&peer, &private, 1));
+        assert!(!lan.allow(&peer, &public, 1) && wan.allow(&peer, &public, 1));
+        assert!(lan.allow(&peer, &both, 1) && wan.allow(&peer, &both, 1));
+        assert!(!lan.allow(&peer, &[], 1) && !wan.allow(&peer, &[], 1));
+
+        // unique local and link-local IPv6 addresses are not public
+        for a in &["/ip6/fd00::1/tcp/4001", "/ip6/fe80::1/tcp/4001", "/ip4/100.64.0.1/tcp/4001"] {
+            assert!(lan.allow(&peer, &addr(a), 1) && !wan.allow(&peer, &addr(a), 1));
+        }
+        // the addresses without an IP component are in neither scope
+        for a in &["/dns4/example.com/tcp/4001", "/memory/1234"] {
+            assert!(!lan.allow(&peer, &addr(a), 1) && !wan.allow(&peer, &addr(a), 1));
+        }
+    }
+
+    #[test]
+    fn chained() {
+        let mut filter = ChainedFilter::new(
+            Box::new(AddressScopeFilter::new(AddressScope::Public)),
+            Box::new(IpGroupFilter::new(1, 1)),
+        );
+        let (a, b) = (PeerId::random(), PeerId::random());
+
+        assert!(!filter.allow(&a, &addr("/ip4/10.0.0.1/tcp/4001"), 1));
+        assert!(filter.allow(&a, &addr("/ip4/1.2.3.4/tcp/4001"), 1));
+        filter.on_added(&a, &addr("/ip4/1.2.3.4/tcp/4001"), 1);
+        assert!(!filter.allow(&b, &addr("/ip4/1.2.100.100/tcp/4001"), 2));
+
+        filter.on_removed(&a);
+        assert!(filter.allow(&b, &addr("/ip4/1.2.100.100/tcp/4001"), 2));
+    }
+}
@@ -160,6 +160,11 @@ impl KeyBytes {
         let key_int = U256::from(self.0.as_slice()) ^ d.0;
         KeyBytes(GenericArray::from(<[u8; 32]>::from(key_int)))
     }
+
+    /// Returns the raw bytes of the key.
+    pub(crate) fn to_vec(&self) -> Vec<u8> {
+        self.0.to_vec()
+    }
 }
 
 impl AsRef<KeyBytes> for KeyBytes {
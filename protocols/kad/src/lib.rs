@@ -25,23 +25,35 @@
 //       be useful later for record store
 #![allow(dead_code)]
 
+pub mod crawler;
+pub mod discovery;
+pub mod dual;
+pub mod events;
 pub mod kad;
 pub mod kbucket;
 pub mod protocol;
+pub mod ratelimit;
 pub mod record;
+pub mod reprovider;
 
 mod addresses;
 pub mod cli;
 mod control;
 mod query;
+mod snapshot;
 mod task_limit;
 
 pub use control::Control;
+pub use query::{PeerRecord, QueryStream, Quorum};
 
 mod dht_proto {
     include!(concat!(env!("OUT_DIR"), "/dht.pb.rs"));
 }
 
+mod ipns_proto {
+    include!(concat!(env!("OUT_DIR"), "/ipns.pb.rs"));
+}
+
 pub use record::{store, ProviderRecord, Record};
 
 use std::error::Error;
@@ -118,12 +130,18 @@ pub enum KadError {
     InvalidSource(PeerId),
     /// Received an request that is not supported yet.
     Unsupported(&'static str),
+    /// The record failed the validation.
+    InvalidRecord(&'static str),
+    /// The quorum is not met, only `success` of `quorum` peers succeeded.
+    QuorumFailed { success: usize, quorum: usize },
     /// I/O error in the substream.
     Io(std::io::Error),
     /// Underlying Swarm error.
     Swarm(SwarmError),
     /// Indicates that the Kad main loop is Closing.
     Closing(u32),
+    /// The inbound request is rejected by the rate limiter.
+    RateLimited,
 }
 
 impl fmt::Display for KadError {
@@ -133,7 +151,10 @@ impl fmt::Display for KadError {
             KadError::NoKnownPeers => write!(f, "No any peers in routing table"),
             KadError::NotFound => write!(f, "Not found"),
             KadError::Timeout => write!(f, "Iterative query timeout"),
+            KadError::InvalidRecord(e) => write!(f, "Invalid record: {}", e),
+            KadError::QuorumFailed { success, quorum } => write!(f, "Quorum failed, {} of {} succeeded", success, quorum),
             KadError::Swarm(e) => write!(f, "Underlying Swarm error {}", e),
+            KadError::RateLimited => write!(f, "Rate limited"),
             _ => write!(f, "Kad error"),
         }
     }
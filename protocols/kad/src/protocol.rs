@@ -31,6 +31,8 @@ use futures::channel::oneshot;
 use prost::Message;
 use std::error::Error;
 use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 use std::{convert::TryFrom, time::Duration, time::Instant};
 
 use async_trait::async_trait;
@@ -43,8 +45,12 @@ use libp2prs_swarm::substream::{Substream, SubstreamView};
 use libp2prs_swarm::Control as SwarmControl;
 use libp2prs_traits::{ReadEx, WriteEx};
 
+use crate::events::RequestKind;
 use crate::kad::KadPoster;
+use crate::ratelimit::RateLimiter;
+use crate::record::validator::{NamespacedValidator, Validator};
 use crate::record::{self, Record};
+use crate::snapshot::SnapshotPeer;
 use crate::{dht_proto as proto, KadError, ProviderRecord};
 
 /// The protocol name used for negotiating with multistream-select.
@@ -195,21 +201,34 @@ impl Default for KademliaProtocolConfig {
 pub struct KadProtocolHandler {
     /// The configuration of the protocol handler.
     config: KademliaProtocolConfig,
-    /// If false, we deny incoming requests.
-    allow_listening: bool,
+    /// If false, we deny incoming requests. It is shared with Kademlia, which
+    /// switches it according to the mode.
+    allow_listening: Arc<AtomicBool>,
     /// Time after which we close an idle connection.
     idle_timeout: Duration,
+    /// The validator for the inbound PutValue records.
+    validator: NamespacedValidator,
+    /// The rate limiter of the inbound requests, shared by all substreams.
+    limiter: Arc<Mutex<RateLimiter>>,
     /// Used to post ProtocolEvent to Kad main loop.
     poster: KadPoster,
 }
 
 impl KadProtocolHandler {
     /// Make a new KadProtocolHandler.
-    pub(crate) fn new(config: KademliaProtocolConfig, poster: KadPoster) -> Self {
+    pub(crate) fn new(
+        config: KademliaProtocolConfig,
+        validator: NamespacedValidator,
+        allow_listening: Arc<AtomicBool>,
+        limiter: Arc<Mutex<RateLimiter>>,
+        poster: KadPoster,
+    ) -> Self {
         KadProtocolHandler {
             config,
-            allow_listening: false,
+            allow_listening,
             idle_timeout: Duration::from_secs(10),
+            validator,
+            limiter,
             poster,
         }
     }
@@ -256,12 +275,33 @@ impl ProtocolHandler for KadProtocolHandler {
         let source = stream.remote_peer();
         log::trace!("Kad Handler opened for remote {:?}", source);
         loop {
+            // we might be switched to client mode in the middle of the way
+            if !self.accepting_inbound() {
+                log::debug!("Kad handler in client mode, rejecting {:?}", source);
+                return Err(KadError::Unsupported("client mode").into());
+            }
+
             let packet = stream.read_one(self.config.max_packet_size).await?;
             let request = proto::Message::decode(&packet[..]).map_err(|_| KadError::Decode)?;
             log::trace!("Kad handler recv : {:?}", request);
 
             let request = proto_to_req_msg(request)?;
 
+            // close the substream if the peer is sending too fast
+            let kind = RequestKind::from(&request);
+            if !self.limiter.lock().unwrap().check(&source, kind, Instant::now()) {
+                log::info!("Kad handler rate limited {:?} from {:?}", kind, source);
+                return Err(KadError::RateLimited.into());
+            }
+
+            // reject the invalid record before bothering the main loop
+            if let KadRequestMsg::PutValue { record } = &request {
+                if let Err(e) = self.validator.validate(&record.key, &record.value) {
+                    log::info!("Kad handler rejected PutValue from {:?}: {:?}", source, e);
+                    return Err(e.into());
+                }
+            }
+
             // For AddProvider request, KadResponse is not needed
             let (tx, rx) = oneshot::channel();
             let evt = ProtocolEvent::KadRequest {
@@ -283,6 +323,10 @@ impl ProtocolHandler for KadProtocolHandler {
         }
     }
 
+    fn accepting_inbound(&self) -> bool {
+        self.allow_listening.load(Ordering::SeqCst)
+    }
+
     fn box_clone(&self) -> IProtocolHandler {
         Box::new(self.clone())
     }
@@ -367,6 +411,14 @@ impl KadMessenger {
         Ok(response)
     }
 
+    pub(crate) async fn send_ping(&mut self) -> Result<(), KadError> {
+        let rsp = self.send_request(KadRequestMsg::Ping).await?;
+        match rsp {
+            KadResponseMsg::Pong => Ok(()),
+            _ => Err(KadError::UnexpectedMessage("wrong message type received when Ping")),
+        }
+    }
+
     pub(crate) async fn send_find_node(&mut self, key: record::Key) -> Result<Vec<KadPeer>, KadError> {
         let req = KadRequestMsg::FindNode { key };
         let rsp = self.send_request(req).await?;
@@ -893,6 +945,21 @@ pub(crate) enum ProtocolEvent {
     /// Timer event for Refresh.
     RefreshTimer,
 
+    /// Timer event for saving the snapshot of the routing table.
+    SnapshotTimer,
+
+    /// The peers loaded from the snapshot of the routing table.
+    SnapshotLoaded(Vec<SnapshotPeer>),
+
+    /// Timer event for republishing the IPNS records.
+    RepublishTimer,
+
+    /// Timer event for reproviding the provider records.
+    ReprovideTimer,
+
+    /// Timer event for random-walk discovery.
+    DiscoveryTimer,
+
     /// Kad request message from remote peer.
     ///
     KadRequest {
@@ -18,12 +18,15 @@
 // FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
 // DEALINGS IN THE SOFTWARE.
 
-use futures::channel::mpsc;
+use fnv::FnvHashMap;
+use futures::channel::{mpsc, oneshot};
 use futures::future::Either;
-use futures::{FutureExt, SinkExt, StreamExt};
+use futures::{FutureExt, SinkExt, Stream, StreamExt};
 use std::collections::BTreeMap;
+use std::pin::Pin;
 use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
 use std::{num::NonZeroUsize, time::Duration, time::Instant};
 
 use libp2prs_core::peerstore::{PROVIDER_ADDR_TTL, TEMP_ADDR_TTL};
@@ -31,7 +34,9 @@ use libp2prs_core::{Multiaddr, PeerId};
 use libp2prs_runtime::task;
 use libp2prs_swarm::Control as SwarmControl;
 
+use crate::events::{KadEvent, KadEventSender, PathReport, QueryKind, QueryOutcome, QueryReport};
 use crate::kbucket::{Distance, Key};
+use crate::record::validator::{NamespacedValidator, Validator};
 use crate::{record, KadError, ALPHA_VALUE, BETA_VALUE, K_VALUE};
 
 use crate::kad::{KadPoster, MessageStats, MessengerManager};
@@ -64,6 +69,14 @@ impl IterativeStatsAtomic {
     }
 }
 
+// Updates the statistics of the given path of a disjoint query, if the query has
+// multiple paths.
+fn update_path(paths: &Mutex<Vec<PathReport>>, path: usize, f: impl FnOnce(&mut PathReport)) {
+    if let Some(stats) = paths.lock().unwrap().get_mut(path) {
+        f(stats);
+    }
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct QueryStats {
     pub(crate) fixed_query_executed: usize,
@@ -71,12 +84,26 @@ pub struct QueryStats {
     pub(crate) iter_query_executed: usize,
     pub(crate) iter_query_completed: usize,
     pub(crate) iter_query_timeout: usize,
+    pub(crate) iter_query_cancelled: usize,
     // details of iterative query
     pub(crate) iterative: IterativeStats,
     // Kad message tx
     pub(crate) message_tx: MessageStats,
 }
 
+impl QueryStats {
+    /// The number of streaming queries cancelled by dropping the stream.
+    pub fn iter_query_cancelled(&self) -> usize {
+        self.iter_query_cancelled
+    }
+
+    /// The number of iterative queries still in progress.
+    pub fn iter_query_running(&self) -> usize {
+        self.iter_query_executed
+            .saturating_sub(self.iter_query_completed + self.iter_query_timeout + self.iter_query_cancelled)
+    }
+}
+
 #[derive(Default)]
 pub(crate) struct QueryStatsAtomic {
     pub(crate) fixed_query_executed: AtomicUsize,
@@ -84,6 +111,7 @@ pub(crate) struct QueryStatsAtomic {
     pub(crate) iter_query_executed: AtomicUsize,
     pub(crate) iter_query_completed: AtomicUsize,
     pub(crate) iter_query_timeout: AtomicUsize,
+    pub(crate) iter_query_cancelled: AtomicUsize,
     // details of iterative query
     pub(crate) iterative: IterativeStatsAtomic,
     // message tx
@@ -98,6 +126,7 @@ impl QueryStatsAtomic {
             iter_query_executed: self.iter_query_executed.load(Ordering::Relaxed),
             iter_query_completed: self.iter_query_completed.load(Ordering::Relaxed),
             iter_query_timeout: self.iter_query_timeout.load(Ordering::Relaxed),
+            iter_query_cancelled: self.iter_query_cancelled.load(Ordering::Relaxed),
             iterative: self.iterative.to_view(),
             message_tx: self.message_tx.to_view(),
         }
@@ -144,6 +173,10 @@ pub struct QueryConfig {
     /// The number of peers closest to a target that must have responded
     /// for an iterative query to terminate.
     pub beta_value: NonZeroUsize,
+
+    /// The number of disjoint paths for FindPeer, GetProviders and GetValue
+    /// lookups, as described in S/Kademlia. `None` means a single path.
+    pub disjoint_paths: Option<NonZeroUsize>,
 }
 
 impl Default for QueryConfig {
@@ -153,6 +186,87 @@ impl Default for QueryConfig {
             k_value: K_VALUE,
             alpha_value: ALPHA_VALUE,
             beta_value: BETA_VALUE,
+            disjoint_paths: None,
+        }
+    }
+}
+
+/// The streaming results of an iterative query.
+///
+/// The items are delivered as soon as they are found by the query. The stream
+/// terminates when the query is done, and the query is cancelled if the stream
+/// is dropped.
+pub struct QueryStream<T> {
+    rx: mpsc::UnboundedReceiver<T>,
+    // dropping it signals the query to be cancelled
+    _cancel: oneshot::Sender<()>,
+}
+
+impl<T> QueryStream<T> {
+    /// Creates a new stream, along with the sender of the items and the
+    /// cancellation receiver for the query.
+    pub(crate) fn new() -> (Self, mpsc::UnboundedSender<T>, oneshot::Receiver<()>) {
+        let (tx, rx) = mpsc::unbounded();
+        let (cancel_tx, cancel_rx) = oneshot::channel();
+        (Self { rx, _cancel: cancel_tx }, tx, cancel_rx)
+    }
+}
+
+impl<T> Stream for QueryStream<T> {
+    type Item = T;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.rx.poll_next_unpin(cx)
+    }
+}
+
+/// The sending side of a [`QueryStream`], used by the iterative query to deliver
+/// the providers or records as they are found.
+pub(crate) enum QuerySink {
+    Providers(mpsc::UnboundedSender<KadPeer>),
+    Records(mpsc::UnboundedSender<PeerRecord>),
+}
+
+impl QuerySink {
+    pub(crate) fn send_providers(&self, providers: &[KadPeer]) {
+        if let QuerySink::Providers(tx) = self {
+            for p in providers {
+                let _ = tx.unbounded_send(p.clone());
+            }
+        }
+    }
+
+    pub(crate) fn send_records(&self, records: &[PeerRecord]) {
+        if let QuerySink::Records(tx) = self {
+            for r in records {
+                let _ = tx.unbounded_send(r.clone());
+            }
+        }
+    }
+}
+
+/// The quorum of a query, i.e. the minimum number of distinct peers which
+/// must respond successfully.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Quorum {
+    /// One peer.
+    One,
+    /// More than half of the peers.
+    Majority,
+    /// All of the peers.
+    All,
+    /// The given number of peers, but no more than all of them.
+    N(NonZeroUsize),
+}
+
+impl Quorum {
+    /// Evaluates the quorum w.r.t. the total number of peers.
+    pub(crate) fn eval(&self, total: NonZeroUsize) -> NonZeroUsize {
+        match self {
+            Quorum::One => NonZeroUsize::new(1).expect("1 != 0"),
+            Quorum::Majority => NonZeroUsize::new(total.get() / 2 + 1).expect("n / 2 + 1 != 0"),
+            Quorum::All => total,
+            Quorum::N(n) => NonZeroUsize::min(total, *n),
         }
     }
 }
@@ -191,9 +305,11 @@ impl FixedQuery {
         }
     }
 
+    /// Runs the fixed query. The number of peers which have succeeded is delivered
+    /// into the callback.
     pub(crate) fn run<F>(self, f: F)
     where
-        F: FnOnce(Result<()>) + Send + 'static,
+        F: FnOnce(Result<usize>) + Send + 'static,
     {
         log::debug!("run fixed query {:?}", self.query_type);
 
@@ -211,12 +327,14 @@ impl FixedQuery {
         let me = self;
         let mut limiter = TaskLimiter::new(me.config.alpha_value);
         let message_stats = stats.clone();
+        let succeeded = Arc::new(AtomicUsize::new(0));
         task::spawn(async move {
             for peer in me.peers {
                 // let record = record.clone();
                 let mut messengers = me.messengers.clone();
                 let qt = me.query_type.clone();
                 let stats = message_stats.clone();
+                let succeeded = succeeded.clone();
 
                 limiter
                     .run(async move {
@@ -225,12 +343,14 @@ impl FixedQuery {
                                 QueryType::PutRecord { record } => {
                                     stats.message_tx.put_value.fetch_add(1, Ordering::SeqCst);
                                     if ms.send_put_value(record).await.is_ok() {
+                                        succeeded.fetch_add(1, Ordering::SeqCst);
                                         messengers.put_messenger(ms);
                                     }
                                 }
                                 QueryType::AddProvider { provider, addresses } => {
                                     stats.message_tx.add_provider.fetch_add(1, Ordering::SeqCst);
                                     if ms.send_add_provider(provider, addresses).await.is_ok() {
+                                        succeeded.fetch_add(1, Ordering::SeqCst);
                                         messengers.put_messenger(ms);
                                     }
                                 }
@@ -244,12 +364,13 @@ impl FixedQuery {
                     .await;
             }
             let c = limiter.wait().await;
-            log::info!("data announced to total {} peers", c);
+            let succeeded = succeeded.load(Ordering::SeqCst);
+            log::info!("data announced to total {} peers, {} succeeded", c, succeeded);
 
             // update stats
             stats.fixed_query_completed.fetch_add(1, Ordering::SeqCst);
 
-            f(Ok(()))
+            f(Ok(succeeded))
         });
     }
 }
@@ -394,6 +515,18 @@ pub(crate) struct QueryResult {
     pub(crate) cache_peers: Option<Vec<PeerId>>,
 }
 
+impl QueryResult {
+    // Checks if the query of the kind has found what it wants.
+    fn is_found(&self, kind: QueryKind) -> bool {
+        match kind {
+            QueryKind::FindPeer => self.found_peer.is_some(),
+            QueryKind::GetProviders => self.providers.as_ref().map_or(false, |p| !p.is_empty()),
+            QueryKind::GetRecord => self.records.as_ref().map_or(false, |r| !r.is_empty()),
+            _ => self.closest_peers.is_some(),
+        }
+    }
+}
+
 pub(crate) struct ClosestPeers {
     /// The target key.
     target: Key<record::Key>,
@@ -490,6 +623,77 @@ impl ClosestPeers {
     }
 }
 
+/// The disjoint paths of an iterative query.
+///
+/// Each path has its own closest peers, and a peer belongs to at most one path,
+/// so that it will never be queried by more than one path. A query without the
+/// disjoint paths simply has one path.
+pub(crate) struct DisjointPaths {
+    /// The closest peers of each path.
+    paths: Vec<ClosestPeers>,
+    /// The path which a peer belongs to.
+    owners: FnvHashMap<PeerId, usize>,
+}
+
+impl DisjointPaths {
+    fn new(key: record::Key, num: usize) -> Self {
+        Self {
+            paths: (0..num).map(|_| ClosestPeers::new(key.clone())).collect(),
+            owners: Default::default(),
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.paths.len()
+    }
+
+    // returns the path which the peer belongs to
+    fn path_of(&self, peer_id: &PeerId) -> Option<usize> {
+        self.owners.get(peer_id).copied()
+    }
+
+    // distributes the seeds to the paths in a round-robin fashion
+    fn add_seeds(&mut self, seeds: Vec<KadPeer>) {
+        let num = self.len();
+        for (i, seed) in seeds.into_iter().enumerate() {
+            self.add_peers(i % num, vec![seed]);
+        }
+    }
+
+    // adds the peers to the path, skipping those which belong to other paths
+    fn add_peers(&mut self, path: usize, mut peers: Vec<KadPeer>) {
+        peers.retain(|p| *self.owners.entry(p.node_id).or_insert(path) == path);
+        self.paths[path].add_peers(peers);
+    }
+
+    fn set_peer_state(&mut self, peer_id: &PeerId, state: PeerState) {
+        if let Some(path) = self.path_of(peer_id) {
+            self.paths[path].set_peer_state(peer_id, state);
+        }
+    }
+
+    fn has_target(&self) -> Option<KadPeer> {
+        self.paths.iter().find_map(|p| p.has_target())
+    }
+
+    fn is_starved(&self) -> bool {
+        self.paths.iter().all(|p| p.is_starved())
+    }
+
+    fn can_terminate(&self, beta_value: usize) -> bool {
+        self.paths.iter().all(|p| p.is_starved() || p.can_terminate(beta_value))
+    }
+
+    // merges all paths into one, sorted by distance
+    fn merge(&self) -> ClosestPeers {
+        let mut merged = ClosestPeers::new(self.paths[0].target.preimage().clone());
+        for p in self.paths.iter() {
+            merged.closest_peers.extend(p.closest_peers.iter().map(|(d, p)| (*d, p.clone())));
+        }
+        merged
+    }
+}
+
 /// Information about a running query.
 #[derive(Debug, Clone)]
 pub enum QueryType {
@@ -547,8 +751,16 @@ pub(crate) struct IterativeQuery {
     seeds: Vec<Key<PeerId>>,
     /// The KadPoster used to post ProtocolEvent to Kad main loop.
     poster: KadPoster,
+    /// The validator for the records retrieved by GetRecord.
+    validator: NamespacedValidator,
+    /// The sink of the streaming query, if any.
+    sink: Option<QuerySink>,
+    /// The streaming query is cancelled when it is resolved.
+    cancel: Option<oneshot::Receiver<()>>,
     /// The statistics.
     stats: Arc<QueryStatsAtomic>,
+    /// The sender of Kad events, used to report the query.
+    events: KadEventSender,
 }
 
 impl IterativeQuery {
@@ -562,7 +774,9 @@ impl IterativeQuery {
         config: QueryConfig,
         seeds: Vec<Key<PeerId>>,
         poster: KadPoster,
+        validator: NamespacedValidator,
         stats: Arc<QueryStatsAtomic>,
+        events: KadEventSender,
     ) -> Self {
         Self {
             query_type,
@@ -573,15 +787,40 @@ impl IterativeQuery {
             config,
             seeds,
             poster,
+            validator,
+            sink: None,
+            cancel: None,
             stats,
+            events,
         }
     }
 
+    /// Makes the query a streaming query, which delivers the items found into the
+    /// sink, and can be cancelled by `cancel`.
+    pub(crate) fn with_sink(mut self, sink: QuerySink, cancel: oneshot::Receiver<()>) -> Self {
+        self.sink = Some(sink);
+        self.cancel = Some(cancel);
+        self
+    }
+
+    /// Figures out the closest peers which don't have any valid record. They are
+    /// the candidates to be corrected with the best record.
+    fn peers_without_record(closest_peers: &ClosestPeers, records: &[PeerRecord], k_value: usize) -> Vec<PeerId> {
+        let peers_have_value = records.iter().filter_map(|r| r.peer.as_ref()).collect::<Vec<_>>();
+
+        closest_peers
+            .peers_filter(k_value, |p| {
+                p.state != PeerState::Unreachable && !peers_have_value.contains(&&p.peer.node_id)
+            })
+            .map(|p| p.peer.node_id)
+            .collect::<Vec<_>>()
+    }
+
     pub(crate) async fn handle_update(
         &mut self,
         update: QueryUpdate,
         query_results: &mut QueryResult,
-        closest_peers: &mut ClosestPeers,
+        paths: &mut DisjointPaths,
         this_stats: &IterativeStatsAtomic,
     ) -> bool {
         let me = self;
@@ -620,8 +859,14 @@ impl IterativeQuery {
                     me.swarm.add_addrs(&peer.node_id, addrs, TEMP_ADDR_TTL);
                 }
 
-                closest_peers.add_peers(closer);
-                closest_peers.set_peer_state(&source, PeerState::Succeeded);
+                // the seeds come from myself, otherwise the closer peers go to the path of the source
+                match paths.path_of(&source) {
+                    Some(path) => {
+                        paths.add_peers(path, closer);
+                        paths.set_peer_state(&source, PeerState::Succeeded);
+                    }
+                    None => paths.add_seeds(closer),
+                }
 
                 // signal the k-buckets for new peer found
                 let _ = me.poster.post(ProtocolEvent::KadPeerFound(source, true)).await;
@@ -630,7 +875,7 @@ impl IterativeQuery {
                 match me.query_type {
                     QueryType::GetClosestPeers => {}
                     QueryType::FindPeer => {
-                        if let Some(peer) = closest_peers.has_target() {
+                        if let Some(peer) = paths.has_target() {
                             log::debug!("FindPeer: successfully located, {:?}", peer);
                             query_results.found_peer = Some(peer);
                             return true;
@@ -648,15 +893,15 @@ impl IterativeQuery {
                             }
 
                             if !provider.is_empty() {
-                                // append or create the query_results.providers
-                                if let Some(mut old) = query_results.providers.take() {
-                                    old.extend(provider);
-                                    // remove duplicated peers
-                                    old.dedup_by(|a, b| a.node_id == b.node_id);
-                                    query_results.providers = Some(old);
-                                } else {
-                                    query_results.providers = Some(provider);
+                                // append or create the query_results.providers, removing duplicated peers
+                                let mut providers = query_results.providers.take().unwrap_or_default();
+                                let mut found = provider;
+                                found.retain(|p| providers.iter().all(|o| o.node_id != p.node_id));
+                                if let Some(sink) = &me.sink {
+                                    sink.send_providers(&found);
                                 }
+                                providers.extend(found);
+                                query_results.providers = Some(providers);
                                 // check if we have enough providers
                                 if count != 0 && query_results.providers.as_ref().map_or(0, |p| p.len()) >= count {
                                     log::debug!("GetProviders: got enough provider for {:?}, limit={}", me.key, count);
@@ -669,10 +914,19 @@ impl IterativeQuery {
                         if let Some(record) = record {
                             log::debug!("GetRecord: record found {:?} key={:?}", record, me.key);
 
+                            // drop the invalid record, the peer will be corrected later with the best one
+                            if let Err(e) = me.validator.validate(&record.key, &record.value) {
+                                log::info!("GetRecord: invalid record from {:?}: {:?}", source, e);
+                                return false;
+                            }
+
                             let pr = PeerRecord {
                                 peer: Some(source),
                                 record,
                             };
+                            if let Some(sink) = &me.sink {
+                                sink.send_records(std::slice::from_ref(&pr));
+                            }
                             if let Some(mut old) = query_results.records.take() {
                                 old.push(pr);
                                 query_results.records = Some(old);
@@ -681,24 +935,11 @@ impl IterativeQuery {
                             }
 
                             // check if we have enough records
-                            if query_results.records.as_ref().map_or(0, |r| r.len()) > quorum {
+                            if query_results.records.as_ref().map_or(0, |r| r.len()) >= quorum {
                                 log::info!("GetRecord: got enough records for key={:?}", me.key);
 
-                                let peers_have_value = query_results
-                                    .records
-                                    .as_ref()
-                                    .expect("must be Some")
-                                    .iter()
-                                    .filter_map(|r| r.peer.as_ref())
-                                    .collect::<Vec<_>>();
-
-                                // figure out the closest peers which didn't have the record
-                                let peers = closest_peers
-                                    .peers_filter(k_value, |p| {
-                                        p.state != PeerState::Unreachable && !peers_have_value.contains(&&p.peer.node_id)
-                                    })
-                                    .map(|p| p.peer.node_id)
-                                    .collect::<Vec<_>>();
+                                let records = query_results.records.as_ref().expect("must be Some");
+                                let peers = Self::peers_without_record(&paths.merge(), records, k_value);
 
                                 log::debug!("GetValue, got peers which don't have the value, {:?}", peers);
                                 query_results.cache_peers = Some(peers);
@@ -715,7 +956,7 @@ impl IterativeQuery {
 
                 this_stats.failure.fetch_add(1, Ordering::SeqCst);
 
-                closest_peers.set_peer_state(&peer, PeerState::Unreachable);
+                paths.set_peer_state(&peer, PeerState::Unreachable);
                 // signal for dead peer detected
                 let _ = me.poster.post(ProtocolEvent::KadPeerStopped(peer)).await;
             }
@@ -740,6 +981,11 @@ impl IterativeQuery {
         // update stats
         self.stats.iter_query_executed.fetch_add(1, Ordering::SeqCst);
 
+        let kind = QueryKind::from(&self.query_type);
+        let key = self.key.clone();
+        let events = self.events.clone();
+        events.emit(KadEvent::QueryStarted { kind, key: key.clone() });
+
         let mut me = self;
         let alpha_value = me.config.alpha_value.get();
         let beta_value = me.config.beta_value.get();
@@ -749,7 +995,14 @@ impl IterativeQuery {
 
         // closest_peers is used to retrieve the closer peers. It is a sorted btree-map, which is
         // indexed by Distance of the peer. The queried 'key' is used to calculate the distance.
-        let mut closest_peers = ClosestPeers::new(me.key.clone());
+        // There is one for each disjoint path, if the disjoint paths are enabled for the query.
+        let num_paths = match me.query_type {
+            QueryType::FindPeer | QueryType::GetProviders { .. } | QueryType::GetRecord { .. } => {
+                me.config.disjoint_paths.map_or(1, |d| d.get())
+            }
+            _ => 1,
+        };
+        let mut paths = DisjointPaths::new(me.key.clone(), num_paths);
         // prepare the query result
         let mut query_results = QueryResult {
             closest_peers: None,
@@ -770,6 +1023,10 @@ impl IterativeQuery {
             }
             _ => {}
         }
+        if let Some(sink) = &me.sink {
+            sink.send_providers(query_results.providers.as_deref().unwrap_or_default());
+            sink.send_records(query_results.records.as_deref().unwrap_or_default());
+        }
 
         // the channel used to deliver the result of each jobs
         let (mut tx, mut rx) = mpsc::channel(alpha_value);
@@ -785,8 +1042,28 @@ impl IterativeQuery {
             log::info!("iterative query timeout");
         };
 
+        // cancellation of a streaming query, never happens for a non-streaming query
+        let stats = me.stats.clone();
+        let cancel = me.cancel.take();
+        let cancel = async move {
+            match cancel {
+                Some(cancel) => {
+                    let _ = cancel.await;
+                    stats.iter_query_cancelled.fetch_add(1, Ordering::SeqCst);
+                    log::info!("iterative query cancelled, report : {:?}", stats.iterative.to_view());
+                }
+                None => futures::future::pending().await,
+            }
+        };
+
         // clone stats and move into query
         let stats = me.stats.clone();
+        // statistics of this query only, for the query report
+        let this_query = Arc::new(IterativeStatsAtomic::default());
+        let counters = this_query.clone();
+        // statistics of each path of this query, empty if there is a single path
+        let this_paths = Arc::new(Mutex::new(vec![PathReport::default(); if num_paths > 1 { num_paths } else { 0 }]));
+        let path_counters = this_paths.clone();
         // a runtime for query
         let query = async move {
             let seeds = me
@@ -819,64 +1096,83 @@ impl IterativeQuery {
             loop {
                 // note that the first update comes from the initial seeds
                 let update = rx.next().await.expect("must");
-                let is_completed = me
-                    .handle_update(update, &mut query_results, &mut closest_peers, &stats.iterative)
-                    .await;
+                match &update {
+                    QueryUpdate::Queried { source, .. } if *source != me.local_id => {
+                        counters.success.fetch_add(1, Ordering::SeqCst);
+                        if let Some(path) = paths.path_of(source) {
+                            update_path(&path_counters, path, |s| s.success += 1);
+                        }
+                    }
+                    QueryUpdate::Unreachable(peer) => {
+                        counters.failure.fetch_add(1, Ordering::SeqCst);
+                        if let Some(path) = paths.path_of(peer) {
+                            update_path(&path_counters, path, |s| s.failure += 1);
+                        }
+                    }
+                    _ => {}
+                }
+                let is_completed = me.handle_update(update, &mut query_results, &mut paths, &stats.iterative).await;
                 if is_completed {
                     log::debug!("iterative query completed due to value found");
                     break;
                 }
 
                 // starvation, if no peer to contact and no pending query
-                if closest_peers.is_starved() {
+                if paths.is_starved() {
                     //return true, LookupStarvation, nil
                     log::debug!("iterative query terminated due to starvation(no peer to contact and no pending query)");
                     break;
                 }
                 // meet the k_value? meaning lookup completed
-                if closest_peers.can_terminate(beta_value) {
+                if paths.can_terminate(beta_value) {
                     //return true, LookupCompleted, nil
                     log::debug!("iterative query terminated due to no more closer peer");
                     break;
                 }
 
-                // calculate the maximum number of queries we could be running
-                // Note: NumWaiting will be updated before invoking job.execute()
-                let num_jobs = alpha_value.checked_sub(closest_peers.num_of_state(PeerState::Waiting)).unwrap();
-
-                log::debug!("iterative query, starting {} query jobs at most", num_jobs);
-
-                let peer_iter = closest_peers.peers_in_state_mut(PeerState::NotContacted, num_jobs);
-                for peer in peer_iter {
-                    //closest_peers.set_peer_state(&peer, PeerState::Waiting);
-                    peer.state = PeerState::Waiting;
-                    let peer_id = peer.peer.node_id;
-
-                    log::debug!("creating query job for {:?}", peer_id);
-
-                    stats.iterative.requests.fetch_add(1, Ordering::SeqCst);
-
-                    let job = QueryJob {
-                        key: me.key.clone(),
-                        qt: me.query_type.clone(),
-                        messengers: me.messengers.clone(),
-                        peer: peer_id,
-                        stats: stats.clone(),
-                        tx: tx.clone(),
-                    };
-
-                    let mut tx = tx.clone();
-                    let _ = task::spawn(async move {
-                        let r = job.execute().await;
-                        if r.is_err() {
-                            log::debug!("Unreachable error: {:?}", r);
-                            let _ = tx.send(QueryUpdate::Unreachable(peer_id)).await;
-                        }
-                    });
+                // each path runs its own query jobs, at most alpha in parallel
+                for (path, closest_peers) in paths.paths.iter_mut().enumerate() {
+                    // calculate the maximum number of queries we could be running
+                    // Note: NumWaiting will be updated before invoking job.execute()
+                    let num_jobs = alpha_value.checked_sub(closest_peers.num_of_state(PeerState::Waiting)).unwrap();
+
+                    log::debug!("iterative query, starting {} query jobs at most on path {}", num_jobs, path);
+
+                    let peer_iter = closest_peers.peers_in_state_mut(PeerState::NotContacted, num_jobs);
+                    for peer in peer_iter {
+                        //closest_peers.set_peer_state(&peer, PeerState::Waiting);
+                        peer.state = PeerState::Waiting;
+                        let peer_id = peer.peer.node_id;
+
+                        log::debug!("creating query job for {:?}", peer_id);
+
+                        stats.iterative.requests.fetch_add(1, Ordering::SeqCst);
+                        counters.requests.fetch_add(1, Ordering::SeqCst);
+                        update_path(&path_counters, path, |s| s.requests += 1);
+
+                        let job = QueryJob {
+                            key: me.key.clone(),
+                            qt: me.query_type.clone(),
+                            messengers: me.messengers.clone(),
+                            peer: peer_id,
+                            stats: stats.clone(),
+                            tx: tx.clone(),
+                        };
+
+                        let mut tx = tx.clone();
+                        let _ = task::spawn(async move {
+                            let r = job.execute().await;
+                            if r.is_err() {
+                                log::debug!("Unreachable error: {:?}", r);
+                                let _ = tx.send(QueryUpdate::Unreachable(peer_id)).await;
+                            }
+                        });
+                    }
                 }
             }
 
-            // collect the query result
+            // collect the query result, merging all the paths
+            let closest_peers = paths.merge();
             let wanted_states = vec![PeerState::NotContacted, PeerState::Waiting, PeerState::Succeeded];
             let peers = closest_peers
                 .peers_in_states(wanted_states, k_value)
@@ -896,6 +1192,16 @@ impl IterativeQuery {
                 query_results.closest_peers = Some(peers);
             }
 
+            // the query might be terminated without enough records, we still have to figure out
+            // the peers to be corrected
+            if let QueryType::GetRecord { .. } = me.query_type {
+                if query_results.cache_peers.is_none() {
+                    if let Some(records) = query_results.records.as_ref() {
+                        query_results.cache_peers = Some(Self::peers_without_record(&closest_peers, records, k_value));
+                    }
+                }
+            }
+
             stats.iter_query_completed.fetch_add(1, Ordering::SeqCst);
 
             // calculate how long this iterative query lasts
@@ -906,11 +1212,31 @@ impl IterativeQuery {
             Ok(query_results)
         };
 
-        task::spawn(async {
-            let either = futures::future::select(query.boxed(), deadline.boxed()).await;
+        task::spawn(async move {
+            let either = futures::future::select(query.boxed(), futures::future::select(deadline.boxed(), cancel.boxed())).await;
+            let outcome = match &either {
+                Either::Left((Ok(r), _)) if r.is_found(kind) => QueryOutcome::Succeeded,
+                Either::Left(_) => QueryOutcome::Failed,
+                Either::Right((Either::Left(_), _)) => QueryOutcome::Timeout,
+                Either::Right((Either::Right(_), _)) => QueryOutcome::Cancelled,
+            };
+            let counters = this_query.to_view();
+            events.emit(KadEvent::QueryFinished(QueryReport {
+                kind,
+                key,
+                outcome,
+                duration: start.elapsed(),
+                requests: counters.requests,
+                success: counters.success,
+                failure: counters.failure,
+                paths: this_paths.lock().unwrap().clone(),
+            }));
+
             match either {
                 Either::Left((result, _)) => f(result),
-                Either::Right((_, _)) => f(Err(KadError::Timeout)),
+                Either::Right((Either::Left(_), _)) => f(Err(KadError::Timeout)),
+                // nobody cares about the result any more
+                Either::Right((Either::Right(_), _)) => {}
             }
         });
     }
@@ -921,6 +1247,16 @@ impl IterativeQuery {
 mod test_closest_peers {
     use super::*;
 
+    #[test]
+    fn test_quorum_eval() {
+        let total = NonZeroUsize::new(20).unwrap();
+        assert_eq!(Quorum::One.eval(total).get(), 1);
+        assert_eq!(Quorum::Majority.eval(total).get(), 11);
+        assert_eq!(Quorum::All.eval(total).get(), 20);
+        assert_eq!(Quorum::N(NonZeroUsize::new(5).unwrap()).eval(total).get(), 5);
+        assert_eq!(Quorum::N(NonZeroUsize::new(50).unwrap()).eval(total).get(), 20);
+    }
+
     #[test]
     fn test_closest_peers_distance() {
         let peer = PeerId::random();
@@ -968,6 +1304,44 @@ mod test_closest_peers {
         assert!(closest_peers.is_starved());
     }
 
+    #[test]
+    fn test_disjoint_paths() {
+        let kad_peer = |node_id| KadPeer {
+            node_id,
+            multiaddrs: vec![],
+            connection_ty: KadConnectionType::NotConnected,
+        };
+
+        let mut paths = DisjointPaths::new(PeerId::random().into(), 2);
+        let seeds = (0..4).map(|_| PeerId::random()).collect::<Vec<_>>();
+        paths.add_seeds(seeds.iter().map(|p| kad_peer(*p)).collect());
+
+        // round-robin
+        assert_eq!(paths.path_of(&seeds[0]), Some(0));
+        assert_eq!(paths.path_of(&seeds[1]), Some(1));
+        assert_eq!(paths.paths[0].closest_peers.len(), 2);
+        assert_eq!(paths.paths[1].closest_peers.len(), 2);
+
+        // the peers belong to path 1 can't be taken by path 0
+        let peer = PeerId::random();
+        paths.add_peers(0, vec![kad_peer(seeds[1]), kad_peer(peer)]);
+        assert_eq!(paths.path_of(&seeds[1]), Some(1));
+        assert_eq!(paths.path_of(&peer), Some(0));
+        assert_eq!(paths.paths[0].closest_peers.len(), 3);
+        assert_eq!(paths.paths[1].closest_peers.len(), 2);
+
+        // path 1 terminates only when all its peers succeeded
+        for p in seeds.iter().chain(std::iter::once(&peer)) {
+            paths.set_peer_state(p, PeerState::Succeeded);
+            assert_eq!(paths.paths[paths.path_of(p).unwrap()].get_peer_state(p), Some(PeerState::Succeeded));
+        }
+        assert!(paths.is_starved());
+        assert!(paths.can_terminate(1));
+
+        let merged = paths.merge();
+        assert_eq!(merged.closest_peers.len(), 5);
+    }
+
     #[test]
     fn test_terminate() {
         // closest_peers is empty, can terminate
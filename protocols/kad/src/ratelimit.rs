@@ -0,0 +1,319 @@
+// Copyright 2020 Netwarps Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Rate limiting of the inbound Kad requests.
+//!
+//! Every type of inbound requests can be limited by a token bucket per peer,
+//! and a global token bucket shared by all peers. A request is rejected if
+//! either of the buckets runs out of tokens, and the substream it came from
+//! is closed. Besides, the number of provider records stored for a peer can
+//! be capped, so that a single peer can't fill up the store.
+
+use fnv::FnvHashMap;
+use std::time::{Duration, Instant};
+
+use libp2prs_core::PeerId;
+
+use crate::events::RequestKind;
+
+/// The number of peers tracked, above which the idle buckets are pruned.
+const PRUNE_THRESHOLD: usize = 1024;
+
+/// A token bucket limit, i.e. `rate` tokens per second, up to `burst` tokens.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    /// The number of tokens refilled per second.
+    pub rate: f64,
+    /// The capacity of the bucket.
+    pub burst: u32,
+}
+
+impl RateLimit {
+    /// Creates a limit of `rate` requests per second, with bursts of at most
+    /// `burst` requests.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `rate` is negative or not finite, or if `burst` is 0.
+    pub fn new(rate: f64, burst: u32) -> Self {
+        assert!(rate.is_finite() && rate >= 0.0, "invalid rate: {}", rate);
+        assert!(burst > 0, "burst must be greater than 0");
+        Self { rate, burst }
+    }
+
+    // The time it takes to refill an empty bucket, which saturates if the rate
+    // is too small to be represented.
+    fn refill_time(&self) -> Duration {
+        let secs = self.burst as f64 / self.rate;
+        if secs < u64::MAX as f64 {
+            Duration::from_secs_f64(secs)
+        } else {
+            Duration::from_secs(u64::MAX)
+        }
+    }
+}
+
+/// The configuration of the rate limiting. Nothing is limited by default.
+#[derive(Debug, Clone, Default)]
+pub struct RateLimitConfig {
+    per_peer: FnvHashMap<RequestKind, RateLimit>,
+    global: FnvHashMap<RequestKind, RateLimit>,
+    max_providers_per_peer: Option<usize>,
+}
+
+impl RateLimitConfig {
+    /// Sets the limit of the requests of the type, for each peer.
+    pub fn with_peer_limit(mut self, kind: RequestKind, limit: RateLimit) -> Self {
+        self.per_peer.insert(kind, limit);
+        self
+    }
+
+    /// Sets the limit of the requests of the type, for all peers.
+    pub fn with_global_limit(mut self, kind: RequestKind, limit: RateLimit) -> Self {
+        self.global.insert(kind, limit);
+        self
+    }
+
+    /// Sets the maximum number of provider records stored for a peer.
+    pub fn with_max_providers_per_peer(mut self, max: usize) -> Self {
+        self.max_providers_per_peer = Some(max);
+        self
+    }
+
+    /// Returns the maximum number of provider records stored for a peer.
+    pub fn max_providers_per_peer(&self) -> Option<usize> {
+        self.max_providers_per_peer
+    }
+}
+
+/// The statistics of the rate limiting.
+#[derive(Debug, Clone, Default)]
+pub struct RateLimitStats {
+    /// The number of requests rejected by the per-peer limits.
+    pub peer_rejected: usize,
+    /// The number of requests rejected by the global limits.
+    pub global_rejected: usize,
+    /// The number of provider records rejected by the cap of each peer.
+    pub provider_rejected: usize,
+}
+
+#[derive(Debug, Clone)]
+struct TokenBucket {
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    fn new(limit: &RateLimit, now: Instant) -> Self {
+        Self {
+            tokens: limit.burst as f64,
+            last: now,
+        }
+    }
+
+    // Refills the bucket per the time elapsed.
+    fn refill(&mut self, limit: &RateLimit, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.rate).min(limit.burst as f64);
+        self.last = now;
+    }
+
+    fn has_token(&self) -> bool {
+        self.tokens >= 1.0
+    }
+}
+
+/// The rate limiter of the inbound requests. It is shared by all the inbound
+/// substreams.
+#[derive(Debug, Default)]
+pub(crate) struct RateLimiter {
+    config: RateLimitConfig,
+    global: FnvHashMap<RequestKind, TokenBucket>,
+    peers: FnvHashMap<PeerId, FnvHashMap<RequestKind, TokenBucket>>,
+    pub(crate) stats: RateLimitStats,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(config: RateLimitConfig) -> Self {
+        Self {
+            config,
+            ..Default::default()
+        }
+    }
+
+    /// Checks if the request of the peer is allowed, a token is taken from
+    /// both the per-peer and global buckets if so. Nothing is taken if the
+    /// request is rejected by either of them.
+    pub(crate) fn check(&mut self, peer: &PeerId, kind: RequestKind, now: Instant) -> bool {
+        if self.config.per_peer.contains_key(&kind) && self.peers.len() > PRUNE_THRESHOLD {
+            self.prune(now);
+        }
+
+        let (config, peers, global) = (&self.config, &mut self.peers, &mut self.global);
+        let mut peer_bucket = config.per_peer.get(&kind).map(|limit| {
+            let bucket = peers
+                .entry(*peer)
+                .or_default()
+                .entry(kind)
+                .or_insert_with(|| TokenBucket::new(limit, now));
+            bucket.refill(limit, now);
+            bucket
+        });
+        let mut global_bucket = config.global.get(&kind).map(|limit| {
+            let bucket = global.entry(kind).or_insert_with(|| TokenBucket::new(limit, now));
+            bucket.refill(limit, now);
+            bucket
+        });
+
+        if !peer_bucket.as_ref().map_or(true, |b| b.has_token()) {
+            self.stats.peer_rejected += 1;
+            return false;
+        }
+        if !global_bucket.as_ref().map_or(true, |b| b.has_token()) {
+            self.stats.global_rejected += 1;
+            return false;
+        }
+
+        for bucket in peer_bucket.iter_mut().chain(global_bucket.iter_mut()) {
+            bucket.tokens -= 1.0;
+        }
+        true
+    }
+
+    // Removes the buckets which have been refilled, they are identical to new ones.
+    fn prune(&mut self, now: Instant) {
+        let per_peer = &self.config.per_peer;
+        self.peers.retain(|_, buckets| {
+            buckets.retain(|kind, b| {
+                per_peer
+                    .get(kind)
+                    .map_or(false, |l| now.saturating_duration_since(b.last) < l.refill_time())
+            });
+            !buckets.is_empty()
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Refills the bucket, then takes a token if any.
+    fn try_take(bucket: &mut TokenBucket, limit: &RateLimit, now: Instant) -> bool {
+        bucket.refill(limit, now);
+        if bucket.has_token() {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    #[test]
+    fn test_token_bucket() {
+        let limit = RateLimit::new(2.0, 3);
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(&limit, now);
+
+        // burst
+        for _ in 0..3 {
+            assert!(try_take(&mut bucket, &limit, now));
+        }
+        assert!(!try_take(&mut bucket, &limit, now));
+
+        // refilled at 2 tokens per second
+        let later = now + Duration::from_millis(500);
+        assert!(try_take(&mut bucket, &limit, later));
+        assert!(!try_take(&mut bucket, &limit, later));
+        let later = later + Duration::from_secs(10);
+        for _ in 0..3 {
+            assert!(try_take(&mut bucket, &limit, later));
+        }
+        assert!(!try_take(&mut bucket, &limit, later));
+    }
+
+    #[test]
+    fn test_rate_limiter() {
+        let config = RateLimitConfig::default()
+            .with_peer_limit(RequestKind::PutValue, RateLimit::new(1.0, 2))
+            .with_global_limit(RequestKind::PutValue, RateLimit::new(1.0, 3));
+        let mut limiter = RateLimiter::new(config);
+        let now = Instant::now();
+        let (a, b) = (PeerId::random(), PeerId::random());
+
+        assert!(limiter.check(&a, RequestKind::PutValue, now));
+        assert!(limiter.check(&a, RequestKind::PutValue, now));
+        assert!(!limiter.check(&a, RequestKind::PutValue, now));
+        assert_eq!(limiter.stats.peer_rejected, 1);
+
+        // the other peer is limited by the global bucket
+        assert!(limiter.check(&b, RequestKind::PutValue, now));
+        assert!(!limiter.check(&b, RequestKind::PutValue, now));
+        assert_eq!(limiter.stats.global_rejected, 1);
+
+        // not limited
+        for _ in 0..10 {
+            assert!(limiter.check(&a, RequestKind::FindNode, now));
+        }
+
+        // the idle buckets are pruned
+        limiter.prune(now + Duration::from_secs(3));
+        assert!(limiter.peers.is_empty());
+    }
+
+    #[test]
+    fn test_rejected_by_global_takes_no_peer_token() {
+        let config = RateLimitConfig::default()
+            .with_peer_limit(RequestKind::PutValue, RateLimit::new(0.01, 2))
+            .with_global_limit(RequestKind::PutValue, RateLimit::new(1.0, 1));
+        let mut limiter = RateLimiter::new(config);
+        let now = Instant::now();
+        let (a, b) = (PeerId::random(), PeerId::random());
+
+        assert!(limiter.check(&a, RequestKind::PutValue, now));
+        // the global bucket is empty, the peer keeps its tokens
+        assert!(!limiter.check(&b, RequestKind::PutValue, now));
+        assert!(!limiter.check(&b, RequestKind::PutValue, now));
+        assert_eq!(limiter.stats.global_rejected, 2);
+
+        let later = now + Duration::from_secs(1);
+        assert!(limiter.check(&b, RequestKind::PutValue, later));
+        assert!(limiter.check(&b, RequestKind::PutValue, later + Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn test_refill_time() {
+        assert_eq!(RateLimit::new(2.0, 4).refill_time(), Duration::from_secs(2));
+        assert_eq!(RateLimit::new(0.0, 4).refill_time(), Duration::from_secs(u64::MAX));
+        assert_eq!(RateLimit::new(1e-300, 4).refill_time(), Duration::from_secs(u64::MAX));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_invalid_rate() {
+        RateLimit::new(f64::NAN, 1);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_invalid_burst() {
+        RateLimit::new(1.0, 0);
+    }
+}
@@ -21,7 +21,9 @@
 
 //! Records and record storage abstraction of the libp2p Kademlia DHT.
 
+pub mod ipns;
 pub mod store;
+pub mod validator;
 
 use bytes::Bytes;
 use std::borrow::Borrow;
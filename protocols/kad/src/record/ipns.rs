@@ -0,0 +1,247 @@
+// Copyright 2020 Netwarps Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! IPNS-style signed mutable records.
+//!
+//! An [`IpnsRecord`] is keyed by `/ipns/<peer-id>` and signed with the keypair
+//! of the peer, so that nobody else can overwrite it. The [`IpnsValidator`]
+//! checks the signature and the validity, and prefers the record with the
+//! highest sequence number.
+
+use prost::Message;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use libp2prs_core::identity::Keypair;
+use libp2prs_core::{PeerId, PublicKey};
+
+use super::validator::{split_key, Validator};
+use super::Key;
+use crate::{ipns_proto as proto, KadError};
+
+type Result<T> = std::result::Result<T, KadError>;
+
+/// The namespace of IPNS records.
+pub const IPNS_NAMESPACE: &str = "ipns";
+
+/// Returns the key of the IPNS record of the peer, i.e. `/ipns/<peer-id>`.
+pub fn ipns_key(peer_id: &PeerId) -> Key {
+    let mut key = format!("/{}/", IPNS_NAMESPACE).into_bytes();
+    key.extend(peer_id.to_bytes());
+    Key::from(key)
+}
+
+/// A signed mutable record, keyed by `/ipns/<peer-id>`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IpnsRecord {
+    /// The value the name points to.
+    pub value: Vec<u8>,
+    /// The sequence number. A record with a higher sequence number supersedes
+    /// the others.
+    pub sequence: u64,
+    /// The end of life of the record, in whole seconds.
+    pub validity: SystemTime,
+    /// The public key of the owner.
+    pub public_key: PublicKey,
+    /// The signature of value, validity and sequence.
+    signature: Vec<u8>,
+}
+
+impl IpnsRecord {
+    /// Creates a record signed with the keypair, which is valid until `validity`.
+    pub fn new(keypair: &Keypair, value: Vec<u8>, sequence: u64, validity: SystemTime) -> Result<Self> {
+        let validity = to_secs(validity);
+        let signature = keypair
+            .sign(&signing_bytes(&value, validity, sequence))
+            .map_err(|_| KadError::InvalidRecord("failed to sign"))?;
+
+        Ok(Self {
+            value,
+            sequence,
+            validity: UNIX_EPOCH + Duration::from_secs(validity),
+            public_key: keypair.public(),
+            signature,
+        })
+    }
+
+    /// Returns the peer Id of the owner.
+    pub fn peer_id(&self) -> PeerId {
+        self.public_key.clone().into_peer_id()
+    }
+
+    /// Returns the key of the record.
+    pub fn key(&self) -> Key {
+        ipns_key(&self.peer_id())
+    }
+
+    /// Verifies the signature, and checks if the record has expired.
+    pub fn verify(&self) -> Result<()> {
+        let msg = signing_bytes(&self.value, to_secs(self.validity), self.sequence);
+        if !self.public_key.verify(&msg, &self.signature) {
+            return Err(KadError::InvalidRecord("invalid signature"));
+        }
+        if self.validity < SystemTime::now() {
+            return Err(KadError::InvalidRecord("record expired"));
+        }
+        Ok(())
+    }
+
+    /// Encodes the record into protobuf.
+    pub fn encode(&self) -> Vec<u8> {
+        let entry = proto::IpnsEntry {
+            value: self.value.clone(),
+            signature: self.signature.clone(),
+            validity: to_secs(self.validity),
+            sequence: self.sequence,
+            pub_key: self.public_key.clone().into_protobuf_encoding(),
+        };
+        let mut buf = Vec::with_capacity(entry.encoded_len());
+        entry.encode(&mut buf).expect("Vec<u8> provides capacity as needed");
+        buf
+    }
+
+    /// Decodes the record from protobuf. The record is not verified.
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        let entry = proto::IpnsEntry::decode(bytes).map_err(|_| KadError::Decode)?;
+        let public_key =
+            PublicKey::from_protobuf_encoding(&entry.pub_key).map_err(|_| KadError::InvalidRecord("invalid public key"))?;
+
+        Ok(Self {
+            value: entry.value,
+            sequence: entry.sequence,
+            validity: UNIX_EPOCH + Duration::from_secs(entry.validity),
+            public_key,
+            signature: entry.signature,
+        })
+    }
+}
+
+fn to_secs(t: SystemTime) -> u64 {
+    t.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+// the data to be signed, the value is followed by the fixed length fields
+fn signing_bytes(value: &[u8], validity: u64, sequence: u64) -> Vec<u8> {
+    let mut buf = b"ipns-signature:".to_vec();
+    buf.extend_from_slice(value);
+    buf.extend_from_slice(&validity.to_be_bytes());
+    buf.extend_from_slice(&sequence.to_be_bytes());
+    buf
+}
+
+/// Validator for IPNS records, keyed by `/ipns/<peer-id>`.
+///
+/// The record must be signed by the peer in the key and not expired. The one
+/// with the highest sequence number is selected, then the one lasts longest.
+#[derive(Clone, Debug, Default)]
+pub struct IpnsValidator;
+
+impl Validator for IpnsValidator {
+    fn validate(&self, key: &Key, value: &[u8]) -> Result<()> {
+        let (ns, rest) = split_key(key).ok_or(KadError::InvalidRecord("invalid key"))?;
+        if ns != IPNS_NAMESPACE {
+            return Err(KadError::InvalidRecord("namespace is not 'ipns'"));
+        }
+
+        let record = IpnsRecord::decode(value)?;
+        if record.peer_id().to_bytes() != rest {
+            return Err(KadError::InvalidRecord("public key does not match the key"));
+        }
+        record.verify()
+    }
+
+    fn select(&self, _key: &Key, values: &[&[u8]]) -> Result<usize> {
+        values
+            .iter()
+            .enumerate()
+            .filter_map(|(i, v)| IpnsRecord::decode(v).ok().map(|r| (i, r)))
+            // the first one wins if they are equally good
+            .max_by(|(ia, a), (ib, b)| {
+                a.sequence
+                    .cmp(&b.sequence)
+                    .then(a.validity.cmp(&b.validity))
+                    .then(ib.cmp(ia))
+            })
+            .map(|(i, _)| i)
+            .ok_or(KadError::InvalidRecord("no valid record"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn later(secs: u64) -> SystemTime {
+        SystemTime::now() + Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn test_ipns_record() {
+        let keypair = Keypair::generate_ed25519();
+        let record = IpnsRecord::new(&keypair, b"hello".to_vec(), 1, later(60)).unwrap();
+        assert_eq!(record.key(), ipns_key(&keypair.public().into_peer_id()));
+
+        let decoded = IpnsRecord::decode(&record.encode()).unwrap();
+        assert_eq!(decoded, record);
+        assert!(decoded.verify().is_ok());
+
+        // tampered
+        let mut tampered = record.clone();
+        tampered.sequence = 2;
+        assert!(tampered.verify().is_err());
+
+        // expired
+        let expired = IpnsRecord::new(&keypair, b"hello".to_vec(), 1, SystemTime::now() - Duration::from_secs(1)).unwrap();
+        assert!(expired.verify().is_err());
+    }
+
+    #[test]
+    fn test_ipns_validator() {
+        let v = IpnsValidator;
+        let keypair = Keypair::generate_ed25519();
+        let key = ipns_key(&keypair.public().into_peer_id());
+
+        let record = IpnsRecord::new(&keypair, b"hello".to_vec(), 1, later(60)).unwrap();
+        assert!(v.validate(&key, &record.encode()).is_ok());
+
+        // signed by someone else
+        let other = IpnsRecord::new(&Keypair::generate_ed25519(), b"hello".to_vec(), 1, later(60)).unwrap();
+        assert!(v.validate(&key, &other.encode()).is_err());
+        // garbage
+        assert!(v.validate(&key, b"hello").is_err());
+    }
+
+    #[test]
+    fn test_ipns_select() {
+        let v = IpnsValidator;
+        let keypair = Keypair::generate_ed25519();
+        let key = ipns_key(&keypair.public().into_peer_id());
+
+        let r1 = IpnsRecord::new(&keypair, b"a".to_vec(), 1, later(600)).unwrap().encode();
+        let r2 = IpnsRecord::new(&keypair, b"b".to_vec(), 2, later(60)).unwrap().encode();
+        let r3 = IpnsRecord::new(&keypair, b"b".to_vec(), 2, later(120)).unwrap().encode();
+
+        assert_eq!(v.select(&key, &[&r1, &r2]).unwrap(), 1);
+        assert_eq!(v.select(&key, &[&r2, &r1]).unwrap(), 0);
+        // same sequence, the longer validity wins
+        assert_eq!(v.select(&key, &[&r2, &r3, &r1]).unwrap(), 1);
+        // equally good
+        assert_eq!(v.select(&key, &[&r2, &r2]).unwrap(), 0);
+    }
+}
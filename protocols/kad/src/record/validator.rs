@@ -0,0 +1,263 @@
+// Copyright 2020 Netwarps Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Record validation and selection.
+//!
+//! A [`Validator`] decides whether a record is acceptable and, given a set of
+//! candidate values for the same key, which one is the best. Validators are
+//! registered per namespace, i.e. the first path component of a key, such as
+//! `/pk/` or `/ipns/`, in a [`NamespacedValidator`].
+//!
+//! Keys without a registered namespace are not checked at all, so that opaque
+//! keys keep working as they did before validators existed.
+
+use fnv::FnvHashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use libp2prs_core::{PeerId, PublicKey};
+
+use super::ipns::{IpnsValidator, IPNS_NAMESPACE};
+use super::Key;
+use crate::KadError;
+
+type Result<T> = std::result::Result<T, KadError>;
+
+/// The namespace of public key records.
+pub const PK_NAMESPACE: &str = "pk";
+
+/// Trait for types that validate and select records of a namespace.
+pub trait Validator: Send + Sync {
+    /// Validates the given record, returning an error if it is invalid, e.g.
+    /// expired or signed by the wrong key.
+    fn validate(&self, key: &Key, value: &[u8]) -> Result<()>;
+
+    /// Selects the best value out of the given values for the same key, and
+    /// returns its index.
+    ///
+    /// All the values have been validated before `select` is called. An index
+    /// out of range is treated as `KadError::InvalidRecord` by the DHT.
+    fn select(&self, key: &Key, values: &[&[u8]]) -> Result<usize>;
+}
+
+/// A validator which dispatches to the validator registered for the namespace
+/// of a key.
+#[derive(Clone)]
+pub struct NamespacedValidator {
+    validators: FnvHashMap<String, Arc<dyn Validator>>,
+}
+
+impl fmt::Debug for NamespacedValidator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NamespacedValidator")
+            .field("namespaces", &self.validators.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl Default for NamespacedValidator {
+    /// Creates a validator with the public key validator registered for `/pk/`,
+    /// and the IPNS validator for `/ipns/`.
+    fn default() -> Self {
+        let mut v = Self::new();
+        v.insert(PK_NAMESPACE, PublicKeyValidator);
+        v.insert(IPNS_NAMESPACE, IpnsValidator);
+        v
+    }
+}
+
+impl NamespacedValidator {
+    /// Creates a validator without any namespace registered.
+    pub fn new() -> Self {
+        Self {
+            validators: Default::default(),
+        }
+    }
+
+    /// Registers a validator for the given namespace, replacing the old one if any.
+    ///
+    /// The namespace is given without slashes, e.g. "ipns" for `/ipns/<peer-id>`.
+    pub fn insert<V: Validator + 'static>(&mut self, namespace: &str, validator: V) {
+        self.validators.insert(namespace.to_string(), Arc::new(validator));
+    }
+
+    /// Returns the validator registered for the namespace of the key.
+    fn validator(&self, key: &Key) -> Option<&Arc<dyn Validator>> {
+        split_key(key).and_then(|(ns, _)| self.validators.get(ns))
+    }
+}
+
+impl Validator for NamespacedValidator {
+    fn validate(&self, key: &Key, value: &[u8]) -> Result<()> {
+        match self.validator(key) {
+            Some(v) => v.validate(key, value),
+            None => Ok(()),
+        }
+    }
+
+    fn select(&self, key: &Key, values: &[&[u8]]) -> Result<usize> {
+        if values.is_empty() {
+            return Err(KadError::NotFound);
+        }
+        match self.validator(key) {
+            // the index selected by a custom validator might be out of range
+            Some(v) => match v.select(key, values)? {
+                best if best < values.len() => Ok(best),
+                _ => Err(KadError::InvalidRecord("selected index out of range")),
+            },
+            None => Ok(0),
+        }
+    }
+}
+
+/// Validator for public key records, keyed by `/pk/<peer-id>`.
+///
+/// The value must be a protobuf encoded public key whose peer Id matches the
+/// one in the key.
+#[derive(Clone, Debug, Default)]
+pub struct PublicKeyValidator;
+
+impl Validator for PublicKeyValidator {
+    fn validate(&self, key: &Key, value: &[u8]) -> Result<()> {
+        let (ns, rest) = split_key(key).ok_or(KadError::InvalidRecord("invalid key"))?;
+        if ns != PK_NAMESPACE {
+            return Err(KadError::InvalidRecord("namespace is not 'pk'"));
+        }
+
+        let pk = PublicKey::from_protobuf_encoding(value).map_err(|_| KadError::InvalidRecord("invalid public key"))?;
+        if PeerId::from_public_key(pk).to_bytes() != rest {
+            return Err(KadError::InvalidRecord("public key does not match the key"));
+        }
+        Ok(())
+    }
+
+    fn select(&self, _key: &Key, _values: &[&[u8]]) -> Result<usize> {
+        // all valid public keys of a peer are identical
+        Ok(0)
+    }
+}
+
+/// Splits a key in the form of `/namespace/rest` into its namespace and the rest.
+///
+/// Returns `None` if the key is not in this form.
+pub fn split_key(key: &Key) -> Option<(&str, &[u8])> {
+    let key = key.as_ref();
+    if key.first() != Some(&b'/') {
+        return None;
+    }
+    let key = &key[1..];
+    let pos = key.iter().position(|b| *b == b'/')?;
+    let ns = std::str::from_utf8(&key[..pos]).ok()?;
+    let rest = &key[pos + 1..];
+    if ns.is_empty() || rest.is_empty() {
+        return None;
+    }
+    Some((ns, rest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libp2prs_core::identity::Keypair;
+
+    fn pk_record() -> (Key, Vec<u8>) {
+        let pk = Keypair::generate_ed25519().public();
+        let mut key = b"/pk/".to_vec();
+        key.extend(pk.clone().into_peer_id().to_bytes());
+        (Key::from(key), pk.into_protobuf_encoding())
+    }
+
+    #[test]
+    fn test_split_key() {
+        assert_eq!(split_key(&Key::new(b"/pk/abc")), Some(("pk", &b"abc"[..])));
+        assert_eq!(split_key(&Key::new(b"/ipns/a/b")), Some(("ipns", &b"a/b"[..])));
+        assert_eq!(split_key(&Key::new(b"pk/abc")), None);
+        assert_eq!(split_key(&Key::new(b"/pk/")), None);
+        assert_eq!(split_key(&Key::new(b"//abc")), None);
+        assert_eq!(split_key(&Key::new(b"/pk")), None);
+    }
+
+    #[test]
+    fn test_public_key_validator() {
+        let v = NamespacedValidator::default();
+
+        let (key, value) = pk_record();
+        assert!(v.validate(&key, &value).is_ok());
+
+        // public key of another peer
+        let (_, other) = pk_record();
+        assert!(v.validate(&key, &other).is_err());
+        // garbage
+        assert!(v.validate(&key, b"hello").is_err());
+    }
+
+    #[test]
+    fn test_unknown_namespace() {
+        let v = NamespacedValidator::default();
+
+        let key = Key::new(b"/v/hello");
+        assert!(v.validate(&key, b"world").is_ok());
+        assert_eq!(v.select(&key, &[b"a", b"b"]).unwrap(), 0);
+        assert!(v.select(&key, &[]).is_err());
+    }
+
+    #[test]
+    fn test_custom_select() {
+        struct Longest;
+        impl Validator for Longest {
+            fn validate(&self, _key: &Key, value: &[u8]) -> Result<()> {
+                if value.is_empty() {
+                    Err(KadError::InvalidRecord("empty"))
+                } else {
+                    Ok(())
+                }
+            }
+            fn select(&self, _key: &Key, values: &[&[u8]]) -> Result<usize> {
+                Ok(values.iter().enumerate().max_by_key(|(_, v)| v.len()).map(|(i, _)| i).unwrap())
+            }
+        }
+
+        let mut v = NamespacedValidator::new();
+        v.insert("l", Longest);
+
+        let key = Key::new(b"/l/x");
+        assert!(v.validate(&key, b"").is_err());
+        assert_eq!(v.select(&key, &[b"a", b"abc", b"ab"]).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_select_out_of_range() {
+        struct Broken;
+        impl Validator for Broken {
+            fn validate(&self, _key: &Key, _value: &[u8]) -> Result<()> {
+                Ok(())
+            }
+            fn select(&self, _key: &Key, values: &[&[u8]]) -> Result<usize> {
+                Ok(values.len())
+            }
+        }
+
+        let mut v = NamespacedValidator::new();
+        v.insert("b", Broken);
+
+        let key = Key::new(b"/b/x");
+        assert!(matches!(v.select(&key, &[b"a", b"b"]), Err(KadError::InvalidRecord(_))));
+    }
+}
@@ -0,0 +1,247 @@
+// Copyright 2020 Netwarps Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Reproviding of the provider records of the local node.
+//!
+//! The provider records announced by the local node expire in the DHT, hence
+//! they have to be announced again periodically. Which keys are reprovided is
+//! decided by a [`ReproviderStrategy`].
+//!
+//! Announcing a key requires a lookup for the closest peers to the key, which is
+//! expensive. Keys are therefore sorted in the keyspace, and consecutive keys
+//! which are closer to each other than to the closest peers found for the first
+//! one are announced to the same peers, i.e. a batch of keys costs one lookup.
+//! Besides, the batches of a round are spread across the reprovide interval to
+//! avoid a burst of queries.
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use libp2prs_core::PeerId;
+use libp2prs_runtime::task;
+
+use crate::{kbucket, record, Control, KadError};
+
+/// A callback to decide if a key is pinned.
+pub type KeyFilter = Arc<dyn Fn(&record::Key) -> bool + Send + Sync>;
+
+/// A callback to list the keys to be reprovided.
+pub type KeySource = Arc<dyn Fn() -> Vec<record::Key> + Send + Sync>;
+
+/// The strategy to decide which keys are reprovided.
+#[derive(Clone)]
+pub enum ReproviderStrategy {
+    /// Reprovides all the keys provided by the local node.
+    All,
+    /// Reprovides the keys provided by the local node which are pinned, as told
+    /// by the callback.
+    Pinned(KeyFilter),
+    /// Reprovides the keys returned by the callback, e.g. the roots of the pinned
+    /// DAGs, regardless of the keys provided by the local node.
+    Roots(KeySource),
+}
+
+impl Default for ReproviderStrategy {
+    fn default() -> Self {
+        ReproviderStrategy::All
+    }
+}
+
+impl fmt::Debug for ReproviderStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReproviderStrategy::All => f.write_str("All"),
+            ReproviderStrategy::Pinned(_) => f.write_str("Pinned"),
+            ReproviderStrategy::Roots(_) => f.write_str("Roots"),
+        }
+    }
+}
+
+impl ReproviderStrategy {
+    /// Returns the keys to be reprovided, out of the keys provided by the local node.
+    pub(crate) fn keys<I: Iterator<Item = record::Key>>(&self, provided: I) -> Vec<record::Key> {
+        match self {
+            ReproviderStrategy::All => provided.collect(),
+            ReproviderStrategy::Pinned(pinned) => provided.filter(|k| pinned(k)).collect(),
+            ReproviderStrategy::Roots(roots) => roots(),
+        }
+    }
+}
+
+/// The progress of reproviding.
+#[derive(Debug, Clone, Default)]
+pub struct ReproviderStats {
+    /// The number of rounds completed.
+    pub rounds: usize,
+    /// Whether a round is ongoing.
+    pub running: bool,
+    /// The number of keys in the current or the last round.
+    pub total: usize,
+    /// The number of keys which have been announced in the current or the last round.
+    pub provided: usize,
+    /// The number of keys which failed to be announced in the current or the last round.
+    pub failed: usize,
+    /// The number of lookups for the closest peers in the current or the last round.
+    pub lookups: usize,
+    /// When the current or the last round started.
+    pub started: Option<SystemTime>,
+    /// When the last round finished.
+    pub finished: Option<SystemTime>,
+}
+
+/// Sorts the keys by their positions in the keyspace, relative to the local key.
+///
+/// Keys sharing a longer common prefix end up next to each other.
+pub(crate) fn sort_keys(keys: &mut Vec<record::Key>, local: &kbucket::Key<PeerId>) {
+    keys.sort_by_cached_key(|k| local.distance(&kbucket::Key::new(k.clone())));
+    keys.dedup();
+}
+
+/// Returns how many of the sorted keys, starting from the first one, can be
+/// announced to the closest peers of the first key.
+///
+/// A key is covered if it shares a longer common prefix with the first key than
+/// the farthest peer does, i.e. it falls into the region of the keyspace that
+/// is surrounded by the peers. At least the first key is covered.
+pub(crate) fn batch_len(keys: &[record::Key], peers: &[PeerId]) -> usize {
+    let first = match keys.first() {
+        Some(k) => kbucket::Key::new(k.clone()),
+        None => return 0,
+    };
+    let farthest = peers.iter().filter_map(|p| first.distance(&kbucket::Key::from(*p)).ilog2()).max();
+    let farthest = match farthest {
+        Some(d) => d,
+        None => return 1,
+    };
+
+    1 + keys[1..]
+        .iter()
+        .take_while(|k| first.distance(&kbucket::Key::new((*k).clone())).ilog2() < Some(farthest))
+        .count()
+}
+
+/// Announces the sorted keys in batches of at most `batch_size` keys, and
+/// updates the progress in `stats`.
+///
+/// If `spread` is specified, the batches are spread evenly across the duration.
+/// Returns the number of keys announced.
+pub(crate) async fn provide_keys(
+    mut control: Control,
+    keys: Vec<record::Key>,
+    batch_size: usize,
+    spread: Option<Duration>,
+    stats: Arc<Mutex<ReproviderStats>>,
+) -> usize {
+    let total = keys.len();
+    let started = Instant::now();
+    {
+        let mut stats = stats.lock().unwrap();
+        stats.running = true;
+        stats.total = total;
+        stats.provided = 0;
+        stats.failed = 0;
+        stats.lookups = 0;
+        stats.started = Some(SystemTime::now());
+    }
+
+    let mut done = 0;
+    let mut provided = 0;
+    while done < total {
+        let end = std::cmp::min(done + batch_size.max(1), total);
+        let (covered, succeeded) = match control.provide_batch(keys[done..end].to_vec()).await {
+            Ok(r) => r,
+            Err(KadError::Internal) => {
+                log::info!("Kad main loop closed, reproviding aborted");
+                break;
+            }
+            Err(e) => {
+                log::debug!("failed to provide {:?}: {:?}", keys[done], e);
+                (1, 0)
+            }
+        };
+        done += covered;
+        provided += succeeded;
+
+        {
+            let mut stats = stats.lock().unwrap();
+            stats.provided += succeeded;
+            stats.failed += covered - succeeded;
+            stats.lookups += 1;
+        }
+
+        if let Some(spread) = spread {
+            let due = started + spread.mul_f64(done as f64 / total as f64);
+            let now = Instant::now();
+            if due > now {
+                task::sleep(due - now).await;
+            }
+        }
+    }
+
+    let mut stats = stats.lock().unwrap();
+    stats.running = false;
+    stats.rounds += 1;
+    stats.finished = Some(SystemTime::now());
+    log::info!("{} of {} keys provided, {} lookups", stats.provided, total, stats.lookups);
+
+    provided
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_batch_len() {
+        let local = kbucket::Key::from(PeerId::random());
+        let mut keys = (0..1000u32).map(|i| record::Key::new(&i.to_be_bytes())).collect::<Vec<_>>();
+        sort_keys(&mut keys, &local);
+
+        assert_eq!(batch_len(&[], &[]), 0);
+        assert_eq!(batch_len(&keys, &[]), 1);
+
+        // keys sharing a longer prefix with the first key than the farthest peer are covered
+        let first = kbucket::Key::new(keys[0].clone());
+        let peers = (0..20).map(|_| PeerId::random()).collect::<Vec<_>>();
+        let farthest = peers.iter().map(|p| first.distance(&kbucket::Key::from(*p))).max().unwrap();
+        let n = batch_len(&keys, &peers);
+        assert!(n >= 1);
+        for k in &keys[1..n] {
+            assert!(first.distance(&kbucket::Key::new(k.clone())).ilog2() < farthest.ilog2());
+        }
+        if n < keys.len() {
+            assert!(first.distance(&kbucket::Key::new(keys[n].clone())).ilog2() >= farthest.ilog2());
+        }
+    }
+
+    #[test]
+    fn test_strategy() {
+        let provided = || (0..10u8).map(|i| record::Key::new(&[i]));
+
+        assert_eq!(ReproviderStrategy::All.keys(provided()).len(), 10);
+
+        let pinned = ReproviderStrategy::Pinned(Arc::new(|k: &record::Key| k.as_ref()[0] % 2 == 0));
+        assert_eq!(pinned.keys(provided()).len(), 5);
+
+        let roots = ReproviderStrategy::Roots(Arc::new(|| vec![record::Key::new(&[42u8])]));
+        assert_eq!(roots.keys(provided()), vec![record::Key::new(&[42u8])]);
+    }
+}
@@ -0,0 +1,129 @@
+// Copyright 2020 Netwarps Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Snapshot of the routing table.
+//!
+//! The peers in the routing table, along with their addresses and the last
+//! time we talked to them, are saved into a JSON file, so that they can be
+//! reloaded when Kademlia restarts, instead of starting with an empty routing
+//! table.
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use libp2prs_core::{Multiaddr, PeerId};
+
+/// A peer in the snapshot of the routing table.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct SnapshotPeer {
+    pub(crate) id: PeerId,
+    pub(crate) addrs: Vec<Multiaddr>,
+    /// The last time we talked to the peer.
+    pub(crate) last_seen: Option<SystemTime>,
+}
+
+/// The serialized form of `SnapshotPeer`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct PeerSaved {
+    id: String,
+    addrs: Vec<Multiaddr>,
+    /// Seconds since the UNIX epoch.
+    last_seen: Option<u64>,
+}
+
+/// Saves the peers into the file.
+///
+/// The snapshot is written to a temporary file first and then renamed, so that
+/// the old snapshot is not corrupted by a failed write.
+pub(crate) fn save_snapshot(path: &Path, peers: Vec<SnapshotPeer>) -> io::Result<()> {
+    let saved = peers
+        .into_iter()
+        .map(|p| PeerSaved {
+            id: p.id.to_string(),
+            addrs: p.addrs,
+            last_seen: p.last_seen.and_then(|t| t.duration_since(UNIX_EPOCH).ok()).map(|d| d.as_secs()),
+        })
+        .collect::<Vec<_>>();
+    let json = serde_json::to_vec(&saved)?;
+
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, json)?;
+    fs::rename(&tmp, path)
+}
+
+/// Loads the peers from the file. An empty list is returned if the file doesn't exist.
+pub(crate) fn load_snapshot(path: &Path) -> io::Result<Vec<SnapshotPeer>> {
+    let buf = match fs::read(path) {
+        Ok(buf) => buf,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e),
+    };
+    let saved: Vec<PeerSaved> = serde_json::from_slice(&buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+
+    saved
+        .into_iter()
+        .map(|p| {
+            let id = PeerId::from_str(&p.id).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            Ok(SnapshotPeer {
+                id,
+                addrs: p.addrs,
+                last_seen: p.last_seen.map(|s| UNIX_EPOCH + Duration::from_secs(s)),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot_save_load() {
+        let path = std::env::temp_dir().join(format!("kad-snapshot-{}.json", PeerId::random()));
+
+        // no snapshot yet
+        assert!(load_snapshot(&path).unwrap().is_empty());
+
+        let peers = vec![
+            SnapshotPeer {
+                id: PeerId::random(),
+                addrs: vec!["/ip4/127.0.0.1/tcp/4001".parse().unwrap()],
+                last_seen: Some(UNIX_EPOCH + Duration::from_secs(1_600_000_000)),
+            },
+            SnapshotPeer {
+                id: PeerId::random(),
+                addrs: vec![],
+                last_seen: None,
+            },
+        ];
+        save_snapshot(&path, peers.clone()).unwrap();
+        assert_eq!(load_snapshot(&path).unwrap(), peers);
+
+        // garbage
+        fs::write(&path, b"hello").unwrap();
+        assert!(load_snapshot(&path).is_err());
+
+        let _ = fs::remove_file(&path);
+    }
+}
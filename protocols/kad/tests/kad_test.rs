@@ -18,13 +18,20 @@
 // FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
 // DEALINGS IN THE SOFTWARE.
 
+use futures::StreamExt;
 use libp2prs_core::identity::Keypair;
 use libp2prs_core::multiaddr::protocol::Protocol;
 use libp2prs_core::transport::memory::MemoryTransport;
 use libp2prs_core::transport::upgrade::TransportUpgrade;
 use libp2prs_core::{Multiaddr, PeerId};
+use libp2prs_kad::crawler::{Crawler, CrawlerConfig};
+use libp2prs_kad::discovery::DiscoveryConfig;
+use libp2prs_kad::events::{KadEvent, QueryKind, QueryOutcome, RequestKind};
+use libp2prs_kad::kad::{Kademlia, KademliaConfig, KademliaMode};
+use libp2prs_kad::ratelimit::{RateLimit, RateLimitConfig};
+use libp2prs_kad::record::ipns::{ipns_key, IpnsRecord};
 use libp2prs_kad::store::MemoryStore;
-use libp2prs_kad::{kad::Kademlia, Control as kad_control};
+use libp2prs_kad::{Control as kad_control, Quorum};
 use libp2prs_plaintext as plaintext;
 use libp2prs_runtime::task;
 use libp2prs_swarm::identify::IdentifyConfig;
@@ -32,9 +39,14 @@ use libp2prs_swarm::{Control as swarm_control, Swarm};
 use libp2prs_yamux as yamux;
 use quickcheck::{QuickCheck, TestResult};
 use rand::random;
-use std::time::Duration;
+use std::num::NonZeroUsize;
+use std::time::{Duration, SystemTime};
 
 fn setup_kad(keys: Keypair, listen_addr: Multiaddr) -> (swarm_control, kad_control) {
+    setup_kad_with_config(keys, listen_addr, KademliaConfig::default())
+}
+
+fn setup_kad_with_config(keys: Keypair, listen_addr: Multiaddr, config: KademliaConfig) -> (swarm_control, kad_control) {
     let sec = plaintext::PlainTextConfig::new(keys.clone());
     let mux = yamux::Config::new();
     let tu = TransportUpgrade::new(MemoryTransport::default(), mux, sec);
@@ -47,7 +59,7 @@ fn setup_kad(keys: Keypair, listen_addr: Multiaddr) -> (swarm_control, kad_contr
 
     // start kad protocol
     let store = MemoryStore::new(*swarm.local_peer_id());
-    let kad = Kademlia::new(*swarm.local_peer_id(), store);
+    let kad = Kademlia::with_config(*swarm.local_peer_id(), store, config);
     let kad_ctrl = kad.control();
 
     // register handler to swarm
@@ -156,6 +168,220 @@ fn test_simple_value_get_set() {
     QuickCheck::new().tests(10).quickcheck(prop as fn() -> _);
 }
 
+#[test]
+fn test_quorum_value_get_set() {
+    fn prop() -> TestResult {
+        task::block_on(async {
+            let infos = setup_kads(3);
+            let mut node0 = infos.get(0).expect("get peer info").clone();
+            let mut node1 = infos.get(1).expect("get peer info").clone();
+            let mut node2 = infos.get(2).expect("get peer info").clone();
+
+            connect(&mut node0, &mut node1).await;
+            connect(&mut node1, &mut node2).await;
+            connect(&mut node0, &mut node2).await;
+
+            // wait for identify result
+            task::sleep(Duration::from_millis(200)).await;
+
+            let key = b"/v/quorum".to_vec();
+            let value = b"world".to_vec();
+            let stored = node0
+                .kad_ctrl
+                .put_value_with_quorum(key.clone(), value.clone(), Quorum::All)
+                .await
+                .expect("put value");
+            assert_eq!(stored, 2);
+
+            let records = node2
+                .kad_ctrl
+                .get_value_with_quorum(key.clone(), Quorum::One)
+                .await
+                .expect("get value");
+            assert_eq!(records.len(), 1);
+            assert_eq!(records[0].record.value, value);
+
+            TestResult::passed()
+        })
+    }
+    QuickCheck::new().tests(5).quickcheck(prop as fn() -> _);
+}
+
+#[test]
+fn test_client_mode() {
+    fn prop() -> TestResult {
+        task::block_on(async {
+            let base_port = 1 + random::<u64>();
+            let mut nodes = vec![];
+            for (i, mode) in [KademliaMode::Server, KademliaMode::Client].iter().enumerate() {
+                let key = Keypair::generate_ed25519();
+                let pid = key.public().into_peer_id();
+                let addr: Multiaddr = Protocol::Memory(base_port + i as u64).into();
+                let config = KademliaConfig::default().with_mode(*mode);
+                let (swarm_ctrl, kad_ctrl) = setup_kad_with_config(key, addr.clone(), config);
+                nodes.push(PeerInfo {
+                    pid,
+                    addr,
+                    swarm_ctrl,
+                    kad_ctrl,
+                });
+            }
+            let mut client = nodes.pop().expect("client");
+            let mut server = nodes.pop().expect("server");
+
+            connect(&mut client, &mut server).await;
+
+            // wait for identify result
+            task::sleep(Duration::from_millis(200)).await;
+
+            // client doesn't advertise Kad protocol
+            let protocols = server.swarm_ctrl.get_protocols(&client.pid).expect("identified");
+            assert!(!protocols.iter().any(|p| p.as_bytes() == b"/ipfs/kad/1.0.0"));
+
+            // client is able to use server
+            let stored = client
+                .kad_ctrl
+                .put_value_with_quorum(b"/v/client".to_vec(), b"world".to_vec(), Quorum::One)
+                .await
+                .expect("put value");
+            assert_eq!(stored, 1);
+
+            // but client is not added to the routing table of server
+            let entries = server.kad_ctrl.dump_kbuckets().await.expect("dump kbuckets");
+            assert!(entries.iter().all(|b| b.bucket.iter().all(|n| n.id != client.pid)));
+            let entries = client.kad_ctrl.dump_kbuckets().await.expect("dump kbuckets");
+            assert!(entries.iter().any(|b| b.bucket.iter().any(|n| n.id == server.pid)));
+
+            TestResult::passed()
+        })
+    }
+    QuickCheck::new().tests(5).quickcheck(prop as fn() -> _);
+}
+
+#[test]
+fn test_rate_limit() {
+    fn prop() -> TestResult {
+        task::block_on(async {
+            let base_port = 1 + random::<u64>();
+            let rate_limit = RateLimitConfig::default().with_peer_limit(RequestKind::PutValue, RateLimit::new(0.01, 1));
+            let mut nodes = vec![];
+            for (i, config) in [KademliaConfig::default(), KademliaConfig::default().with_rate_limit(rate_limit)]
+                .iter()
+                .enumerate()
+            {
+                let key = Keypair::generate_ed25519();
+                let pid = key.public().into_peer_id();
+                let addr: Multiaddr = Protocol::Memory(base_port + i as u64).into();
+                let (swarm_ctrl, kad_ctrl) = setup_kad_with_config(key, addr.clone(), config.clone());
+                nodes.push(PeerInfo {
+                    pid,
+                    addr,
+                    swarm_ctrl,
+                    kad_ctrl,
+                });
+            }
+            let mut server = nodes.pop().expect("server");
+            let mut client = nodes.pop().expect("client");
+
+            connect(&mut client, &mut server).await;
+
+            // wait for identify result
+            task::sleep(Duration::from_millis(200)).await;
+
+            // the first one is allowed, the second one is rejected
+            let r = client
+                .kad_ctrl
+                .put_value_with_quorum(b"/v/first".to_vec(), b"world".to_vec(), Quorum::One)
+                .await;
+            assert_eq!(r.ok(), Some(1));
+            let r = client
+                .kad_ctrl
+                .put_value_with_quorum(b"/v/second".to_vec(), b"world".to_vec(), Quorum::One)
+                .await;
+            assert!(r.is_err());
+
+            // other requests are not limited
+            assert!(client.kad_ctrl.find_peer(&server.pid).await.is_ok());
+
+            let stats = server.kad_ctrl.dump_statistics().await.expect("dump statistics");
+            assert_eq!(stats.rate_limit.peer_rejected, 1);
+
+            TestResult::passed()
+        })
+    }
+    QuickCheck::new().tests(3).quickcheck(prop as fn() -> _);
+}
+
+#[test]
+fn test_streaming_queries() {
+    fn prop() -> TestResult {
+        task::block_on(async {
+            let infos = setup_kads(3);
+            let mut node0 = infos.get(0).expect("get peer info").clone();
+            let mut node1 = infos.get(1).expect("get peer info").clone();
+            let mut node2 = infos.get(2).expect("get peer info").clone();
+
+            connect(&mut node0, &mut node1).await;
+            connect(&mut node1, &mut node2).await;
+
+            // wait for identify result
+            task::sleep(Duration::from_millis(200)).await;
+
+            let key = b"/v/stream".to_vec();
+            let value = b"world".to_vec();
+            node0.kad_ctrl.provide(key.clone()).await.expect("provide");
+            node0.kad_ctrl.put_value(key.clone(), value.clone()).await.expect("put value");
+
+            // take the first item and drop the stream, which cancels the query
+            let mut providers = node2.kad_ctrl.find_providers_stream(key.clone(), 0).await.expect("find providers");
+            let provider = providers.next().await.expect("provider found");
+            assert_eq!(provider.node_id, node0.pid);
+            drop(providers);
+
+            let mut records = node2.kad_ctrl.get_value_stream(key.clone(), Quorum::All).await.expect("get value");
+            let record = records.next().await.expect("record found");
+            assert_eq!(record.record.value, value);
+            drop(records);
+
+            TestResult::passed()
+        })
+    }
+    QuickCheck::new().tests(5).quickcheck(prop as fn() -> _);
+}
+
+#[test]
+fn test_cancelled_query_stats() {
+    fn prop() -> TestResult {
+        task::block_on(async {
+            let infos = setup_kads(2);
+            let mut node0 = infos.get(0).expect("get peer info").clone();
+            let mut node1 = infos.get(1).expect("get peer info").clone();
+
+            connect(&mut node0, &mut node1).await;
+
+            // wait for identify result
+            task::sleep(Duration::from_millis(200)).await;
+
+            // drop the stream before the query gets any chance to complete
+            let providers = node1
+                .kad_ctrl
+                .find_providers_stream(b"/v/cancel".to_vec(), 0)
+                .await
+                .expect("find providers");
+            drop(providers);
+
+            task::sleep(Duration::from_millis(200)).await;
+
+            let stats = node1.kad_ctrl.dump_statistics().await.expect("dump statistics");
+            assert_eq!(stats.query.iter_query_cancelled(), 1);
+            assert_eq!(stats.query.iter_query_running(), 0);
+
+            TestResult::passed()
+        })
+    }
+    QuickCheck::new().tests(3).quickcheck(prop as fn() -> _);
+}
+
 #[test]
 fn test_simple_provides() {
     fn prop() -> TestResult {
@@ -202,6 +428,250 @@ fn test_simple_provides() {
     QuickCheck::new().tests(10).quickcheck(prop as fn() -> _);
 }
 
+#[test]
+fn test_provide_many() {
+    fn prop() -> TestResult {
+        task::block_on(async {
+            let infos = setup_kads(4);
+            let mut node0 = infos.get(0).expect("get peer info").clone();
+            let mut node1 = infos.get(1).expect("get peer info").clone();
+            let mut node2 = infos.get(2).expect("get peer info").clone();
+            let mut node3 = infos.get(3).expect("get peer info").clone();
+
+            connect(&mut node0, &mut node1).await;
+            connect(&mut node1, &mut node2).await;
+            connect(&mut node2, &mut node3).await;
+
+            // wait for identify result
+            task::sleep(Duration::from_millis(200)).await;
+
+            let keys = (0..50u32).map(|i| format!("key-{}", i).into_bytes()).collect::<Vec<_>>();
+            let n = node0.kad_ctrl.provide_many(keys.clone()).await.expect("provide many");
+            assert_eq!(n, keys.len());
+
+            for key in keys.iter().take(5).cloned() {
+                let providers = node3.kad_ctrl.find_providers(key, 1).await.expect("can't find provider");
+                assert_eq!(providers.first().map(|p| p.node_id), Some(node0.pid));
+            }
+
+            TestResult::passed()
+        })
+    }
+    QuickCheck::new().tests(3).quickcheck(prop as fn() -> _);
+}
+
+#[test]
+fn test_event_stream() {
+    fn prop() -> TestResult {
+        task::block_on(async {
+            let infos = setup_kads(2);
+            let mut node0 = infos.get(0).expect("get peer info").clone();
+            let mut node1 = infos.get(1).expect("get peer info").clone();
+            let mut events0 = node0.kad_ctrl.subscribe().await.expect("subscribe");
+            let mut events1 = node1.kad_ctrl.subscribe().await.expect("subscribe");
+
+            connect(&mut node0, &mut node1).await;
+
+            // wait for identify result
+            task::sleep(Duration::from_millis(200)).await;
+
+            let key = b"/v/hello".to_vec();
+            let _ = node0.kad_ctrl.put_value(key.clone(), b"world".to_vec()).await;
+
+            // node0 stored the record locally, added node1 to its routing table, and ran a query for the key
+            let mut stored = false;
+            let mut added = false;
+            let mut finished = None;
+            while let Some(evt) = events0.next().await {
+                match evt {
+                    KadEvent::RecordStored { key: k, source } => {
+                        assert_eq!(k.to_vec(), key);
+                        assert_eq!(source, node0.pid);
+                        stored = true;
+                    }
+                    KadEvent::PeerAdded { peer, .. } if peer == node1.pid => added = true,
+                    KadEvent::QueryFinished(report) if report.kind == QueryKind::GetClosestPeers => {
+                        finished = Some(report);
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+            let report = finished.expect("query finished");
+            assert!(stored);
+            assert!(added);
+            assert_eq!(report.key.to_vec(), key);
+            assert_eq!(report.outcome, QueryOutcome::Succeeded);
+            assert!(report.success >= 1);
+
+            // node1 received the PutValue request, and stored the record
+            let mut requested = false;
+            while let Some(evt) = events1.next().await {
+                match evt {
+                    KadEvent::InboundRequest { peer, request } if request == RequestKind::PutValue => {
+                        assert_eq!(peer, node0.pid);
+                        requested = true;
+                    }
+                    KadEvent::RecordStored { key: k, source } => {
+                        assert_eq!(k.to_vec(), key);
+                        assert_eq!(source, node0.pid);
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+            assert!(requested);
+
+            TestResult::passed()
+        })
+    }
+    QuickCheck::new().tests(3).quickcheck(prop as fn() -> _);
+}
+
+#[test]
+fn test_disjoint_path_report() {
+    fn prop() -> TestResult {
+        task::block_on(async {
+            let mut infos = setup_kads(3);
+
+            // node0 runs its queries on two disjoint paths
+            let key = Keypair::generate_ed25519();
+            let pid = key.public().into_peer_id();
+            let addr: Multiaddr = Protocol::Memory(1 + random::<u64>()).into();
+            let config = KademliaConfig::default().with_disjoint_paths(NonZeroUsize::new(2));
+            let (swarm_ctrl, kad_ctrl) = setup_kad_with_config(key, addr.clone(), config);
+            let mut node0 = PeerInfo {
+                pid,
+                addr,
+                swarm_ctrl,
+                kad_ctrl,
+            };
+            let mut events0 = node0.kad_ctrl.subscribe().await.expect("subscribe");
+
+            for node in infos.iter_mut() {
+                connect(&mut node0, node).await;
+            }
+
+            // wait for identify result
+            task::sleep(Duration::from_millis(200)).await;
+
+            let unknown = PeerId::random();
+            assert!(node0.kad_ctrl.find_peer(&unknown).await.is_err());
+
+            let mut finished = None;
+            while let Some(evt) = events0.next().await {
+                if let KadEvent::QueryFinished(report) = evt {
+                    if report.kind == QueryKind::FindPeer {
+                        finished = Some(report);
+                        break;
+                    }
+                }
+            }
+            let report = finished.expect("query finished");
+            assert_eq!(report.paths.len(), 2);
+            assert_eq!(report.paths.iter().map(|p| p.requests).sum::<u32>(), report.requests);
+            assert_eq!(report.paths.iter().map(|p| p.success).sum::<u32>(), report.success);
+            assert_eq!(report.paths.iter().map(|p| p.failure).sum::<u32>(), report.failure);
+            assert!(report.paths.iter().all(|p| p.requests > 0));
+
+            TestResult::passed()
+        })
+    }
+    QuickCheck::new().tests(3).quickcheck(prop as fn() -> _);
+}
+
+#[test]
+fn test_crawler() {
+    fn prop() -> TestResult {
+        task::block_on(async {
+            let infos = setup_kads(5);
+            let mut node0 = infos.get(0).expect("get peer info").clone();
+            let mut node1 = infos.get(1).expect("get peer info").clone();
+            let mut node2 = infos.get(2).expect("get peer info").clone();
+            let mut node3 = infos.get(3).expect("get peer info").clone();
+            let node4 = infos.get(4).expect("get peer info").clone();
+
+            connect(&mut node0, &mut node1).await;
+            connect(&mut node1, &mut node2).await;
+            connect(&mut node2, &mut node3).await;
+
+            // wait for identify result
+            task::sleep(Duration::from_millis(200)).await;
+
+            // node4 knows nothing but node0
+            let config = CrawlerConfig::default().with_timeout(Duration::from_secs(5));
+            let mut crawler = Crawler::new(node4.swarm_ctrl.clone(), node4.kad_ctrl.clone(), config);
+            let result = crawler.crawl(vec![(node0.pid, vec![node0.addr.clone()])]).await;
+
+            let mut found = result.peers.iter().map(|p| p.id).collect::<Vec<_>>();
+            let mut expected = vec![node0.pid, node1.pid, node2.pid, node3.pid];
+            found.sort();
+            expected.sort();
+            assert_eq!(found, expected);
+            assert_eq!(result.num_reachable(), 4);
+            assert!(result.peers.iter().all(|p| p.agent_version.is_some()));
+
+            let node1_crawled = result.peers.iter().find(|p| p.id == node1.pid).expect("node1 crawled");
+            assert!(node1_crawled.neighbors.contains(&node0.pid));
+            assert!(node1_crawled.neighbors.contains(&node2.pid));
+
+            TestResult::passed()
+        })
+    }
+    QuickCheck::new().tests(3).quickcheck(prop as fn() -> _);
+}
+
+#[test]
+fn test_random_walk_discovery() {
+    fn prop() -> TestResult {
+        task::block_on(async {
+            let infos = setup_kads(4);
+            let mut node0 = infos.get(0).expect("get peer info").clone();
+            let mut node1 = infos.get(1).expect("get peer info").clone();
+            let mut node2 = infos.get(2).expect("get peer info").clone();
+            let mut node3 = infos.get(3).expect("get peer info").clone();
+
+            connect(&mut node0, &mut node1).await;
+            connect(&mut node1, &mut node2).await;
+            connect(&mut node2, &mut node3).await;
+
+            // node4 runs discovery, and knows nothing but node0
+            let key = Keypair::generate_ed25519();
+            let pid = key.public().into_peer_id();
+            let addr: Multiaddr = Protocol::Memory(1 + random::<u64>()).into();
+            let discovery = DiscoveryConfig::default()
+                .with_interval(Duration::from_millis(200))
+                .with_target_connections(3);
+            let config = KademliaConfig::default().with_discovery(discovery);
+            let (swarm_ctrl, kad_ctrl) = setup_kad_with_config(key, addr.clone(), config);
+            let mut node4 = PeerInfo {
+                pid,
+                addr,
+                swarm_ctrl,
+                kad_ctrl,
+            };
+
+            connect(&mut node4, &mut node0).await;
+
+            // wait for identify result and a few rounds of discovery
+            task::sleep(Duration::from_millis(1500)).await;
+
+            let connections = node4.swarm_ctrl.dump_connections(None).await.expect("dump connections");
+            let mut peers = connections.iter().map(|c| c.info.remote_peer_id).collect::<Vec<_>>();
+            peers.sort();
+            peers.dedup();
+            assert!(peers.len() >= 3, "only {} peers connected", peers.len());
+
+            let stats = node4.kad_ctrl.dump_statistics().await.expect("dump statistics");
+            assert!(stats.discovery.connected >= 2);
+            assert!(stats.discovery.skipped > 0);
+
+            TestResult::passed()
+        })
+    }
+    QuickCheck::new().tests(3).quickcheck(prop as fn() -> _);
+}
+
 #[test]
 fn test_simple_find_peer() {
     fn prop() -> TestResult {
@@ -233,3 +703,98 @@ fn test_simple_find_peer() {
     }
     QuickCheck::new().tests(10).quickcheck(prop as fn() -> _);
 }
+
+#[test]
+fn test_routing_table_snapshot() {
+    fn prop() -> TestResult {
+        task::block_on(async {
+            let path = std::env::temp_dir().join(format!("kad-snapshot-{}.json", PeerId::random()));
+            let base_port = 1 + random::<u64>();
+            let setup = |port: u64| {
+                let key = Keypair::generate_ed25519();
+                let pid = key.public().into_peer_id();
+                let addr: Multiaddr = Protocol::Memory(port).into();
+                let config = KademliaConfig::default().with_snapshot_path(Some(path.clone()));
+                let (swarm_ctrl, kad_ctrl) = setup_kad_with_config(key, addr.clone(), config);
+                PeerInfo {
+                    pid,
+                    addr,
+                    swarm_ctrl,
+                    kad_ctrl,
+                }
+            };
+
+            let mut server = setup_kads(1).pop().expect("server");
+            let mut node = setup(base_port);
+            connect(&mut node, &mut server).await;
+
+            // wait for identify result
+            task::sleep(Duration::from_millis(200)).await;
+
+            // the routing table is saved when closing
+            node.kad_ctrl.close();
+            task::sleep(Duration::from_millis(200)).await;
+
+            // add a peer which is gone to the snapshot
+            let dead = PeerId::random();
+            let saved = std::fs::read_to_string(&path).expect("read snapshot");
+            let entry = format!(r#"[{{"id":"{}","addrs":["/memory/{}"],"last_seen":null}},"#, dead, base_port + 2);
+            std::fs::write(&path, saved.replacen('[', &entry, 1)).expect("write snapshot");
+
+            // the restarted node reloads the server, and validates it with ping,
+            // while the dead peer fails to respond and gets removed
+            let mut node = setup(base_port + 1);
+            task::sleep(Duration::from_millis(200)).await;
+            let entries = node.kad_ctrl.dump_kbuckets().await.expect("dump kbuckets");
+            assert!(entries
+                .iter()
+                .any(|b| b.bucket.iter().any(|n| n.id == server.pid && n.aliveness.is_some())));
+            assert!(entries.iter().all(|b| b.bucket.iter().all(|n| n.id != dead)));
+
+            let _ = std::fs::remove_file(&path);
+            TestResult::passed()
+        })
+    }
+    QuickCheck::new().tests(5).quickcheck(prop as fn() -> _);
+}
+
+#[test]
+fn test_publish_resolve_name() {
+    fn prop() -> TestResult {
+        task::block_on(async {
+            let infos = setup_kads(3);
+            let mut node0 = infos.get(0).expect("get peer info").clone();
+            let mut node1 = infos.get(1).expect("get peer info").clone();
+            let mut node2 = infos.get(2).expect("get peer info").clone();
+
+            connect(&mut node0, &mut node1).await;
+            connect(&mut node1, &mut node2).await;
+
+            // wait for identify result
+            task::sleep(Duration::from_millis(200)).await;
+
+            let keypair = Keypair::generate_ed25519();
+            let name = keypair.public().into_peer_id();
+            node0.kad_ctrl.publish_name(keypair.clone(), b"v1".to_vec()).await.expect("publish");
+            assert_eq!(node2.kad_ctrl.resolve_name(&name).await.expect("resolve"), b"v1".to_vec());
+
+            node0.kad_ctrl.publish_name(keypair, b"v2".to_vec()).await.expect("publish");
+            assert_eq!(node2.kad_ctrl.resolve_name(&name).await.expect("resolve"), b"v2".to_vec());
+
+            // nobody else is able to overwrite it
+            let other = IpnsRecord::new(
+                &Keypair::generate_ed25519(),
+                b"v3".to_vec(),
+                100,
+                SystemTime::now() + Duration::from_secs(60),
+            )
+            .expect("sign");
+            let r = node1.kad_ctrl.put_value(ipns_key(&name).to_vec(), other.encode()).await;
+            assert!(r.is_err());
+            assert_eq!(node1.kad_ctrl.resolve_name(&name).await.expect("resolve"), b"v2".to_vec());
+
+            TestResult::passed()
+        })
+    }
+    QuickCheck::new().tests(5).quickcheck(prop as fn() -> _);
+}
@@ -1,7 +1,7 @@
 use libp2prs_runtime::task;
 
 use libp2prs_core::identity::Keypair;
-use libp2prs_core::Multiaddr;
+use libp2prs_core::{Multiaddr, PeerId};
 use libp2prs_mdns::service::MdnsService;
 use libp2prs_mdns::{AddrInfo, MdnsConfig, Notifee};
 use std::time::Duration;
@@ -12,6 +12,10 @@ impl Notifee for DiscoveryNotifee {
     fn handle_peer_found(&mut self, peer: AddrInfo) {
         log::info!("Discovered peer {}", peer);
     }
+
+    fn handle_peer_expired(&mut self, pid: PeerId) {
+        log::info!("Peer {} expired", pid);
+    }
 }
 
 fn main() {
@@ -19,15 +23,24 @@ fn main() {
     task::block_on(async {
         let pid = Keypair::generate_ed25519().public().into_peer_id();
         let listen_addr: Multiaddr = "/ip4/0.0.0.0/tcp/0".parse().unwrap();
-        let config = MdnsConfig::new(pid, vec![listen_addr], false);
+        let config = MdnsConfig::new(pid, vec![listen_addr], false).with_query_interval(Duration::from_secs(5));
         let service = MdnsService::new(config);
         let mut control = service.control();
 
         service.start();
-        control.register_notifee(Box::new(DiscoveryNotifee {})).await;
+        control.register_notifee(Box::new(DiscoveryNotifee {})).await.unwrap();
+        let _ = control.query_now().await;
 
         loop {
-            task::sleep(Duration::from_secs(120)).await;
+            task::sleep(Duration::from_secs(30)).await;
+            if let Ok(status) = control.status().await {
+                log::info!("mdns status: {:?}", status);
+            }
+            if let Ok(peers) = control.peers().await {
+                for peer in peers {
+                    log::info!("known peer {} expires in {:?}", peer.pid, peer.expiry - std::time::Instant::now());
+                }
+            }
         }
     });
 }
@@ -19,7 +19,7 @@
 // DEALINGS IN THE SOFTWARE.
 
 use crate::service::ControlCommand;
-use crate::INotifiee;
+use crate::{DiscoveredPeer, INotifiee, MdnsError, MdnsStatus};
 use futures::channel::{mpsc, oneshot};
 use futures::SinkExt;
 use std::fmt;
@@ -54,6 +54,9 @@ impl std::hash::Hash for RegId {
 }
 impl nohash_hasher::IsEnabled for RegId {}
 
+type Result<T> = std::result::Result<T, MdnsError>;
+
+#[derive(Clone)]
 pub struct Control {
     tx: mpsc::Sender<ControlCommand>,
 }
@@ -63,14 +66,41 @@ impl Control {
         Control { tx }
     }
 
-    pub async fn register_notifee(&mut self, noti: INotifiee) -> RegId {
+    /// Closes the mDNS service.
+    pub fn close(&mut self) {
+        self.tx.close_channel();
+    }
+
+    pub async fn register_notifee(&mut self, noti: INotifiee) -> Result<RegId> {
         let (tx, rx) = oneshot::channel();
         let cmd = ControlCommand::RegisterNotifee(noti, tx);
-        self.tx.send(cmd).await.expect("send RegisterNotifee failed");
-        rx.await.expect("recv RegisterNotifee failed")
+        self.tx.send(cmd).await?;
+        Ok(rx.await?)
     }
 
     pub async fn unregister_notifee(&mut self, id: RegId) {
         let _ = self.tx.send(ControlCommand::UnregisterNotifee(id)).await;
     }
+
+    /// Sends a query right now, instead of waiting for the query interval. Returns
+    /// the number of interfaces the query is sent on.
+    pub async fn query_now(&mut self) -> Result<usize> {
+        let (tx, rx) = oneshot::channel();
+        self.tx.send(ControlCommand::QueryNow(tx)).await?;
+        Ok(rx.await?)
+    }
+
+    /// Returns the peers discovered and not expired yet.
+    pub async fn peers(&mut self) -> Result<Vec<DiscoveredPeer>> {
+        let (tx, rx) = oneshot::channel();
+        self.tx.send(ControlCommand::Peers(tx)).await?;
+        Ok(rx.await?)
+    }
+
+    /// Returns the status of the service.
+    pub async fn status(&mut self) -> Result<MdnsStatus> {
+        let (tx, rx) = oneshot::channel();
+        self.tx.send(ControlCommand::Status(tx)).await?;
+        Ok(rx.await?)
+    }
 }
@@ -0,0 +1,523 @@
+// Copyright 2020 Netwarps Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Network interfaces mDNS runs on.
+//!
+//! Each network interface is joined to the mDNS multicast group of its address family,
+//! i.e. `224.0.0.251` or `ff02::fb`, on the shared multicast sockets bound to port 5353.
+//! Besides, each interface has its own socket, which sends the queries out of the
+//! interface and receives the unicast responses to them. Interfaces are added and
+//! removed as their addresses come and go.
+
+use futures::channel::mpsc;
+use if_watch::{IfEvent, IfWatcher};
+use ipnet::IpNet;
+use net2::UdpSocketExt;
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use libp2prs_core::multiaddr::protocol::Protocol;
+use libp2prs_core::Multiaddr;
+use libp2prs_runtime::{net::UdpSocket, task};
+
+/// The IPv4 mDNS multicast address.
+pub(crate) const IPV4_MDNS_MULTICAST_ADDRESS: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+/// The IPv6 mDNS multicast address.
+pub(crate) const IPV6_MDNS_MULTICAST_ADDRESS: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0xfb);
+/// The mDNS port.
+pub(crate) const MDNS_PORT: u16 = 5353;
+
+/// A packet received by one of the sockets, with the address of the sender.
+pub(crate) type Packet = (Vec<u8>, SocketAddr);
+
+/// The multicast sockets bound to the mDNS port, one for each address family.
+///
+/// Either of them is `None` if the address family is not available.
+pub(crate) struct MulticastSockets {
+    v4: Option<Arc<UdpSocket>>,
+    v6: Option<Arc<UdpSocket>>,
+}
+
+impl MulticastSockets {
+    /// Binds the multicast sockets, and starts receiving packets on them.
+    pub(crate) fn bind(packet_tx: mpsc::UnboundedSender<Packet>) -> io::Result<Self> {
+        let v4 = bind_multicast(false).map(Arc::new);
+        let v6 = bind_multicast(true).map(Arc::new);
+        if let Err(e) = &v6 {
+            log::info!("IPv6 mDNS is not available: {:?}", e);
+        }
+        let (v4, v6) = match (v4, v6) {
+            (Err(e), Err(_)) => return Err(e),
+            (v4, v6) => (v4.ok(), v6.ok()),
+        };
+
+        for socket in v4.iter().chain(v6.iter()) {
+            task::spawn(recv_loop(socket.clone(), packet_tx.clone()));
+        }
+        Ok(MulticastSockets { v4, v6 })
+    }
+
+    /// Returns the socket of the address family of `addr`, which is used to send
+    /// the responses.
+    pub(crate) fn socket_for(&self, addr: &SocketAddr) -> Option<&UdpSocket> {
+        match addr {
+            SocketAddr::V4(_) => self.v4.as_deref(),
+            SocketAddr::V6(_) => self.v6.as_deref(),
+        }
+    }
+
+    fn join(&self, iface: &Interface) -> io::Result<()> {
+        match (iface.is_v6(), &self.v4, &self.v6) {
+            (false, Some(s), _) => s.join_multicast_v4(IPV4_MDNS_MULTICAST_ADDRESS, iface.ipv4()),
+            (true, _, Some(s)) => s.join_multicast_v6(&IPV6_MDNS_MULTICAST_ADDRESS, iface.index),
+            _ => Err(io::ErrorKind::AddrNotAvailable.into()),
+        }
+    }
+
+    fn leave(&self, iface: &Interface) {
+        let _ = match (iface.is_v6(), &self.v4, &self.v6) {
+            (false, Some(s), _) => s.leave_multicast_v4(IPV4_MDNS_MULTICAST_ADDRESS, iface.ipv4()),
+            (true, _, Some(s)) => s.leave_multicast_v6(&IPV6_MDNS_MULTICAST_ADDRESS, iface.index),
+            _ => Ok(()),
+        };
+    }
+}
+
+/// A network interface of an address family.
+pub(crate) struct Interface {
+    /// The name of the interface.
+    name: String,
+    /// The index of the interface, the scope Id of the link-local IPv6 addresses.
+    index: u32,
+    /// The addresses of the interface.
+    nets: Vec<IpNet>,
+    /// The socket to send the queries and receive the responses to them.
+    socket: Arc<UdpSocket>,
+    /// The task receiving packets on the socket.
+    task: task::TaskHandle<()>,
+}
+
+impl Interface {
+    /// Returns the name of the interface.
+    pub(crate) fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns whether the interface is of IPv6.
+    pub(crate) fn is_v6(&self) -> bool {
+        matches!(self.nets.first().map(|n| n.addr()), Some(IpAddr::V6(_)))
+    }
+
+    /// Sends the packet to the multicast group out of the interface.
+    pub(crate) async fn multicast(&self, packet: &[u8]) -> io::Result<usize> {
+        let group: SocketAddr = if self.is_v6() {
+            (IPV6_MDNS_MULTICAST_ADDRESS, MDNS_PORT).into()
+        } else {
+            (IPV4_MDNS_MULTICAST_ADDRESS, MDNS_PORT).into()
+        };
+        self.socket.send_to(packet, group).await
+    }
+
+    /// Returns whether the sender of a packet is on the link of the interface.
+    pub(crate) fn is_on_link(&self, from: &SocketAddr) -> bool {
+        match from {
+            SocketAddr::V6(a) if a.scope_id() != 0 => a.scope_id() == self.index,
+            _ => self.nets.iter().any(|n| n.contains(&from.ip())),
+        }
+    }
+
+    /// Returns the addresses out of `addrs` which are reachable on the interface.
+    ///
+    /// Unspecified addresses, i.e. `0.0.0.0` or `::`, are replaced with the addresses
+    /// of the interface.
+    pub(crate) fn reachable_addrs(&self, addrs: &[Multiaddr]) -> Vec<Multiaddr> {
+        let mut reachable = vec![];
+        for addr in addrs {
+            let mut iter = addr.iter();
+            let ip = match iter.next() {
+                Some(Protocol::Ip4(ip)) => IpAddr::from(ip),
+                Some(Protocol::Ip6(ip)) => IpAddr::from(ip),
+                _ => continue,
+            };
+            if ip.is_unspecified() {
+                for net in self.nets.iter().filter(|n| n.addr().is_ipv6() == ip.is_ipv6()) {
+                    let mut a = Multiaddr::from(net.addr());
+                    addr.iter().skip(1).for_each(|p| a.push(p));
+                    reachable.push(a);
+                }
+            } else if self.nets.iter().any(|n| n.contains(&ip)) {
+                reachable.push(addr.clone());
+            }
+        }
+        reachable
+    }
+
+    fn ipv4(&self) -> Ipv4Addr {
+        match self.nets.first().map(|n| n.addr()) {
+            Some(IpAddr::V4(ip)) => ip,
+            _ => Ipv4Addr::UNSPECIFIED,
+        }
+    }
+}
+
+/// The network interfaces mDNS runs on, indexed by the name and the address family.
+#[derive(Default)]
+pub(crate) struct Interfaces {
+    ifaces: HashMap<(String, bool), Interface>,
+}
+
+impl Interfaces {
+    /// Adds an address. A new interface is joined to the multicast group if the
+    /// address is the first one of the interface, and the multicast sockets are bound.
+    pub(crate) fn add(&mut self, net: IpNet, multicast: Option<&MulticastSockets>, packet_tx: &mpsc::UnboundedSender<Packet>) {
+        if net.addr().is_loopback() {
+            return;
+        }
+        let (name, index) = interface_of(&net.addr());
+        let key = (name, net.addr().is_ipv6());
+        if let Some(iface) = self.ifaces.get_mut(&key) {
+            if !iface.nets.contains(&net) {
+                iface.nets.push(net);
+            }
+            return;
+        }
+
+        let socket = match bind_unicast(&net.addr(), index) {
+            Ok(s) => Arc::new(s),
+            Err(e) => {
+                log::info!("failed to bind mDNS socket on {}: {:?}", net, e);
+                return;
+            }
+        };
+        let task = task::spawn(recv_loop(socket.clone(), packet_tx.clone()));
+        let iface = Interface {
+            name: key.0.clone(),
+            index,
+            nets: vec![net],
+            socket,
+            task,
+        };
+        if let Some(multicast) = multicast {
+            if let Err(e) = multicast.join(&iface) {
+                log::info!("failed to join mDNS multicast group on {}: {:?}", iface.name, e);
+            }
+        }
+        log::debug!("mDNS running on interface {} for {}", iface.name, net);
+        self.ifaces.insert(key, iface);
+    }
+
+    /// Removes an address. The interface is removed if the address is the last one.
+    pub(crate) fn remove(&mut self, net: IpNet, multicast: Option<&MulticastSockets>) {
+        let key = match self.ifaces.iter().find(|(_, i)| i.nets.contains(&net)) {
+            Some((key, _)) => key.clone(),
+            None => return,
+        };
+        let iface = self.ifaces.get_mut(&key).expect("must exist");
+        iface.nets.retain(|n| n != &net);
+        if iface.nets.is_empty() {
+            let iface = self.ifaces.remove(&key).expect("must exist");
+            log::debug!("mDNS stopped on interface {}", iface.name);
+            if let Some(multicast) = multicast {
+                multicast.leave(&iface);
+            }
+            task::spawn(async move {
+                iface.task.cancel().await;
+            });
+        }
+    }
+
+    /// Joins all the interfaces to the multicast group, when the multicast sockets are
+    /// bound after the interfaces are up.
+    pub(crate) fn join_all(&self, multicast: &MulticastSockets) {
+        for iface in self.ifaces.values() {
+            if let Err(e) = multicast.join(iface) {
+                log::info!("failed to join mDNS multicast group on {}: {:?}", iface.name, e);
+            }
+        }
+    }
+
+    /// Returns the interface on whose link the sender of a packet is.
+    pub(crate) fn find(&self, from: &SocketAddr) -> Option<&Interface> {
+        self.ifaces.values().find(|i| i.is_v6() == from.is_ipv6() && i.is_on_link(from))
+    }
+
+    /// Returns an iterator over the interfaces.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &Interface> {
+        self.ifaces.values()
+    }
+}
+
+/// Watches the addresses of the network interfaces, and sends the changes to `tx`.
+///
+/// The existing addresses are sent as `IfEvent::Up` at first.
+pub(crate) async fn watch_interfaces(tx: mpsc::UnboundedSender<IfEvent>) {
+    let mut watcher = match IfWatcher::new().await {
+        Ok(w) => w,
+        Err(e) => {
+            log::warn!("failed to watch network interfaces: {:?}", e);
+            return;
+        }
+    };
+    loop {
+        match watcher.next().await {
+            Ok(event) => {
+                if tx.unbounded_send(event).is_err() {
+                    return;
+                }
+            }
+            Err(e) => {
+                log::warn!("failed to watch network interfaces: {:?}", e);
+                task::sleep(Duration::from_secs(1)).await;
+            }
+        }
+    }
+}
+
+/// The exponential backoff to retry the failed socket operations.
+pub(crate) struct Backoff {
+    min: Duration,
+    max: Duration,
+    current: Duration,
+}
+
+impl Backoff {
+    pub(crate) fn new(min: Duration, max: Duration) -> Self {
+        Backoff { min, max, current: min }
+    }
+
+    /// Returns the delay before the next retry, which is doubled every time up to
+    /// the maximum.
+    pub(crate) fn next(&mut self) -> Duration {
+        let delay = self.current;
+        self.current = std::cmp::min(self.current * 2, self.max);
+        delay
+    }
+
+    /// Resets the delay to the minimum, after the operation succeeds.
+    pub(crate) fn reset(&mut self) {
+        self.current = self.min;
+    }
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff::new(Duration::from_millis(100), Duration::from_secs(30))
+    }
+}
+
+async fn recv_loop(socket: Arc<UdpSocket>, tx: mpsc::UnboundedSender<Packet>) {
+    let mut buf = [0; 4096];
+    let mut backoff = Backoff::default();
+    loop {
+        match socket.recv_from(&mut buf).await {
+            Ok((len, from)) => {
+                backoff.reset();
+                if tx.unbounded_send((buf[..len].to_vec(), from)).is_err() {
+                    return;
+                }
+            }
+            Err(e) => {
+                log::debug!("mDNS recv failed: {:?}", e);
+                task::sleep(backoff.next()).await;
+            }
+        }
+    }
+}
+
+// Returns the name and the index of the interface which has the address.
+fn interface_of(ip: &IpAddr) -> (String, u32) {
+    match interface_name(ip) {
+        Some(name) => {
+            let index = interface_index(&name);
+            (name, index)
+        }
+        None => (ip.to_string(), 0),
+    }
+}
+
+// Note `if_addrs` skips the link-local IPv6 addresses, so that getifaddrs is used
+// directly on unix.
+#[cfg(unix)]
+fn interface_name(ip: &IpAddr) -> Option<String> {
+    let mut ifap: *mut libc::ifaddrs = std::ptr::null_mut();
+    if unsafe { libc::getifaddrs(&mut ifap) } != 0 {
+        return None;
+    }
+
+    let mut name = None;
+    let mut cur = ifap;
+    while !cur.is_null() {
+        let ifa = unsafe { &*cur };
+        cur = ifa.ifa_next;
+        if ifa.ifa_addr.is_null() {
+            continue;
+        }
+        let addr = match i32::from(unsafe { (*ifa.ifa_addr).sa_family }) {
+            libc::AF_INET => {
+                let sa = unsafe { &*(ifa.ifa_addr as *const libc::sockaddr_in) };
+                IpAddr::from(Ipv4Addr::from(u32::from_be(sa.sin_addr.s_addr)))
+            }
+            libc::AF_INET6 => {
+                let sa = unsafe { &*(ifa.ifa_addr as *const libc::sockaddr_in6) };
+                IpAddr::from(Ipv6Addr::from(sa.sin6_addr.s6_addr))
+            }
+            _ => continue,
+        };
+        if &addr == ip {
+            let n = unsafe { std::ffi::CStr::from_ptr(ifa.ifa_name) };
+            name = Some(n.to_string_lossy().into_owned());
+            break;
+        }
+    }
+
+    unsafe { libc::freeifaddrs(ifap) };
+    name
+}
+
+#[cfg(not(unix))]
+fn interface_name(ip: &IpAddr) -> Option<String> {
+    if_addrs::get_if_addrs()
+        .ok()
+        .and_then(|ifaces| ifaces.into_iter().find(|i| &i.ip() == ip))
+        .map(|i| i.name)
+}
+
+#[cfg(unix)]
+fn interface_index(name: &str) -> u32 {
+    match std::ffi::CString::new(name) {
+        Ok(name) => unsafe { libc::if_nametoindex(name.as_ptr()) },
+        Err(_) => 0,
+    }
+}
+
+#[cfg(not(unix))]
+fn interface_index(_name: &str) -> u32 {
+    // the default interface
+    0
+}
+
+// Binds a socket to the mDNS port, which receives the multicast packets.
+fn bind_multicast(v6: bool) -> io::Result<UdpSocket> {
+    #[cfg(unix)]
+    fn platform_specific(s: &net2::UdpBuilder) -> io::Result<()> {
+        net2::unix::UnixUdpBuilderExt::reuse_port(s, true)?;
+        Ok(())
+    }
+    #[cfg(not(unix))]
+    fn platform_specific(_: &net2::UdpBuilder) -> io::Result<()> {
+        Ok(())
+    }
+
+    let std_socket = if v6 {
+        let builder = net2::UdpBuilder::new_v6()?;
+        builder.only_v6(true)?;
+        builder.reuse_address(true)?;
+        platform_specific(&builder)?;
+        builder.bind((Ipv6Addr::UNSPECIFIED, MDNS_PORT))?
+    } else {
+        let builder = net2::UdpBuilder::new_v4()?;
+        builder.reuse_address(true)?;
+        platform_specific(&builder)?;
+        builder.bind((Ipv4Addr::UNSPECIFIED, MDNS_PORT))?
+    };
+    if v6 {
+        std_socket.set_multicast_loop_v6(true)?;
+    } else {
+        std_socket.set_multicast_loop_v4(true)?;
+        std_socket.set_multicast_ttl_v4(255)?;
+    }
+
+    Ok(UdpSocket::from(std_socket))
+}
+
+// Binds a socket to an ephemeral port on the interface, which sends the queries.
+fn bind_unicast(ip: &IpAddr, index: u32) -> io::Result<UdpSocket> {
+    let std_socket = match ip {
+        IpAddr::V4(ip) => {
+            let s = std::net::UdpSocket::bind((*ip, 0))?;
+            s.set_multicast_if_v4(ip)?;
+            s.set_multicast_loop_v4(true)?;
+            s.set_multicast_ttl_v4(255)?;
+            s
+        }
+        IpAddr::V6(_) => {
+            let builder = net2::UdpBuilder::new_v6()?;
+            builder.only_v6(true)?;
+            let s = builder.bind((Ipv6Addr::UNSPECIFIED, 0))?;
+            s.set_multicast_if_v6(index)?;
+            s.set_multicast_loop_v6(true)?;
+            s.set_multicast_hops_v6(255)?;
+            s
+        }
+    };
+
+    Ok(UdpSocket::from(std_socket))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::SocketAddrV6;
+
+    #[test]
+    fn test_reachable_addrs() {
+        let socket = std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let iface = Interface {
+            name: "eth0".to_string(),
+            index: 2,
+            nets: vec!["192.168.1.10/24".parse().unwrap()],
+            socket: Arc::new(UdpSocket::from(socket)),
+            task: task::spawn(async {}),
+        };
+
+        let addrs: Vec<Multiaddr> = vec![
+            "/ip4/192.168.1.10/tcp/4001".parse().unwrap(),
+            "/ip4/10.0.0.1/tcp/4001".parse().unwrap(),
+            "/ip4/0.0.0.0/tcp/4002".parse().unwrap(),
+            "/ip6/::/tcp/4003".parse().unwrap(),
+        ];
+        let expected: Vec<Multiaddr> = vec![
+            "/ip4/192.168.1.10/tcp/4001".parse().unwrap(),
+            "/ip4/192.168.1.10/tcp/4002".parse().unwrap(),
+        ];
+        assert_eq!(iface.reachable_addrs(&addrs), expected);
+
+        assert!(iface.is_on_link(&"192.168.1.20:5353".parse().unwrap()));
+        assert!(!iface.is_on_link(&"10.0.0.2:5353".parse().unwrap()));
+        let link_local: Ipv6Addr = "fe80::1".parse().unwrap();
+        assert!(iface.is_on_link(&SocketAddrV6::new(link_local, 5353, 0, 2).into()));
+        assert!(!iface.is_on_link(&SocketAddrV6::new(link_local, 5353, 0, 3).into()));
+    }
+
+    #[test]
+    fn test_backoff() {
+        let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(5));
+        assert_eq!(backoff.next(), Duration::from_secs(1));
+        assert_eq!(backoff.next(), Duration::from_secs(2));
+        assert_eq!(backoff.next(), Duration::from_secs(4));
+        assert_eq!(backoff.next(), Duration::from_secs(5));
+        assert_eq!(backoff.next(), Duration::from_secs(5));
+        backoff.reset();
+        assert_eq!(backoff.next(), Duration::from_secs(1));
+    }
+}
@@ -24,8 +24,18 @@
 //! In the context of libp2p, the mDNS protocol is used to discover other nodes on the local
 //! network that support libp2p.
 //!
+//! The service can run standalone, with the discovered peers reported to the registered
+//! [`Notifee`]s, or be started with Swarm, i.e. `service.start_with_swarm(swarm.control())`.
+//! In the latter case, the addresses of the discovered peers are added to the peer store
+//! of Swarm with the TTL of the mDNS records, the peers are optionally connected, and the
+//! listen addresses announced are kept in sync with those of Swarm, as notified by
+//! [`libp2prs_swarm::Control::subscribe_address_change`].
+//!
 
+use futures::channel::{mpsc, oneshot};
 use libp2prs_core::{Multiaddr, PeerId};
+use std::time::{Duration, Instant};
+use std::{error, io};
 
 /// Hardcoded name of the mDNS service. Part of the mDNS libp2p specifications.
 const SERVICE_NAME: &[u8] = b"_p2p._udp.local";
@@ -34,7 +44,9 @@ const META_QUERY_SERVICE: &[u8] = b"_services._dns-sd._udp.local";
 
 pub mod control;
 mod dns;
+mod iface;
 pub mod service;
+pub mod socket;
 
 use smallvec::alloc::fmt::Formatter;
 use std::fmt;
@@ -43,13 +55,23 @@ pub struct MdnsConfig {
     /// local Peer ID
     local_peer: PeerId,
 
-    /// List of multiaddresses we're listening on.
+    /// List of multiaddresses we're listening on. It is replaced by the listen addresses
+    /// of Swarm, when the service is started with Swarm.
     listened_addrs: Vec<Multiaddr>,
 
     /// Whether we send queries on the network at all.
     /// Note that we still need to have an interval for querying, as we need to wake up the socket
     /// regularly to recover from errors. Otherwise we could simply use an `Option<Interval>`.
     silent: bool,
+
+    /// The interval between two queries.
+    query_interval: Duration,
+
+    /// The TTL of the records in the responses, i.e. how long the others remember us.
+    response_ttl: Duration,
+
+    /// Whether to connect the discovered peers, when the service is started with Swarm.
+    auto_connect: bool,
 }
 
 impl MdnsConfig {
@@ -58,10 +80,108 @@ impl MdnsConfig {
             local_peer,
             listened_addrs,
             silent,
+            query_interval: Duration::from_secs(10),
+            response_ttl: Duration::from_secs(5 * 60),
+            auto_connect: false,
+        }
+    }
+
+    /// Sets whether to send queries. A silent service only responds to the queries
+    /// of the others.
+    pub fn with_silent(mut self, silent: bool) -> Self {
+        self.silent = silent;
+        self
+    }
+
+    /// Sets the interval between two queries. The default is 10 seconds.
+    pub fn with_query_interval(mut self, interval: Duration) -> Self {
+        self.query_interval = interval;
+        self
+    }
+
+    /// Sets the TTL of the records in the responses. The default is 5 minutes.
+    pub fn with_response_ttl(mut self, ttl: Duration) -> Self {
+        self.response_ttl = ttl;
+        self
+    }
+
+    /// Sets whether to connect the discovered peers, when the service is started
+    /// with Swarm. The default is false.
+    pub fn with_auto_connect(mut self, auto_connect: bool) -> Self {
+        self.auto_connect = auto_connect;
+        self
+    }
+}
+
+/// A peer discovered and not expired yet.
+#[derive(Debug, Clone)]
+pub struct DiscoveredPeer {
+    pub pid: PeerId,
+    pub addrs: Vec<Multiaddr>,
+    /// When the records of the peer expire.
+    pub expiry: Instant,
+}
+
+/// The status of the mDNS service.
+#[derive(Debug, Clone, Default)]
+pub struct MdnsStatus {
+    /// Whether the multicast sockets are bound. The binding is retried with backoff
+    /// if it fails.
+    pub bound: bool,
+    /// The network interfaces mDNS runs on.
+    pub interfaces: Vec<String>,
+    /// The number of queries sent.
+    pub queries_sent: usize,
+    /// The number of responses sent.
+    pub responses_sent: usize,
+    /// The number of packets which failed to be sent.
+    pub send_errors: usize,
+    /// The last error of the sockets.
+    pub last_error: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum MdnsError {
+    Io(io::Error),
+    Closed,
+}
+
+impl error::Error for MdnsError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            MdnsError::Io(err) => Some(err),
+            MdnsError::Closed => None,
+        }
+    }
+}
+
+impl fmt::Display for MdnsError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            MdnsError::Io(e) => write!(f, "i/o error: {}", e),
+            MdnsError::Closed => f.write_str("mdns service is closed"),
         }
     }
 }
 
+impl From<io::Error> for MdnsError {
+    fn from(e: io::Error) -> Self {
+        MdnsError::Io(e)
+    }
+}
+
+impl From<mpsc::SendError> for MdnsError {
+    fn from(_: mpsc::SendError) -> Self {
+        MdnsError::Closed
+    }
+}
+
+impl From<oneshot::Canceled> for MdnsError {
+    fn from(_: oneshot::Canceled) -> Self {
+        MdnsError::Closed
+    }
+}
+
 #[derive(Clone)]
 pub struct AddrInfo {
     pub pid: PeerId,
@@ -96,6 +216,9 @@ impl fmt::Display for AddrInfo {
 
 pub trait Notifee {
     fn handle_peer_found(&mut self, _discovered: AddrInfo) {}
+    /// It is called when the mDNS records of a peer expire, i.e. the peer is not
+    /// seen on the local network any more.
+    fn handle_peer_expired(&mut self, _pid: PeerId) {}
 }
 
 pub type INotifiee = Box<dyn Notifee + Send + Sync>;
@@ -22,40 +22,39 @@
 use dns_parser::{Packet, RData};
 use futures::{
     channel::{mpsc, oneshot},
-    select, FutureExt, StreamExt,
+    select, stream, FutureExt, StreamExt,
 };
 use futures_timer::Delay;
-use lazy_static::lazy_static;
 use nohash_hasher::IntMap;
 use smallvec::SmallVec;
+use std::fmt;
 use std::{
+    collections::HashMap,
     convert::TryFrom,
     io, iter,
-    net::{Ipv4Addr, SocketAddr},
+    net::SocketAddr,
     str,
-    time::Duration,
+    time::{Duration, Instant},
 };
-use std::{error::Error, fmt};
 
 use libp2prs_core::multiaddr::protocol::Protocol;
 use libp2prs_core::translation::address_translation;
 use libp2prs_core::{Multiaddr, PeerId};
-use libp2prs_runtime::{net::UdpSocket, task};
+use libp2prs_runtime::task;
+use libp2prs_swarm::Control as SwarmControl;
 
 use crate::control::Control;
 use crate::control::RegId;
 use crate::dns::build_service_discovery_response;
-use crate::{dns, AddrInfo, INotifiee, MdnsConfig, META_QUERY_SERVICE, SERVICE_NAME};
-
-const MDNS_RESPONSE_TTL: std::time::Duration = Duration::from_secs(5 * 60);
-
-lazy_static! {
-    static ref IPV4_MDNS_MULTICAST_ADDRESS: SocketAddr = SocketAddr::from((Ipv4Addr::new(224, 0, 0, 251), 5353,));
-}
+use crate::socket::{IMdnsSocket, UdpMdnsSocket};
+use crate::{dns, AddrInfo, DiscoveredPeer, INotifiee, MdnsConfig, MdnsStatus, META_QUERY_SERVICE, SERVICE_NAME};
 
 pub enum ControlCommand {
     RegisterNotifee(INotifiee, oneshot::Sender<RegId>),
     UnregisterNotifee(RegId),
+    QueryNow(oneshot::Sender<usize>),
+    Peers(oneshot::Sender<Vec<DiscoveredPeer>>),
+    Status(oneshot::Sender<MdnsStatus>),
 }
 
 pub struct MdnsService {
@@ -68,6 +67,18 @@ pub struct MdnsService {
 
     /// All registered notifiees.
     notifees: IntMap<RegId, INotifiee>,
+
+    /// The peers discovered, with their addresses and when their records expire.
+    peers: HashMap<PeerId, DiscoveredPeer>,
+
+    /// The counters and the last error of the sockets.
+    status: MdnsStatus,
+
+    /// The socket layer to send and receive the packets.
+    socket: IMdnsSocket,
+
+    /// Used to communicate with Swarm, if the service is started with Swarm.
+    swarm: Option<SwarmControl>,
 }
 
 impl MdnsService {
@@ -80,9 +91,19 @@ impl MdnsService {
             control_tx,
             control_rx,
             notifees: IntMap::default(),
+            peers: HashMap::new(),
+            status: MdnsStatus::default(),
+            socket: Box::new(UdpMdnsSocket::new()),
+            swarm: None,
         }
     }
 
+    /// Replaces the socket layer, which is `UdpMdnsSocket` by default.
+    pub fn with_socket(mut self, socket: IMdnsSocket) -> Self {
+        self.socket = socket;
+        self
+    }
+
     /// Get control of mdns service, which can be used to register and unregister notifiee.
     pub fn control(&self) -> Control {
         Control::new(self.control_tx.clone())
@@ -92,65 +113,134 @@ impl MdnsService {
     pub fn start(self) {
         let mut service = self;
         task::spawn(async move {
-            let _ = service.next().await;
+            service.next().await;
         });
     }
 
-    async fn next(&mut self) -> io::Result<Box<dyn Error>> {
-        // We have to create udp socket locally instead of member of MdnsService.
-        // Otherwise, when we select, compiler will complain about coexist of the immut and mut ref
-        let (socket, query_socket) = bind().expect("bind failed");
-        let mut mrecv_buffer = [0; 4096];
-        let mut urecv_buffer = [0; 4096];
+    /// Starts the service with Swarm.
+    ///
+    /// The addresses of the discovered peers are added to the peer store of Swarm,
+    /// the peers are connected if auto-connect is enabled, and the listen addresses
+    /// of Swarm are announced instead of those in the configuration.
+    pub fn start_with_swarm(mut self, swarm: SwarmControl) -> task::TaskHandle<()> {
+        self.swarm = Some(swarm);
 
-        let mut timer = Box::pin(Delay::new(Duration::from_secs(10)).fuse());
-        loop {
-            // It is better to pin these futures outside "select" macro,
-            // otherwise compiler will give me a confused complain with "recursion limit reached while expanding"
-            let mut m_recv = Box::pin(recv(&query_socket, &mut mrecv_buffer).fuse());
-            let mut u_recv = Box::pin(recv(&socket, &mut urecv_buffer).fuse());
+        let mut service = self;
+        task::spawn(async move {
+            service.next().await;
+        })
+    }
 
+    async fn next(&mut self) {
+        // follow the listen addresses of Swarm, if started with Swarm
+        let mut addr_rx = match self.swarm.as_mut() {
+            Some(swarm) => match swarm.subscribe_address_change().await {
+                Ok(rx) => rx.boxed(),
+                Err(e) => {
+                    log::debug!("failed to subscribe the address change of Swarm: {:?}", e);
+                    stream::pending().boxed()
+                }
+            },
+            None => stream::pending().boxed(),
+        };
+
+        let mut timer = Box::pin(Delay::new(self.config.query_interval).fuse());
+        loop {
             select! {
-                (data, from) = m_recv => {
-                    let packet = MdnsPacket::new_from_bytes(&data, from);
-                    self.handle_received_packet(&socket, packet).await;
-                },
-                (data, from) = u_recv => {
-                    let packet = MdnsPacket::new_from_bytes(&data, from);
-                    self.handle_received_packet(&socket, packet).await;
+                packet = self.socket.recv().fuse() => {
+                    match packet {
+                        Ok((data, from)) => {
+                            let packet = MdnsPacket::new_from_bytes(&data, from);
+                            self.handle_received_packet(packet).await;
+                        }
+                        Err(e) => self.status.last_error = Some(e.to_string()),
+                    }
                 },
                 cmd = self.control_rx.next() => {
-                    self.on_control_command(cmd);
+                    match cmd {
+                        Some(cmd) => self.on_control_command(cmd).await,
+                        None => {
+                            log::debug!("mDNS service is closed");
+                            return;
+                        }
+                    }
+                },
+                addrs = addr_rx.next().fuse() => {
+                    match addrs {
+                        Some(addrs) => {
+                            log::debug!("listen addresses changed: {:?}", addrs);
+                            self.config.listened_addrs = addrs;
+                        }
+                        // Swarm is gone, keep the addresses as they are
+                        None => addr_rx = stream::pending().boxed(),
+                    }
                 },
                 _r = timer => {
-                    timer = Box::pin(Delay::new(Duration::from_secs(10)).fuse());
+                    timer = Box::pin(Delay::new(self.config.query_interval).fuse());
+                    self.expire_peers();
                     if !self.config.silent {
-                        let query = dns::build_query();
-                        query_socket
-                            .send_to(&query, *IPV4_MDNS_MULTICAST_ADDRESS)
-                            .await
-                            .expect("send query failed");
+                        self.send_query().await;
                     }
                 }
             }
         }
     }
 
-    async fn handle_received_packet(&mut self, socket: &UdpSocket, packet: Option<MdnsPacket>) {
+    // Sends a query on every interface. Returns the number of interfaces the query
+    // is sent on.
+    async fn send_query(&mut self) -> usize {
+        let query = dns::build_query();
+        let mut sent = 0;
+        for r in self.socket.multicast(&query).await {
+            match r {
+                Ok(()) => sent += 1,
+                Err(e) => {
+                    log::debug!("failed to send query: {:?}", e);
+                    self.status.send_errors += 1;
+                    self.status.last_error = Some(e.to_string());
+                }
+            }
+        }
+        self.status.queries_sent += sent;
+        sent
+    }
+
+    // Counts the response sent, or the error.
+    fn on_response_sent(&mut self, r: io::Result<()>) {
+        match r {
+            Ok(_) => self.status.responses_sent += 1,
+            Err(e) => {
+                log::debug!("failed to send response: {:?}", e);
+                self.status.send_errors += 1;
+                self.status.last_error = Some(e.to_string());
+            }
+        }
+    }
+
+    async fn handle_received_packet(&mut self, packet: Option<MdnsPacket>) {
         if let Some(packet) = packet {
             match packet {
                 MdnsPacket::Query(query) => {
                     let raddr = query.remote_addr();
                     log::debug!("Query from {:?}", query.remote_addr());
-                    let resp = dns::build_query_response(
+
+                    // Only the addresses reachable on the link of the querier are announced.
+                    let addrs = self.socket.reachable_addrs(raddr, &self.config.listened_addrs);
+                    let resp = match dns::build_query_response(
                         query.query_id,
                         self.config.local_peer,
-                        self.config.listened_addrs.clone().into_iter(),
-                        MDNS_RESPONSE_TTL,
-                    )
-                    .unwrap();
+                        addrs.into_iter(),
+                        self.config.response_ttl,
+                    ) {
+                        Ok(resp) => resp,
+                        Err(e) => {
+                            log::warn!("failed to build query response: {:?}", e);
+                            return;
+                        }
+                    };
 
-                    socket.send_to(&resp, *raddr).await.expect("send query response failed");
+                    let r = self.socket.send_to(&resp, raddr).await;
+                    self.on_response_sent(r);
                 }
                 MdnsPacket::Response(resp) => {
                     // We replace the IP address with the address we observe the
@@ -165,7 +255,12 @@ impl MdnsService {
                             continue;
                         }
 
-                        // TODO: manage time-to-live of peer
+                        // a TTL of zero means the peer is leaving
+                        if peer.ttl() == Duration::from_secs(0) {
+                            self.remove_peer(peer.id());
+                            continue;
+                        }
+
                         let mut addrs: Vec<Multiaddr> = Vec::new();
                         for addr in peer.addresses() {
                             if let Some(new_addr) = address_translation(&addr, &observed) {
@@ -174,6 +269,8 @@ impl MdnsService {
                             addrs.push(addr.clone())
                         }
 
+                        self.update_peer(*peer.id(), addrs.clone(), peer.ttl());
+
                         for addr in addrs {
                             discovered.push((*peer.id(), addr));
                         }
@@ -187,68 +284,96 @@ impl MdnsService {
                     }
                 }
                 MdnsPacket::ServiceDiscovery(disc) => {
-                    let resp = build_service_discovery_response(disc.query_id(), MDNS_RESPONSE_TTL);
+                    let resp = build_service_discovery_response(disc.query_id(), self.config.response_ttl);
 
-                    socket
-                        .send_to(&resp, *IPV4_MDNS_MULTICAST_ADDRESS)
-                        .await
-                        .expect("send query response failed");
+                    match self.socket.multicast_on_link(disc.remote_addr(), &resp).await {
+                        Ok(false) => {}
+                        r => self.on_response_sent(r.map(|_| ())),
+                    }
                 }
             }
         }
     }
 
-    fn on_control_command(&mut self, cmd: Option<ControlCommand>) {
+    // Updates the expiry of the peer, and adds its addresses to the peer store of Swarm.
+    // A peer which is new or has expired is connected, if auto-connect is enabled.
+    fn update_peer(&mut self, pid: PeerId, addrs: Vec<Multiaddr>, ttl: Duration) {
+        let now = Instant::now();
+        let peer = DiscoveredPeer {
+            pid,
+            addrs: addrs.clone(),
+            expiry: now + ttl,
+        };
+        let is_new = self.peers.insert(pid, peer).map_or(true, |old| old.expiry <= now);
+
+        if let Some(swarm) = self.swarm.as_ref() {
+            swarm.add_addrs(&pid, addrs.clone(), ttl);
+            if is_new && self.config.auto_connect {
+                log::debug!("connecting to the discovered peer {}", pid);
+                let mut swarm = swarm.clone();
+                task::spawn(async move {
+                    if let Err(e) = swarm.connect_with_addrs(pid, addrs).await {
+                        log::debug!("failed to connect the discovered peer {}: {:?}", pid, e);
+                    }
+                });
+            }
+        }
+    }
+
+    // Removes the peer and notifies the notifiees.
+    fn remove_peer(&mut self, pid: &PeerId) {
+        if self.peers.remove(pid).is_some() {
+            log::debug!("peer {} expired", pid);
+            for noti in self.notifees.values_mut() {
+                noti.handle_peer_expired(*pid);
+            }
+        }
+    }
+
+    // Removes the peers whose records have expired. Their addresses in the peer store
+    // of Swarm expire on their own.
+    fn expire_peers(&mut self) {
+        let now = Instant::now();
+        let expired = self
+            .peers
+            .iter()
+            .filter(|(_, peer)| peer.expiry <= now)
+            .map(|(pid, _)| *pid)
+            .collect::<Vec<_>>();
+        for pid in expired {
+            self.remove_peer(&pid);
+        }
+    }
+
+    async fn on_control_command(&mut self, cmd: ControlCommand) {
         match cmd {
-            Some(ControlCommand::RegisterNotifee(noti, reply)) => {
+            ControlCommand::RegisterNotifee(noti, reply) => {
                 let id = RegId::random();
                 self.notifees.insert(id, noti);
                 let _ = reply.send(id);
             }
-            Some(ControlCommand::UnregisterNotifee(id)) => {
+            ControlCommand::UnregisterNotifee(id) => {
                 self.notifees.remove(&id);
             }
-            None => {}
+            ControlCommand::QueryNow(reply) => {
+                let sent = self.send_query().await;
+                let _ = reply.send(sent);
+            }
+            ControlCommand::Peers(reply) => {
+                let now = Instant::now();
+                let peers = self.peers.values().filter(|p| p.expiry > now).cloned().collect();
+                let _ = reply.send(peers);
+            }
+            ControlCommand::Status(reply) => {
+                let mut status = self.status.clone();
+                status.bound = self.socket.is_bound();
+                status.interfaces = self.socket.interfaces();
+                let _ = reply.send(status);
+            }
         }
     }
 }
 
-fn bind() -> io::Result<(UdpSocket, UdpSocket)> {
-    let std_socket = {
-        #[cfg(unix)]
-        fn platform_specific(s: &net2::UdpBuilder) -> io::Result<()> {
-            net2::unix::UnixUdpBuilderExt::reuse_port(s, true)?;
-            Ok(())
-        }
-        #[cfg(not(unix))]
-        fn platform_specific(_: &net2::UdpBuilder) -> io::Result<()> {
-            Ok(())
-        }
-        let builder = net2::UdpBuilder::new_v4()?;
-        builder.reuse_address(true)?;
-        platform_specific(&builder)?;
-        builder.bind(("0.0.0.0", 5353))?
-    };
-
-    let socket = UdpSocket::from(std_socket);
-
-    // Given that we pass an IP address to bind, which does not need to be resolved, we can
-    let query_socket = UdpSocket::from(std::net::UdpSocket::bind((Ipv4Addr::from([0u8, 0, 0, 0]), 0u16))?);
-
-    socket.set_multicast_loop_v4(true).expect("couldn't set multicast loop");
-    socket.set_multicast_ttl_v4(255).expect("couldn't set multicast ttl");
-    // TODO: correct interfaces?
-    socket.join_multicast_v4(Ipv4Addr::new(224, 0, 0, 251), Ipv4Addr::UNSPECIFIED)?;
-
-    Ok((socket, query_socket))
-}
-
-async fn recv<'a>(socket: &'a UdpSocket, buf: &'a mut [u8]) -> (&'a [u8], SocketAddr) {
-    let (len, from) = socket.recv_from(buf).await.expect("recv from failed");
-    let (data, _) = buf.split_at(len);
-    (data, from)
-}
-
 /// A valid mDNS packet received by the service.
 #[derive(Debug)]
 pub enum MdnsPacket {
@@ -513,3 +638,36 @@ impl fmt::Debug for MdnsPeer {
         f.debug_struct("MdnsPeer").field("peer_id", &self.peer_id).finish()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libp2prs_core::identity;
+
+    #[test]
+    fn parse_query_and_response() {
+        let from: SocketAddr = "10.0.0.2:5353".parse().unwrap();
+        match MdnsPacket::new_from_bytes(&dns::build_query(), from) {
+            Some(MdnsPacket::Query(query)) => assert_eq!(query.remote_addr(), &from),
+            p => panic!("unexpected packet {:?}", p),
+        }
+
+        let pid = identity::Keypair::generate_ed25519().public().into_peer_id();
+        let addrs: Vec<Multiaddr> = vec!["/ip4/10.0.0.2/tcp/4001".parse().unwrap(), "/ip6/::1/tcp/4001".parse().unwrap()];
+        let resp = dns::build_query_response(0x1234, pid, addrs.clone().into_iter(), Duration::from_secs(120)).unwrap();
+        match MdnsPacket::new_from_bytes(&resp, from) {
+            Some(MdnsPacket::Response(resp)) => {
+                let peers = resp.discovered_peers().collect::<Vec<_>>();
+                assert_eq!(peers.len(), 1);
+                assert_eq!(peers[0].id(), &pid);
+                assert_eq!(peers[0].ttl(), Duration::from_secs(120));
+                assert_eq!(peers[0].addresses(), &addrs);
+            }
+            p => panic!("unexpected packet {:?}", p),
+        }
+
+        let disc = dns::build_service_discovery_response(0x1234, Duration::from_secs(120));
+        assert!(matches!(MdnsPacket::new_from_bytes(&disc, from), Some(MdnsPacket::Response(_))));
+        assert!(MdnsPacket::new_from_bytes(b"garbage", from).is_none());
+    }
+}
@@ -0,0 +1,348 @@
+// Copyright 2020 Netwarps Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! The socket layer of mDNS.
+//!
+//! `MdnsService` sends and receives the packets through `MdnsSocket`. `UdpMdnsSocket`
+//! runs on the multicast groups of the network interfaces, and `MemoryBus` connects the
+//! services in the same process, which makes the tests independent of the network.
+
+use async_trait::async_trait;
+use futures::channel::mpsc;
+use futures::future::Fuse;
+use futures::{select, FutureExt, StreamExt};
+use futures_timer::Delay;
+use if_watch::IfEvent;
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use libp2prs_core::multiaddr::protocol::Protocol;
+use libp2prs_core::Multiaddr;
+use libp2prs_runtime::task;
+
+use crate::iface::{watch_interfaces, Backoff, Interfaces, MulticastSockets, Packet, MDNS_PORT};
+
+/// The socket layer which `MdnsService` sends and receives the packets through.
+#[async_trait]
+pub trait MdnsSocket: Send {
+    /// Receives the next packet, with the address of the sender.
+    ///
+    /// An error doesn't close the socket. It is reported for the status of the service,
+    /// and `recv` is called again.
+    async fn recv(&mut self) -> io::Result<(Vec<u8>, SocketAddr)>;
+
+    /// Sends the packet to the multicast group on every link. Returns the result of
+    /// each link.
+    async fn multicast(&mut self, packet: &[u8]) -> Vec<io::Result<()>>;
+
+    /// Sends the packet to the multicast group on the link of `from`. Returns false
+    /// if the link is unknown.
+    async fn multicast_on_link(&mut self, from: &SocketAddr, packet: &[u8]) -> io::Result<bool>;
+
+    /// Sends the packet to `to`.
+    async fn send_to(&mut self, packet: &[u8], to: &SocketAddr) -> io::Result<()>;
+
+    /// Returns the addresses out of `addrs` which are reachable by `from`.
+    fn reachable_addrs(&self, from: &SocketAddr, addrs: &[Multiaddr]) -> Vec<Multiaddr>;
+
+    /// Returns whether the socket is ready to receive the multicast packets.
+    fn is_bound(&self) -> bool;
+
+    /// Returns the names of the links the socket runs on.
+    fn interfaces(&self) -> Vec<String>;
+}
+
+pub type IMdnsSocket = Box<dyn MdnsSocket>;
+
+/// The UDP sockets on the multicast groups of the network interfaces.
+///
+/// The interfaces are watched and the sockets are bound when `recv` is called at
+/// first, and the multicast sockets are bound again with backoff if the binding fails,
+/// e.g. the port is in use by another responder without SO_REUSEPORT.
+pub struct UdpMdnsSocket {
+    packet_tx: mpsc::UnboundedSender<Packet>,
+    packet_rx: mpsc::UnboundedReceiver<Packet>,
+    if_rx: Option<mpsc::UnboundedReceiver<IfEvent>>,
+    ifaces: Interfaces,
+    multicast: Option<MulticastSockets>,
+    backoff: Backoff,
+    rebind: Pin<Box<Fuse<Delay>>>,
+}
+
+impl UdpMdnsSocket {
+    pub fn new() -> Self {
+        let (packet_tx, packet_rx) = mpsc::unbounded();
+        UdpMdnsSocket {
+            packet_tx,
+            packet_rx,
+            if_rx: None,
+            ifaces: Interfaces::default(),
+            multicast: None,
+            backoff: Backoff::new(Duration::from_secs(1), Duration::from_secs(60)),
+            rebind: Box::pin(Delay::new(Duration::from_secs(0)).fuse()),
+        }
+    }
+
+    fn on_interface_event(&mut self, event: IfEvent) {
+        match event {
+            IfEvent::Up(net) => self.ifaces.add(net, self.multicast.as_ref(), &self.packet_tx),
+            IfEvent::Down(net) => self.ifaces.remove(net, self.multicast.as_ref()),
+        }
+    }
+
+    fn bind(&mut self) -> io::Result<()> {
+        match MulticastSockets::bind(self.packet_tx.clone()) {
+            Ok(sockets) => {
+                self.ifaces.join_all(&sockets);
+                self.multicast = Some(sockets);
+                self.backoff.reset();
+                Ok(())
+            }
+            Err(e) => {
+                let delay = self.backoff.next();
+                log::warn!("failed to bind mDNS sockets, retry in {:?}: {:?}", delay, e);
+                self.rebind = Box::pin(Delay::new(delay).fuse());
+                Err(e)
+            }
+        }
+    }
+}
+
+impl Default for UdpMdnsSocket {
+    fn default() -> Self {
+        UdpMdnsSocket::new()
+    }
+}
+
+#[async_trait]
+impl MdnsSocket for UdpMdnsSocket {
+    async fn recv(&mut self) -> io::Result<(Vec<u8>, SocketAddr)> {
+        if self.if_rx.is_none() {
+            let (if_tx, if_rx) = mpsc::unbounded();
+            task::spawn(watch_interfaces(if_tx));
+            self.if_rx = Some(if_rx);
+        }
+
+        loop {
+            let if_rx = self.if_rx.as_mut().expect("started");
+            select! {
+                packet = self.packet_rx.next() => {
+                    // never None, the sender is held by the socket
+                    if let Some(packet) = packet {
+                        return Ok(packet);
+                    }
+                },
+                event = if_rx.next() => {
+                    if let Some(event) = event {
+                        self.on_interface_event(event);
+                    }
+                },
+                _r = &mut self.rebind => {
+                    self.bind()?;
+                },
+            }
+        }
+    }
+
+    async fn multicast(&mut self, packet: &[u8]) -> Vec<io::Result<()>> {
+        let mut results = vec![];
+        for iface in self.ifaces.iter() {
+            let r = iface.multicast(packet).await.map(|_| ());
+            if let Err(e) = &r {
+                log::debug!("failed to send on {}: {:?}", iface.name(), e);
+            }
+            results.push(r);
+        }
+        results
+    }
+
+    async fn multicast_on_link(&mut self, from: &SocketAddr, packet: &[u8]) -> io::Result<bool> {
+        match self.ifaces.find(from) {
+            Some(iface) => iface.multicast(packet).await.map(|_| true),
+            None => Ok(false),
+        }
+    }
+
+    async fn send_to(&mut self, packet: &[u8], to: &SocketAddr) -> io::Result<()> {
+        match self.multicast.as_ref().and_then(|m| m.socket_for(to)) {
+            Some(socket) => socket.send_to(packet, *to).await.map(|_| ()),
+            None => Err(io::ErrorKind::NotConnected.into()),
+        }
+    }
+
+    fn reachable_addrs(&self, from: &SocketAddr, addrs: &[Multiaddr]) -> Vec<Multiaddr> {
+        // All addresses are announced if the link is unknown, i.e. the interface
+        // is not up yet.
+        match self.ifaces.find(from) {
+            Some(iface) => iface.reachable_addrs(addrs),
+            None => addrs.to_vec(),
+        }
+    }
+
+    fn is_bound(&self) -> bool {
+        self.multicast.is_some()
+    }
+
+    fn interfaces(&self) -> Vec<String> {
+        self.ifaces
+            .iter()
+            .map(|i| format!("{}/{}", i.name(), if i.is_v6() { "ipv6" } else { "ipv4" }))
+            .collect()
+    }
+}
+
+/// An in-memory multicast link, which connects the `MdnsService`s in the same process.
+///
+/// ```no_run
+/// use libp2prs_mdns::service::MdnsService;
+/// use libp2prs_mdns::socket::MemoryBus;
+/// # use libp2prs_mdns::MdnsConfig;
+/// # use libp2prs_core::PeerId;
+///
+/// let bus = MemoryBus::new();
+/// let socket = bus.socket([10, 0, 0, 1].into()).unwrap();
+/// let config = MdnsConfig::new(PeerId::random(), vec!["/ip4/0.0.0.0/tcp/4001".parse().unwrap()], false);
+/// let service = MdnsService::new(config).with_socket(Box::new(socket));
+/// ```
+#[derive(Clone, Default)]
+pub struct MemoryBus {
+    members: Arc<Mutex<HashMap<SocketAddr, mpsc::UnboundedSender<Packet>>>>,
+}
+
+impl MemoryBus {
+    pub fn new() -> Self {
+        MemoryBus::default()
+    }
+
+    /// Attaches a new socket with the IP address to the bus.
+    pub fn socket(&self, ip: IpAddr) -> io::Result<MemorySocket> {
+        let addr = SocketAddr::new(ip, MDNS_PORT);
+        let (tx, rx) = mpsc::unbounded();
+        let mut members = self.members.lock().unwrap();
+        if members.contains_key(&addr) {
+            return Err(io::ErrorKind::AddrInUse.into());
+        }
+        members.insert(addr, tx);
+        Ok(MemorySocket {
+            bus: self.clone(),
+            addr,
+            rx,
+        })
+    }
+
+    /// Returns the number of the sockets attached to the bus.
+    pub fn len(&self) -> usize {
+        self.members.lock().unwrap().len()
+    }
+
+    /// Returns whether no socket is attached to the bus.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Delivers the packet to the sockets selected by `filter`.
+    fn deliver(&self, from: SocketAddr, packet: &[u8], filter: impl Fn(&SocketAddr) -> bool) {
+        let members = self.members.lock().unwrap();
+        for (_, tx) in members.iter().filter(|(addr, _)| filter(addr)) {
+            let _ = tx.unbounded_send((packet.to_vec(), from));
+        }
+    }
+}
+
+/// A socket attached to `MemoryBus`. It is detached when dropped.
+pub struct MemorySocket {
+    bus: MemoryBus,
+    addr: SocketAddr,
+    rx: mpsc::UnboundedReceiver<Packet>,
+}
+
+impl MemorySocket {
+    /// Returns the address of the socket on the bus.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+impl Drop for MemorySocket {
+    fn drop(&mut self) {
+        self.bus.members.lock().unwrap().remove(&self.addr);
+    }
+}
+
+#[async_trait]
+impl MdnsSocket for MemorySocket {
+    async fn recv(&mut self) -> io::Result<(Vec<u8>, SocketAddr)> {
+        match self.rx.next().await {
+            Some(packet) => Ok(packet),
+            // never happens, the sender is held by the bus until the socket is dropped
+            None => futures::future::pending().await,
+        }
+    }
+
+    async fn multicast(&mut self, packet: &[u8]) -> Vec<io::Result<()>> {
+        let addr = self.addr;
+        self.bus.deliver(addr, packet, |to| *to != addr);
+        vec![Ok(())]
+    }
+
+    async fn multicast_on_link(&mut self, _from: &SocketAddr, packet: &[u8]) -> io::Result<bool> {
+        let addr = self.addr;
+        self.bus.deliver(addr, packet, |to| *to != addr);
+        Ok(true)
+    }
+
+    async fn send_to(&mut self, packet: &[u8], to: &SocketAddr) -> io::Result<()> {
+        // the packet is lost if the receiver is gone, just like UDP
+        self.bus.deliver(self.addr, packet, |addr| addr == to);
+        Ok(())
+    }
+
+    fn reachable_addrs(&self, _from: &SocketAddr, addrs: &[Multiaddr]) -> Vec<Multiaddr> {
+        let ip = Protocol::from(self.addr.ip());
+        addrs
+            .iter()
+            .map(|addr| match addr.iter().next() {
+                Some(Protocol::Ip4(a)) if a.is_unspecified() => replace_ip(addr, ip.clone()),
+                Some(Protocol::Ip6(a)) if a.is_unspecified() => replace_ip(addr, ip.clone()),
+                _ => addr.clone(),
+            })
+            .collect()
+    }
+
+    fn is_bound(&self) -> bool {
+        true
+    }
+
+    fn interfaces(&self) -> Vec<String> {
+        vec!["memory".to_string()]
+    }
+}
+
+// Replaces the IP address of `addr` with `ip`.
+fn replace_ip(addr: &Multiaddr, ip: Protocol<'static>) -> Multiaddr {
+    let mut a = Multiaddr::empty();
+    a.push(ip);
+    addr.iter().skip(1).for_each(|p| a.push(p));
+    a
+}
@@ -0,0 +1,281 @@
+// Copyright 2020 Netwarps Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+use futures::channel::mpsc;
+use futures::StreamExt;
+use libp2prs_core::identity::Keypair;
+use libp2prs_core::multiaddr::protocol::Protocol;
+use libp2prs_core::transport::memory::MemoryTransport;
+use libp2prs_core::transport::upgrade::TransportUpgrade;
+use libp2prs_core::{Multiaddr, PeerId};
+use libp2prs_mdns::control::Control;
+use libp2prs_mdns::service::MdnsService;
+use libp2prs_mdns::socket::MemoryBus;
+use libp2prs_mdns::{AddrInfo, MdnsConfig, Notifee};
+use libp2prs_plaintext as plaintext;
+use libp2prs_runtime::task;
+use libp2prs_swarm::{Control as SwarmControl, Swarm};
+use libp2prs_yamux as yamux;
+use std::net::IpAddr;
+use std::time::Duration;
+
+enum Event {
+    Found(AddrInfo),
+    Expired(PeerId),
+}
+
+struct TestNotifee(mpsc::UnboundedSender<Event>);
+
+impl Notifee for TestNotifee {
+    fn handle_peer_found(&mut self, discovered: AddrInfo) {
+        let _ = self.0.unbounded_send(Event::Found(discovered));
+    }
+
+    fn handle_peer_expired(&mut self, pid: PeerId) {
+        let _ = self.0.unbounded_send(Event::Expired(pid));
+    }
+}
+
+// Starts a service on the bus, which queries only when asked to, unless the query
+// interval is changed.
+async fn start_service(
+    bus: &MemoryBus,
+    ip: &str,
+    f: impl FnOnce(MdnsConfig) -> MdnsConfig,
+) -> (PeerId, Control, mpsc::UnboundedReceiver<Event>) {
+    let pid = PeerId::random();
+    let listen_addr: Multiaddr = "/ip4/0.0.0.0/tcp/4001".parse().unwrap();
+    let config = MdnsConfig::new(pid, vec![listen_addr], false).with_query_interval(Duration::from_secs(3600));
+    let socket = bus.socket(ip.parse::<IpAddr>().unwrap()).unwrap();
+    let service = MdnsService::new(f(config)).with_socket(Box::new(socket));
+    let mut control = service.control();
+    service.start();
+
+    let (tx, rx) = mpsc::unbounded();
+    control.register_notifee(Box::new(TestNotifee(tx))).await.unwrap();
+    (pid, control, rx)
+}
+
+async fn next_found(rx: &mut mpsc::UnboundedReceiver<Event>) -> AddrInfo {
+    loop {
+        if let Some(Event::Found(info)) = rx.next().await {
+            return info;
+        }
+    }
+}
+
+#[test]
+fn test_discovery() {
+    task::block_on(async {
+        let bus = MemoryBus::new();
+        let (pid1, mut control1, mut rx1) = start_service(&bus, "10.0.0.1", |c| c).await;
+        let (pid2, mut control2, mut rx2) = start_service(&bus, "10.0.0.2", |c| c).await;
+        assert_eq!(bus.len(), 2);
+
+        assert_eq!(control1.query_now().await.unwrap(), 1);
+        let info = next_found(&mut rx1).await;
+        assert_eq!(info.pid, pid2);
+        // the unspecified address is replaced with the address on the link
+        assert_eq!(info.addrs, vec!["/ip4/10.0.0.2/tcp/4001".parse::<Multiaddr>().unwrap()]);
+
+        let peers = control1.peers().await.unwrap();
+        assert_eq!(peers.len(), 1);
+        assert_eq!(peers[0].pid, pid2);
+
+        let status = control1.status().await.unwrap();
+        assert!(status.bound);
+        assert_eq!(status.queries_sent, 1);
+        assert_eq!(status.send_errors, 0);
+        assert_eq!(control2.status().await.unwrap().responses_sent, 1);
+
+        control2.query_now().await.unwrap();
+        assert_eq!(next_found(&mut rx2).await.pid, pid1);
+
+        control1.close();
+        control2.close();
+    });
+}
+
+#[test]
+fn test_silent() {
+    task::block_on(async {
+        let bus = MemoryBus::new();
+        let (pid1, mut control1, mut rx1) =
+            start_service(&bus, "10.0.0.1", |c| c.with_query_interval(Duration::from_millis(100))).await;
+        let (_pid2, mut control2, _rx2) = start_service(&bus, "10.0.0.2", |c| {
+            c.with_silent(true).with_query_interval(Duration::from_millis(100))
+        })
+        .await;
+
+        // the silent one responds, but never queries
+        next_found(&mut rx1).await;
+        task::sleep(Duration::from_millis(300)).await;
+        assert_eq!(control2.status().await.unwrap().queries_sent, 0);
+        assert!(control2.peers().await.unwrap().iter().all(|p| p.pid != pid1));
+        assert!(control1.status().await.unwrap().queries_sent > 0);
+
+        control1.close();
+        control2.close();
+    });
+}
+
+#[test]
+fn test_ttl_expiry() {
+    task::block_on(async {
+        let bus = MemoryBus::new();
+        let (_pid1, mut control1, mut rx1) =
+            start_service(&bus, "10.0.0.1", |c| c.with_query_interval(Duration::from_millis(100))).await;
+        let (pid2, mut control2, _rx2) =
+            start_service(&bus, "10.0.0.2", |c| c.with_silent(true).with_response_ttl(Duration::from_secs(1))).await;
+
+        let info = next_found(&mut rx1).await;
+        assert_eq!(info.pid, pid2);
+        let expiry = control1.peers().await.unwrap()[0].expiry;
+        assert!(expiry <= std::time::Instant::now() + Duration::from_secs(1));
+
+        // the peer expires after it is gone
+        control2.close();
+        while bus.len() > 1 {
+            task::sleep(Duration::from_millis(10)).await;
+        }
+        loop {
+            if let Some(Event::Expired(pid)) = rx1.next().await {
+                assert_eq!(pid, pid2);
+                break;
+            }
+        }
+        assert!(control1.peers().await.unwrap().is_empty());
+
+        control1.close();
+    });
+}
+
+// Starts a Swarm listening on a memory address, along with a service on the bus
+// started with the Swarm.
+async fn start_swarm_service(
+    bus: &MemoryBus,
+    ip: &str,
+    f: impl FnOnce(MdnsConfig) -> MdnsConfig,
+) -> (PeerId, Multiaddr, SwarmControl, Control, mpsc::UnboundedReceiver<Event>) {
+    let keys = Keypair::generate_ed25519();
+    let pid = keys.public().into_peer_id();
+    let addr: Multiaddr = Protocol::Memory(1 + rand::random::<u64>()).into();
+
+    let sec = plaintext::PlainTextConfig::new(keys.clone());
+    let tu = TransportUpgrade::new(MemoryTransport::default(), yamux::Config::new(), sec);
+    let mut swarm = Swarm::new(keys.public()).with_transport(Box::new(tu));
+    swarm.listen_on(vec![addr.clone()]).expect("listen on");
+    let swarm_ctrl = swarm.control();
+    swarm.start();
+
+    // the listen addresses in the configuration are replaced by those of Swarm
+    let config = MdnsConfig::new(pid, vec![], false).with_query_interval(Duration::from_secs(3600));
+    let socket = bus.socket(ip.parse::<IpAddr>().unwrap()).unwrap();
+    let service = MdnsService::new(f(config)).with_socket(Box::new(socket));
+    let mut control = service.control();
+    service.start_with_swarm(swarm_ctrl.clone());
+
+    let (tx, rx) = mpsc::unbounded();
+    control.register_notifee(Box::new(TestNotifee(tx))).await.unwrap();
+    (pid, addr, swarm_ctrl, control, rx)
+}
+
+#[test]
+fn test_swarm_peer_store() {
+    task::block_on(async {
+        let bus = MemoryBus::new();
+        let (_pid1, _addr1, swarm1, mut control1, mut rx1) = start_swarm_service(&bus, "10.0.0.1", |c| c).await;
+        let (pid2, addr2, _swarm2, mut control2, _rx2) = start_swarm_service(&bus, "10.0.0.2", |c| c).await;
+
+        // wait for the listen address of Swarm to be announced
+        task::sleep(Duration::from_millis(100)).await;
+
+        control1.query_now().await.unwrap();
+        let info = next_found(&mut rx1).await;
+        assert_eq!(info.pid, pid2);
+        assert_eq!(info.addrs, vec![addr2.clone()]);
+
+        // the discovered peer is added to the peer store, but not connected
+        assert_eq!(swarm1.get_addrs(&pid2), Some(vec![addr2]));
+        task::sleep(Duration::from_millis(100)).await;
+        assert!(swarm1.clone().dump_connections(Some(pid2)).await.unwrap().is_empty());
+
+        control1.close();
+        control2.close();
+    });
+}
+
+#[test]
+fn test_swarm_auto_connect() {
+    task::block_on(async {
+        let bus = MemoryBus::new();
+        let (_pid1, _addr1, mut swarm1, mut control1, mut rx1) =
+            start_swarm_service(&bus, "10.0.0.1", |c| c.with_auto_connect(true)).await;
+        let (pid2, _addr2, _swarm2, mut control2, _rx2) = start_swarm_service(&bus, "10.0.0.2", |c| c).await;
+
+        task::sleep(Duration::from_millis(100)).await;
+
+        control1.query_now().await.unwrap();
+        assert_eq!(next_found(&mut rx1).await.pid, pid2);
+
+        // the discovered peer is connected
+        let mut connected = false;
+        for _ in 0..100 {
+            if !swarm1.dump_connections(Some(pid2)).await.unwrap().is_empty() {
+                connected = true;
+                break;
+            }
+            task::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(connected);
+
+        control1.close();
+        control2.close();
+    });
+}
+
+#[test]
+fn test_swarm_expiry() {
+    task::block_on(async {
+        let bus = MemoryBus::new();
+        let (_pid1, _addr1, swarm1, mut control1, mut rx1) =
+            start_swarm_service(&bus, "10.0.0.1", |c| c.with_query_interval(Duration::from_millis(100))).await;
+        let (pid2, addr2, _swarm2, mut control2, _rx2) =
+            start_swarm_service(&bus, "10.0.0.2", |c| c.with_silent(true).with_response_ttl(Duration::from_secs(1))).await;
+
+        assert_eq!(next_found(&mut rx1).await.pid, pid2);
+        assert_eq!(swarm1.get_addrs(&pid2), Some(vec![addr2]));
+
+        // the peer expires after it is gone
+        control2.close();
+        while bus.len() > 1 {
+            task::sleep(Duration::from_millis(10)).await;
+        }
+        loop {
+            if let Some(Event::Expired(pid)) = rx1.next().await {
+                assert_eq!(pid, pid2);
+                break;
+            }
+        }
+        assert!(control1.peers().await.unwrap().is_empty());
+
+        control1.close();
+    });
+}
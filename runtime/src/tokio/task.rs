@@ -109,6 +109,18 @@ where
     TaskHandle(h)
 }
 
+/// Spawns a blocking runtime, running the closure on a thread where blocking is acceptable.
+///
+/// The returned TaskHandle can be used to wait for its result.
+pub fn spawn_blocking<F, T>(f: F) -> TaskHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let h = tokio().spawn_blocking(f);
+    TaskHandle(h)
+}
+
 /// Sleeps for the specified amount of time.
 pub async fn sleep(dur: Duration) {
     time::sleep(dur).await
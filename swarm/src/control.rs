@@ -59,10 +59,14 @@ pub enum SwarmControlCmd {
     CloseStream(ConnectionId, StreamId),
     /// Retrieve the self multi addresses of Swarm.
     SelfAddresses(oneshot::Sender<Vec<Multiaddr>>),
+    /// Subscribe to the changes of the self multi addresses of Swarm.
+    SubscribeAddresses(mpsc::UnboundedSender<Vec<Multiaddr>>),
     /// Retrieve network information of Swarm.
     NetworkInfo(oneshot::Sender<NetworkInfo>),
     /// Retrieve network information of Swarm.
     IdentifyInfo(oneshot::Sender<IdentifyInfo>),
+    /// Push the identify information to all connected peers.
+    IdentifyPush,
     ///
     Dump(DumpCommand),
 }
@@ -208,6 +212,16 @@ impl Control {
         Ok(rx.await?)
     }
 
+    /// Subscribe to the changes of the self addresses of Swarm.
+    ///
+    /// The current addresses are delivered first, then all the addresses are
+    /// delivered whenever they change, until the receiver is dropped.
+    pub async fn subscribe_address_change(&mut self) -> Result<mpsc::UnboundedReceiver<Vec<Multiaddr>>> {
+        let (tx, rx) = mpsc::unbounded();
+        self.sender.send(SwarmControlCmd::SubscribeAddresses(tx)).await?;
+        Ok(rx)
+    }
+
     /// Retrieve network information from Swarm.
     pub async fn retrieve_networkinfo(&mut self) -> Result<NetworkInfo> {
         let (tx, rx) = oneshot::channel();
@@ -222,6 +236,13 @@ impl Control {
         Ok(rx.await?)
     }
 
+    /// Push the identify information to all connected peers, if the Push service
+    /// is enabled, e.g. after the supported protocols have changed.
+    pub async fn identify_push(&mut self) -> Result<()> {
+        self.sender.send(SwarmControlCmd::IdentifyPush).await?;
+        Ok(())
+    }
+
     pub async fn dump_connections(&mut self, peer_id: Option<PeerId>) -> Result<Vec<ConnectionView>> {
         let (tx, rx) = oneshot::channel();
         self.sender
@@ -273,6 +294,11 @@ impl Control {
         self.peer_store.get_key(peer_id)
     }
 
+    /// Gets the agent version of a peer, which is reported by Identify.
+    pub fn get_agent_version(&self, peer_id: &PeerId) -> Option<String> {
+        self.peer_store.get_agent_version(peer_id)
+    }
+
     /// Gets all multiaddr of a peer.
     pub fn get_addrs(&self, peer_id: &PeerId) -> Option<Vec<Multiaddr>> {
         self.peer_store.get_addrs(peer_id)
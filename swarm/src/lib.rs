@@ -317,6 +317,12 @@ pub struct Swarm {
     /// List of nodes for which are forbidden.
     banned_peers: HashSet<PeerId>,
 
+    /// The subscribers of the changes of the self addresses.
+    addr_subscribers: Vec<mpsc::UnboundedSender<Vec<Multiaddr>>>,
+
+    /// Whether the identify information is pushed to connections.
+    identify_push: bool,
+
     /// The all connections_by_peer connections organized by their Ids
     connections_by_id: FnvHashMap<ConnectionId, Connection>,
     /// The all connections_by_peer connections by  peer Id
@@ -378,6 +384,8 @@ impl Swarm {
             listened_addrs: Default::default(),
             external_addrs: Default::default(),
             banned_peers: Default::default(),
+            addr_subscribers: vec![],
+            identify_push: false,
             connections_by_id: Default::default(),
             connections_by_peer: Default::default(),
             metric: Arc::new(metric),
@@ -449,6 +457,7 @@ impl Swarm {
     }
     /// Modifies Swarm with Identify service.
     pub fn with_identify(mut self, config: IdentifyConfig) -> Self {
+        self.identify_push = config.push;
         let handler = IdentifyHandler::new(self.ctrl_sender.clone());
         self.muxer.add_protocol_handler(Box::new(handler));
         let handler = IdentifyPushHandler::new(config, self.event_sender.clone());
@@ -493,6 +502,8 @@ impl Swarm {
         for handler in self.muxer.protocol_handlers.values_mut() {
             handler.address_changed(own_addrs.clone());
         }
+        // and to the subscribers, dropping those gone
+        self.addr_subscribers.retain(|tx| tx.unbounded_send(own_addrs.clone()).is_ok());
     }
 
     fn on_event(&mut self, event: SwarmEvent) {
@@ -574,6 +585,12 @@ impl Swarm {
                     let _ = reply.send(r);
                 });
             }
+            SwarmControlCmd::SubscribeAddresses(tx) => {
+                // deliver the current addresses right away
+                if tx.unbounded_send(self.get_self_addrs()).is_ok() {
+                    self.addr_subscribers.push(tx);
+                }
+            }
             SwarmControlCmd::NetworkInfo(reply) => {
                 // Received from channel, try retrieving network info
                 let _ = self.on_retrieve_network_info(|r| {
@@ -586,6 +603,9 @@ impl Swarm {
                     let _ = reply.send(r);
                 });
             }
+            SwarmControlCmd::IdentifyPush => {
+                self.on_identify_push();
+            }
             SwarmControlCmd::Dump(cmd) => match cmd {
                 DumpCommand::Connections(peer_id, reply) => {
                     let _ = self.on_retrieve_connection_views(peer_id, |r| {
@@ -704,6 +724,15 @@ impl Swarm {
         f(self.get_identify_info());
         Ok(())
     }
+    /// Pushes the identify information to all connections.
+    fn on_identify_push(&mut self) {
+        if self.identify_push {
+            log::debug!("pushing identify info to {} connections", self.connections_by_id.len());
+            for connection in self.connections_by_id.values_mut() {
+                connection.start_identify_push();
+            }
+        }
+    }
     /// Retrieves the connection views.
     fn on_retrieve_connection_views(&mut self, pid: Option<PeerId>, f: impl FnOnce(Vec<ConnectionView>)) -> Result<()> {
         f(self.get_connection_views(pid));
@@ -1360,10 +1389,11 @@ impl Swarm {
                     // update peerstore with the listening addresses and protocols of the remote peer
                     // Note, we don't use connection.remote_addr(), because it might be a NATed address/port which
                     // changed very frequently. Instead, using info.listen_addrs is a better solution.
-                    // TODO: to handle info.protocol_version .agent_version
+                    // TODO: to handle info.protocol_version
                     self.peer_store.add_addrs(&peer_id, info.listen_addrs, ADDRESS_TTL);
                     self.peer_store.add_key(&peer_id, remote_pubkey);
                     self.peer_store.add_protocols(&peer_id, info.protocols);
+                    self.peer_store.add_agent_version(&peer_id, info.agent_version);
 
                     // well, kick off all protocol handlers for the Identify completion
                     for handler in self.muxer.protocol_handlers.values_mut() {
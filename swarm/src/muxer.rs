@@ -69,8 +69,12 @@ impl Muxer {
         });
     }
 
+    /// Returns the protocols of the handlers which are accepting inbound sub-streams.
     pub(crate) fn supported_protocols(&self) -> impl IntoIterator<Item = ProtocolId> + '_ {
-        self.protocol_handlers.keys().cloned()
+        self.protocol_handlers
+            .iter()
+            .filter(|(_, h)| h.accepting_inbound())
+            .map(|(pid, _)| pid.clone())
     }
 
     pub(crate) async fn select_inbound(
@@ -100,6 +100,14 @@ pub trait ProtocolHandler: UpgradeInfo + Notifiee {
     ///
     /// The `info` is the identifier of the protocol, as produced by `protocol_info`.
     async fn handle(&mut self, stream: Substream, info: <Self as UpgradeInfo>::Info) -> Result<(), Box<dyn Error>>;
+    /// Returns whether the handler is accepting inbound sub-streams at the moment.
+    ///
+    /// The protocols of a handler which is not accepting inbound are neither negotiated for
+    /// the inbound sub-streams nor advertised via Identify, but the handler still receives
+    /// the notifications from Swarm. The default is true.
+    fn accepting_inbound(&self) -> bool {
+        true
+    }
     /// This is to provide a clone method for the trait object.
     fn box_clone(&self) -> IProtocolHandler;
 }
@@ -0,0 +1,94 @@
+// Copyright 2020 Netwarps Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+type Entries = HashMap<String, (Vec<IpAddr>, Instant)>;
+
+/// The cache of the resolved IP addresses, shared by the clones of `DnsConfig`.
+#[derive(Clone, Debug)]
+pub(crate) struct DnsCache {
+    ttl: Duration,
+    entries: Arc<Mutex<Entries>>,
+}
+
+impl DnsCache {
+    /// Creates a cache whose entries expire after `ttl`. Nothing is cached if `ttl` is zero.
+    pub(crate) fn new(ttl: Duration) -> Self {
+        DnsCache {
+            ttl,
+            entries: Default::default(),
+        }
+    }
+
+    /// Returns the IP addresses of `name`, unless they are not cached or expired.
+    pub(crate) fn get(&self, name: &str) -> Option<Vec<IpAddr>> {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get(name) {
+            Some((ips, expiry)) if *expiry > Instant::now() => Some(ips.clone()),
+            Some(_) => {
+                entries.remove(name);
+                None
+            }
+            None => None,
+        }
+    }
+
+    pub(crate) fn insert(&self, name: String, ips: Vec<IpAddr>) {
+        if self.ttl == Duration::from_secs(0) || ips.is_empty() {
+            return;
+        }
+        let expiry = Instant::now() + self.ttl;
+        self.entries.lock().unwrap().insert(name, (ips, expiry));
+    }
+
+    /// Removes the entry of `name`, e.g. when none of its addresses could be dialed.
+    pub(crate) fn remove(&self, name: &str) {
+        self.entries.lock().unwrap().remove(name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cache_expiry() {
+        let ips: Vec<IpAddr> = vec!["10.0.0.1".parse().unwrap(), "::1".parse().unwrap()];
+
+        let cache = DnsCache::new(Duration::from_millis(50));
+        cache.insert("example.com".to_string(), ips.clone());
+        assert_eq!(cache.get("example.com"), Some(ips.clone()));
+        assert_eq!(cache.get("example.org"), None);
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(cache.get("example.com"), None);
+
+        cache.insert("example.com".to_string(), ips.clone());
+        cache.remove("example.com");
+        assert_eq!(cache.get("example.com"), None);
+
+        let disabled = DnsCache::new(Duration::from_secs(0));
+        disabled.insert("example.com".to_string(), ips);
+        assert_eq!(disabled.get("example.com"), None);
+    }
+}
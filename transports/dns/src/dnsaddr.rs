@@ -0,0 +1,153 @@
+// Copyright 2020 Netwarps Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Resolution of `/dnsaddr/`.
+//!
+//! `/dnsaddr/<name>` is resolved to the addresses in the TXT records of `_dnsaddr.<name>`,
+//! in the form of `dnsaddr=<multiaddr>`. The addresses might contain `/dnsaddr/` again,
+//! which is resolved recursively up to a maximum depth.
+
+use libp2prs_core::multiaddr::{protocol::Protocol, Multiaddr};
+
+use crate::resolver::Resolver;
+
+/// The default maximum depth of the recursive resolution.
+pub const DEFAULT_MAX_DNSADDR_DEPTH: usize = 4;
+
+const DNSADDR_TXT_PREFIX: &str = "dnsaddr=";
+
+/// Returns whether `addr` has a `/dnsaddr/` component.
+pub(crate) fn has_dnsaddr(addr: &Multiaddr) -> bool {
+    addr.iter().any(|p| matches!(p, Protocol::Dnsaddr(_)))
+}
+
+/// Resolves the `/dnsaddr/` components of `addr` recursively.
+///
+/// The components after `/dnsaddr/`, e.g. `/p2p/<id>`, are used as a filter, that only
+/// the addresses ending with them are returned. The addresses which are still not
+/// resolved at the maximum depth are dropped.
+pub(crate) async fn resolve_dnsaddr(resolver: &dyn Resolver, addr: Multiaddr, max_depth: usize) -> Vec<Multiaddr> {
+    let mut resolved = vec![];
+    let mut pending = vec![(addr, 0)];
+    while let Some((addr, depth)) = pending.pop() {
+        let (index, name) = match addr.iter().enumerate().find_map(|(i, p)| match p {
+            Protocol::Dnsaddr(name) => Some((i, name.to_string())),
+            _ => None,
+        }) {
+            Some(found) => found,
+            None => {
+                if !resolved.contains(&addr) {
+                    resolved.push(addr);
+                }
+                continue;
+            }
+        };
+        if depth >= max_depth {
+            log::warn!("{} is not resolved, the maximum depth {} is reached", addr, max_depth);
+            continue;
+        }
+
+        let prefix = addr.iter().take(index).collect::<Vec<_>>();
+        let suffix = addr.iter().skip(index + 1).collect::<Vec<_>>();
+
+        let txts = match resolver.lookup_txt(&format!("_dnsaddr.{}", name)).await {
+            Ok(txts) => txts,
+            Err(e) => {
+                log::debug!("failed to look up the TXT records of {}: {:?}", name, e);
+                continue;
+            }
+        };
+
+        // pushed in the reverse order, so that they are popped in the order of the records
+        for txt in txts.iter().rev() {
+            let record = match txt.strip_prefix(DNSADDR_TXT_PREFIX).map(|s| s.parse::<Multiaddr>()) {
+                Some(Ok(record)) => record,
+                _ => {
+                    log::debug!("invalid dnsaddr TXT record of {}: {}", name, txt);
+                    continue;
+                }
+            };
+            let components = record.iter().collect::<Vec<_>>();
+            if !components.ends_with(&suffix) {
+                continue;
+            }
+            let addr = prefix.iter().cloned().chain(components).collect::<Multiaddr>();
+            pending.push((addr, depth + 1));
+        }
+    }
+    resolved
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolver::StaticResolver;
+    use libp2prs_runtime::task;
+
+    #[test]
+    fn resolve_recursively() {
+        let resolver = StaticResolver::new()
+            .with_txt(
+                "_dnsaddr.bootstrap.test",
+                "dnsaddr=/dnsaddr/sjc.bootstrap.test/p2p/QmNnooDu7bfjPFoTZYxMNLWUQJyrVwtbZg5gBMjTezGAJN",
+            )
+            .with_txt(
+                "_dnsaddr.bootstrap.test",
+                "dnsaddr=/dnsaddr/ams.bootstrap.test/p2p/QmQCU2EcMqAqQPR2i9bChDtGNJchTbq5TbXJJ16u19uLTa",
+            )
+            .with_txt("_dnsaddr.bootstrap.test", "invalid record")
+            .with_txt(
+                "_dnsaddr.sjc.bootstrap.test",
+                "dnsaddr=/ip4/10.0.0.1/tcp/4001/p2p/QmNnooDu7bfjPFoTZYxMNLWUQJyrVwtbZg5gBMjTezGAJN",
+            )
+            .with_txt(
+                "_dnsaddr.ams.bootstrap.test",
+                "dnsaddr=/dns4/ams.bootstrap.test/tcp/4001/p2p/QmQCU2EcMqAqQPR2i9bChDtGNJchTbq5TbXJJ16u19uLTa",
+            )
+            .with_txt("_dnsaddr.loop.test", "dnsaddr=/dnsaddr/loop.test");
+
+        task::block_on(async {
+            let addr: Multiaddr = "/dnsaddr/bootstrap.test".parse().unwrap();
+            let resolved = resolve_dnsaddr(&resolver, addr, DEFAULT_MAX_DNSADDR_DEPTH).await;
+            let expected: Vec<Multiaddr> = vec![
+                "/ip4/10.0.0.1/tcp/4001/p2p/QmNnooDu7bfjPFoTZYxMNLWUQJyrVwtbZg5gBMjTezGAJN"
+                    .parse()
+                    .unwrap(),
+                "/dns4/ams.bootstrap.test/tcp/4001/p2p/QmQCU2EcMqAqQPR2i9bChDtGNJchTbq5TbXJJ16u19uLTa"
+                    .parse()
+                    .unwrap(),
+            ];
+            assert_eq!(resolved, expected);
+
+            // filtered by the peer Id
+            let addr: Multiaddr = "/dnsaddr/bootstrap.test/p2p/QmNnooDu7bfjPFoTZYxMNLWUQJyrVwtbZg5gBMjTezGAJN"
+                .parse()
+                .unwrap();
+            let resolved = resolve_dnsaddr(&resolver, addr, DEFAULT_MAX_DNSADDR_DEPTH).await;
+            assert_eq!(resolved, expected[..1].to_vec());
+
+            // limited by the depth
+            let addr: Multiaddr = "/dnsaddr/bootstrap.test".parse().unwrap();
+            assert!(resolve_dnsaddr(&resolver, addr, 1).await.is_empty());
+            let addr: Multiaddr = "/dnsaddr/loop.test".parse().unwrap();
+            assert!(resolve_dnsaddr(&resolver, addr, DEFAULT_MAX_DNSADDR_DEPTH).await.is_empty());
+        });
+    }
+}
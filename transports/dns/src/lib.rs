@@ -32,35 +32,140 @@
 //! Whenever we want to dial an address through the `DnsConfig` and that address contains a
 //! `/dns/`, `/dns4/`, or `/dns6/` component, a DNS resolve will be performed and the component
 //! will be replaced with `/ip4/` and/or `/ip6/` components.
+//!
+//! All the resolved IP addresses are dialed in the order of happy eyeballs (RFC 8305). The
+//! next connection attempt is started once the previous one failed, or 250 ms after it was
+//! started. The first connection established wins, and the other attempts are cancelled.
+//! The resolved addresses are cached for a configurable TTL. The DNS components in the
+//! addresses to listen on are resolved as well.
+//!
+//! `/dnsaddr/` components are resolved through the TXT records of `_dnsaddr.<name>`, which
+//! is what the bootstrap lists such as `/dnsaddr/bootstrap.libp2p.io` are made of.
+//!
+//! The names are looked up through a `Resolver`, which is selected per `DnsConfig` with
+//! `DnsConfig::with_resolver`:
+//!
+//! - `SystemResolver`, the resolver of the operating system, which is the default. It
+//!   resolves `/dnsaddr/` through the name servers in `/etc/resolv.conf` on unix, or through
+//!   the fallback `NameServerResolver` configured, if any.
+//! - `NameServerResolver`, which talks to the given name servers over UDP or TCP, regardless
+//!   of the configuration of the operating system.
+//! - `StaticResolver`, which resolves from a static map of the records, e.g. in the tests.
+
+mod cache;
+mod dnsaddr;
+mod nameserver;
+mod resolver;
+
+pub use dnsaddr::DEFAULT_MAX_DNSADDR_DEPTH;
+pub use nameserver::{NameServerProtocol, NameServerResolver, DEFAULT_QUERY_TIMEOUT};
+pub use resolver::{Resolver, StaticResolver, SystemResolver};
 
 use async_trait::async_trait;
+use futures::future::{self, Either};
+use futures::stream::{FuturesUnordered, StreamExt};
 use libp2prs_core::transport::{IListener, ITransport};
 use libp2prs_core::{
     multiaddr::{protocol, protocol::Protocol, Multiaddr},
     transport::TransportError,
-    Transport,
+    PeerId, Transport,
 };
-use libp2prs_runtime::net;
+use libp2prs_runtime::task;
 use log::{error, trace};
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
 use std::{error, fmt, io};
 
+use crate::cache::DnsCache;
+use crate::dnsaddr::{has_dnsaddr, resolve_dnsaddr};
+
+/// The default TTL of the resolved addresses in the cache.
+pub const DEFAULT_CACHE_TTL: Duration = Duration::from_secs(60);
+
+/// The delay before starting the next connection attempt, recommended by RFC 8305.
+const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
 /// Represents the configuration for a DNS transport capability of libp2p.
 ///
 /// This struct implements the `Transport` trait and holds an underlying transport. Any call to
-/// `dial` with a multiaddr that contains `/dns/`, `/dns4/`, or `/dns6/` will be first be resolved,
-/// then passed to the underlying transport.
-///
-/// Listening is unaffected.
+/// `dial` or `listen_on` with a multiaddr that contains `/dns/`, `/dns4/`, or `/dns6/` will be
+/// first be resolved, then passed to the underlying transport. `/dnsaddr/` is resolved when
+/// dialing only.
 #[derive(Clone)]
 pub struct DnsConfig<T> {
     /// Underlying transport to use once the DNS addresses have been resolved.
     inner: T,
+    /// The resolver to look up the names.
+    resolver: Arc<dyn Resolver>,
+    /// The resolved addresses.
+    cache: DnsCache,
+    /// The maximum depth of the recursive resolution of `/dnsaddr/`.
+    max_dnsaddr_depth: usize,
 }
 
 impl<T> DnsConfig<T> {
     /// Creates a new configuration object for DNS.
     pub fn new(inner: T) -> Self {
-        DnsConfig { inner }
+        DnsConfig {
+            inner,
+            resolver: Arc::new(SystemResolver::new()),
+            cache: DnsCache::new(DEFAULT_CACHE_TTL),
+            max_dnsaddr_depth: DEFAULT_MAX_DNSADDR_DEPTH,
+        }
+    }
+
+    /// Sets the resolver to look up the names, which is `SystemResolver` by default.
+    pub fn with_resolver<R: Resolver + 'static>(mut self, resolver: R) -> Self {
+        self.resolver = Arc::new(resolver);
+        self
+    }
+
+    /// Sets the maximum depth of the recursive resolution of `/dnsaddr/`.
+    pub fn with_max_dnsaddr_depth(mut self, depth: usize) -> Self {
+        self.max_dnsaddr_depth = depth;
+        self
+    }
+
+    /// Sets the TTL of the resolved addresses in the cache. A TTL of zero disables the cache.
+    ///
+    /// The cache is shared by the clones of the `DnsConfig`.
+    pub fn with_cache_ttl(mut self, ttl: Duration) -> Self {
+        self.cache = DnsCache::new(ttl);
+        self
+    }
+
+    /// Resolves the `/dnsaddr/` components of `addr` recursively. The other DNS components
+    /// are left as they are.
+    ///
+    /// The components after `/dnsaddr/` are used as a filter, e.g. only the addresses
+    /// ending with `/p2p/<id>` are returned for `/dnsaddr/<name>/p2p/<id>`.
+    pub async fn resolve_dnsaddr(&self, addr: &Multiaddr) -> Result<Vec<Multiaddr>, TransportError> {
+        let resolved = resolve_dnsaddr(&*self.resolver, addr.clone(), self.max_dnsaddr_depth).await;
+        if resolved.is_empty() {
+            return Err(TransportError::ResolveFail(addr.to_string()));
+        }
+        Ok(resolved)
+    }
+
+    /// Resolves `addr` like `resolve_dnsaddr`, and splits the peer Ids off the addresses.
+    /// The addresses without a peer Id are skipped.
+    ///
+    /// The result can be used to bootstrap Kademlia:
+    ///
+    /// ```ignore
+    /// let peers = dns.resolve_peers(&"/dnsaddr/bootstrap.libp2p.io".parse().unwrap()).await?;
+    /// kad_control.bootstrap(peers).await;
+    /// ```
+    pub async fn resolve_peers(&self, addr: &Multiaddr) -> Result<Vec<(PeerId, Multiaddr)>, TransportError> {
+        let resolved = self.resolve_dnsaddr(addr).await?;
+        Ok(resolved
+            .into_iter()
+            .filter_map(|mut addr| match addr.pop() {
+                Some(Protocol::P2p(mh)) => PeerId::from_multihash(mh).ok().map(|pid| (pid, addr)),
+                _ => None,
+            })
+            .collect())
     }
 }
 
@@ -81,66 +186,102 @@ where
     type Output = T::Output;
 
     fn listen_on(&mut self, addr: Multiaddr) -> Result<IListener<Self::Output>, TransportError> {
-        self.inner.listen_on(addr)
+        let (index, name, dns4, dns6) = match find_dns(&addr) {
+            Some(found) => found,
+            None => return self.inner.listen_on(addr),
+        };
+
+        // Only one listener can be returned, so it listens on the first address.
+        let ips = match self.cache.get(&name) {
+            Some(ips) => ips,
+            None => {
+                let ips = self.resolver.lookup_ip_blocking(&name).map_err(|e| {
+                    error!("failed to resolve {}: {:?}", name, e);
+                    TransportError::ResolveFail(name.clone())
+                })?;
+                self.cache.insert(name.clone(), ips.clone());
+                ips
+            }
+        };
+        let ip = filter_and_sort(&name, ips, dns4, dns6)?[0];
+        match addr.replace(index, |_| Some(Protocol::from(ip))) {
+            Some(addr) => self.inner.listen_on(addr),
+            None => Err(TransportError::ResolveFail(name)),
+        }
     }
 
     async fn dial(&mut self, addr: Multiaddr) -> Result<Self::Output, TransportError> {
-        // one step complete all runtime
-        let mut iter = addr.iter();
-        let proto = iter.find_map(|x| match x {
-            Protocol::Dns(name) => Some((name, true, true)),
-            Protocol::Dns4(name) => Some((name, true, false)),
-            Protocol::Dns6(name) => Some((name, false, true)),
-            _ => None,
-        });
-
-        let index = addr.iter().count() - iter.count() - 1;
-
-        // As an optimization, we immediately pass through if no component of the address contain
-        // a DNS protocol.
-        let (name, dns4, dns6) = match proto {
-            Some((name, dns4, dns6)) => (name, dns4, dns6),
+        let dnsaddr = has_dnsaddr(&addr);
+        let name = match dns_name(&addr) {
+            Some(name) => name,
             None => {
+                // As an optimization, we immediately pass through if no component of the
+                // address contain a DNS protocol.
                 trace!("Pass-through address without DNS: {}", addr);
                 return self.inner.dial(addr).await;
             }
         };
 
-        let name = name.to_string();
-        let to_resolve = format!("{}:0", name);
+        let resolver = self.resolver.clone();
+        let cache = self.cache.clone();
 
-        let list = net::resolve_host(to_resolve).await.map_err(|_| {
-            error!("DNS resolver crashed");
-            TransportError::ResolveFail(name.clone())
-        })?;
-        let list = list.map(|s| s.ip()).collect::<Vec<_>>();
+        // `/dnsaddr/` is resolved to the addresses with the peer Ids, which are dialed
+        // without the peer Ids.
+        let candidates = if dnsaddr {
+            let resolved = resolve_dnsaddr(&*resolver, addr, self.max_dnsaddr_depth).await;
+            if resolved.is_empty() {
+                return Err(TransportError::ResolveFail(name));
+            }
+            resolved
+                .into_iter()
+                .map(|mut addr| {
+                    if let Some(Protocol::P2p(_)) = addr.iter().last() {
+                        addr.pop();
+                    }
+                    addr
+                })
+                .collect()
+        } else {
+            vec![addr]
+        };
 
-        let outcome = list
-            .into_iter()
-            .filter_map(|addr| {
-                if (dns4 && addr.is_ipv4()) || (dns6 && addr.is_ipv6()) {
-                    Some(Protocol::from(addr))
-                } else {
-                    None
+        let mut attempts = vec![];
+        let mut resolved_names = vec![];
+        let mut addrs = vec![];
+        for candidate in candidates {
+            match find_dns(&candidate) {
+                Some((index, name, dns4, dns6)) => {
+                    let ips = match resolve(&cache, &*resolver, &name, dns4, dns6).await {
+                        Ok(ips) => ips,
+                        Err(e) if dnsaddr => {
+                            attempts.push((candidate, e));
+                            continue;
+                        }
+                        Err(e) => return Err(e),
+                    };
+                    resolved_names.push(name);
+                    addrs.extend(
+                        ips.into_iter()
+                            .filter_map(|ip| candidate.replace(index, |_| Some(Protocol::from(ip)))),
+                    );
                 }
-            })
-            .next()
-            .ok_or_else(|| TransportError::ResolveFail(name.clone()))?;
-
-        // let iter = addr.iter().map( |x| match x {
-        //     Protocol::Dns(_) | Protocol::Dns4(_) | Protocol::Dns6(_) => {
-        //         outcome.clone()
-        //     }
-        //     proto => proto,
-        // });
-        //
-        // let addr = Multiaddr::from_iter(iter);
-
-        if let Some(addr) = addr.replace(index, |_| Some(outcome.clone())) {
-            return self.inner.dial(addr).await;
+                None => addrs.push(candidate),
+            }
+        }
+
+        if let Some(output) = dial_staggered(self.inner.clone(), addrs, &mut attempts).await {
+            return Ok(output);
         }
 
-        Err(TransportError::ResolveFail(name))
+        // The addresses might be stale, resolve them again next time.
+        for name in resolved_names {
+            cache.remove(&name);
+        }
+        Err(DnsErr::DialFailed {
+            domain_name: name,
+            attempts,
+        }
+        .into())
     }
 
     fn box_clone(&self) -> ITransport<Self::Output> {
@@ -150,10 +291,120 @@ where
     fn protocols(&self) -> Vec<u32> {
         let mut p = self.inner.protocols();
         p.push(protocol::DNS);
+        p.push(protocol::DNSADDR);
         p
     }
 }
 
+// Dials the addresses in order, the next attempt is started once the previous one failed
+// or after `CONNECTION_ATTEMPT_DELAY`. Returns the first connection established, the
+// other attempts are cancelled by being dropped. The failed attempts are appended to
+// `attempts`, in the order they failed.
+async fn dial_staggered<T>(inner: T, addrs: Vec<Multiaddr>, attempts: &mut Vec<(Multiaddr, TransportError)>) -> Option<T::Output>
+where
+    T: Transport + Clone,
+{
+    let mut pending = addrs.into_iter();
+    let mut dials = FuturesUnordered::new();
+    loop {
+        if let Some(addr) = pending.next() {
+            let mut inner = inner.clone();
+            dials.push(async move {
+                let r = inner.dial(addr.clone()).await;
+                (addr, r)
+            });
+        }
+
+        let finished = if pending.as_slice().is_empty() {
+            dials.next().await
+        } else {
+            match future::select(dials.next(), Box::pin(task::sleep(CONNECTION_ATTEMPT_DELAY))).await {
+                Either::Left((finished, _)) => finished,
+                // start the next attempt
+                Either::Right(_) => continue,
+            }
+        };
+
+        match finished {
+            Some((_, Ok(output))) => return Some(output),
+            Some((addr, Err(e))) => {
+                trace!("failed to dial {}: {:?}", addr, e);
+                attempts.push((addr, e));
+            }
+            // all attempts failed
+            None => return None,
+        }
+    }
+}
+
+// Resolves `name` to the IP addresses of the families wanted, in the order to dial.
+async fn resolve(
+    cache: &DnsCache,
+    resolver: &dyn Resolver,
+    name: &str,
+    dns4: bool,
+    dns6: bool,
+) -> Result<Vec<IpAddr>, TransportError> {
+    let ips = match cache.get(name) {
+        Some(ips) => ips,
+        None => {
+            let ips = resolver.lookup_ip(name).await.map_err(|e| {
+                error!("failed to resolve {}: {:?}", name, e);
+                TransportError::ResolveFail(name.to_string())
+            })?;
+            cache.insert(name.to_string(), ips.clone());
+            ips
+        }
+    };
+    filter_and_sort(name, ips, dns4, dns6)
+}
+
+// Returns the name of the first DNS component of `addr`, including `/dnsaddr/`.
+fn dns_name(addr: &Multiaddr) -> Option<String> {
+    addr.iter().find_map(|p| match p {
+        Protocol::Dns(name) | Protocol::Dns4(name) | Protocol::Dns6(name) | Protocol::Dnsaddr(name) => Some(name.to_string()),
+        _ => None,
+    })
+}
+
+// Returns the index and the name of the first DNS component of `addr`, and whether it
+// is resolved to IPv4 and IPv6 addresses.
+fn find_dns(addr: &Multiaddr) -> Option<(usize, String, bool, bool)> {
+    addr.iter().enumerate().find_map(|(index, p)| match p {
+        Protocol::Dns(name) => Some((index, name.to_string(), true, true)),
+        Protocol::Dns4(name) => Some((index, name.to_string(), true, false)),
+        Protocol::Dns6(name) => Some((index, name.to_string(), false, true)),
+        _ => None,
+    })
+}
+
+// Filters the IP addresses by the families wanted, and sorts them in the order of happy
+// eyeballs, i.e. alternating between the families, starting with IPv6.
+fn filter_and_sort(name: &str, ips: Vec<IpAddr>, dns4: bool, dns6: bool) -> Result<Vec<IpAddr>, TransportError> {
+    let mut v4 = vec![];
+    let mut v6 = vec![];
+    for ip in ips {
+        match ip {
+            IpAddr::V4(_) if dns4 && !v4.contains(&ip) => v4.push(ip),
+            IpAddr::V6(_) if dns6 && !v6.contains(&ip) => v6.push(ip),
+            _ => {}
+        }
+    }
+    if v4.is_empty() && v6.is_empty() {
+        return Err(TransportError::ResolveFail(name.to_string()));
+    }
+
+    let mut sorted = Vec::with_capacity(v4.len() + v6.len());
+    let (mut v4, mut v6) = (v4.into_iter(), v6.into_iter());
+    loop {
+        match (v6.next(), v4.next()) {
+            (None, None) => break,
+            (a, b) => sorted.extend(a.into_iter().chain(b)),
+        }
+    }
+    Ok(sorted)
+}
+
 /// Error that can be generated by the DNS layer.
 #[derive(Debug)]
 pub enum DnsErr {
@@ -163,6 +414,11 @@ pub enum DnsErr {
     ResolveError { domain_name: String, error: io::Error },
     /// Found an IP address, but the underlying transport doesn't support the multiaddr.
     MultiaddrNotSupported,
+    /// Failed to dial every address resolved.
+    DialFailed {
+        domain_name: String,
+        attempts: Vec<(Multiaddr, TransportError)>,
+    },
 }
 
 impl fmt::Display for DnsErr {
@@ -171,6 +427,13 @@ impl fmt::Display for DnsErr {
             DnsErr::ResolveFail(addr) => write!(f, "Failed to resolve DNS address: {:?}", addr),
             DnsErr::ResolveError { domain_name, error } => write!(f, "Failed to resolve DNS address: {:?}; {:?}", domain_name, error),
             DnsErr::MultiaddrNotSupported => write!(f, "Resolve multiaddr not supported"),
+            DnsErr::DialFailed { domain_name, attempts } => {
+                write!(f, "Failed to dial {:?}, attempted:", domain_name)?;
+                for (addr, error) in attempts {
+                    write!(f, " {} ({});", addr, error)?;
+                }
+                Ok(())
+            }
         }
     }
 }
@@ -181,19 +444,184 @@ impl error::Error for DnsErr {
             DnsErr::ResolveFail(_) => None,
             DnsErr::ResolveError { error, .. } => Some(error),
             DnsErr::MultiaddrNotSupported => None,
+            DnsErr::DialFailed { attempts, .. } => attempts.last().map(|(_, e)| e as &(dyn error::Error + 'static)),
         }
     }
 }
 
+impl From<DnsErr> for TransportError {
+    fn from(e: DnsErr) -> Self {
+        TransportError::DnsError(Box::new(e))
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::DnsConfig;
-    use libp2prs_core::transport::ListenerEvent;
+    use super::{filter_and_sort, DnsConfig, DnsErr, StaticResolver};
+    use async_trait::async_trait;
+    use libp2prs_core::transport::{IListener, ITransport, ListenerEvent, TransportError};
     use libp2prs_core::Transport;
     use libp2prs_multiaddr::Multiaddr;
     use libp2prs_runtime::task;
     use libp2prs_tcp::TcpConfig;
     use libp2prs_traits::{ReadEx, WriteEx};
+    use std::net::IpAddr;
+    use std::time::{Duration, Instant};
+
+    // A transport whose dialing to 127.0.0.3 never completes, and succeeds otherwise.
+    #[derive(Clone)]
+    struct HangingTransport;
+
+    #[async_trait]
+    impl Transport for HangingTransport {
+        type Output = Multiaddr;
+
+        fn listen_on(&mut self, addr: Multiaddr) -> Result<IListener<Self::Output>, TransportError> {
+            Err(TransportError::MultiaddrNotSupported(addr))
+        }
+
+        async fn dial(&mut self, addr: Multiaddr) -> Result<Self::Output, TransportError> {
+            if addr.to_string().starts_with("/ip4/127.0.0.3/") {
+                futures::future::pending::<()>().await;
+            }
+            Ok(addr)
+        }
+
+        fn box_clone(&self) -> ITransport<Self::Output> {
+            Box::new(self.clone())
+        }
+
+        fn protocols(&self) -> Vec<u32> {
+            vec![]
+        }
+    }
+
+    #[test]
+    fn happy_eyeballs_order() {
+        let ips: Vec<IpAddr> = ["10.0.0.1", "10.0.0.2", "10.0.0.3", "::1", "::2", "10.0.0.1"]
+            .iter()
+            .map(|ip| ip.parse().unwrap())
+            .collect();
+
+        let sorted = filter_and_sort("example.com", ips.clone(), true, true).unwrap();
+        let expected: Vec<IpAddr> = ["::1", "10.0.0.1", "::2", "10.0.0.2", "10.0.0.3"]
+            .iter()
+            .map(|ip| ip.parse().unwrap())
+            .collect();
+        assert_eq!(sorted, expected);
+
+        assert_eq!(filter_and_sort("example.com", ips.clone(), true, false).unwrap().len(), 3);
+        assert!(filter_and_sort("example.com", ips[..3].to_vec(), false, true).is_err());
+    }
+
+    #[test]
+    fn dial_every_address() {
+        task::block_on(async move {
+            let listen_addr: Multiaddr = "/ip4/127.0.0.1/tcp/8385".parse().unwrap();
+            let addr: Multiaddr = "/dns4/example.test/tcp/8385".parse().unwrap();
+            let mut transport = DnsConfig::new(TcpConfig::default());
+            let mut client = transport.clone();
+
+            // nothing is listening on the first address
+            let ips = vec!["127.0.0.3".parse().unwrap(), "127.0.0.1".parse().unwrap()];
+            client.cache.insert("example.test".to_string(), ips);
+
+            let mut listener = transport.listen_on(listen_addr).unwrap();
+            let handle = task::spawn(async move {
+                let _ = listener.accept().await.unwrap();
+            });
+
+            let _conn = client.dial(addr).await.expect("client dial");
+            handle.await;
+        });
+    }
+
+    #[test]
+    fn dial_staggered() {
+        task::block_on(async move {
+            let addr: Multiaddr = "/dns4/example.test/tcp/8388".parse().unwrap();
+            let mut client = DnsConfig::new(HangingTransport);
+            let ips = vec!["127.0.0.3".parse().unwrap(), "127.0.0.1".parse().unwrap()];
+            client.cache.insert("example.test".to_string(), ips);
+
+            // the second address is dialed while the first attempt is still pending
+            let start = Instant::now();
+            let dialed = task::timeout(Duration::from_secs(5), client.dial(addr)).await;
+            assert_eq!(dialed.unwrap().unwrap().to_string(), "/ip4/127.0.0.1/tcp/8388");
+            assert!(start.elapsed() >= Duration::from_millis(250));
+        });
+    }
+
+    #[test]
+    fn dial_failed_reports_attempts() {
+        task::block_on(async move {
+            let addr: Multiaddr = "/dns4/example.test/tcp/8386".parse().unwrap();
+            let mut client = DnsConfig::new(TcpConfig::default());
+            let ips = vec!["127.0.0.3".parse().unwrap(), "127.0.0.4".parse().unwrap()];
+            client.cache.insert("example.test".to_string(), ips);
+
+            match client.dial(addr).await {
+                Ok(_) => panic!("dial must fail"),
+                Err(TransportError::DnsError(e)) => match e.downcast_ref::<DnsErr>() {
+                    Some(DnsErr::DialFailed { domain_name, attempts }) => {
+                        assert_eq!(domain_name, "example.test");
+                        let attempted = attempts.iter().map(|(a, _)| a.to_string()).collect::<Vec<_>>();
+                        assert_eq!(attempted, vec!["/ip4/127.0.0.3/tcp/8386", "/ip4/127.0.0.4/tcp/8386"]);
+                    }
+                    e => panic!("unexpected error {:?}", e),
+                },
+                Err(e) => panic!("unexpected error {:?}", e),
+            }
+            // the stale addresses are removed from the cache
+            assert!(client.cache.get("example.test").is_none());
+        });
+    }
+
+    #[test]
+    fn dial_dnsaddr() {
+        task::block_on(async move {
+            let pid = "QmNnooDu7bfjPFoTZYxMNLWUQJyrVwtbZg5gBMjTezGAJN";
+            let resolver = StaticResolver::new()
+                .with_txt("_dnsaddr.node.test", format!("dnsaddr=/ip4/127.0.0.3/tcp/8387/p2p/{}", pid))
+                .with_txt("_dnsaddr.node.test", format!("dnsaddr=/dns4/node.test/tcp/8387/p2p/{}", pid))
+                .with_host("node.test", "127.0.0.1".parse().unwrap());
+            let listen_addr: Multiaddr = "/ip4/127.0.0.1/tcp/8387".parse().unwrap();
+            let mut transport = DnsConfig::new(TcpConfig::default()).with_resolver(resolver);
+            let mut client = transport.clone();
+
+            let peers = client.resolve_peers(&"/dnsaddr/node.test".parse().unwrap()).await.unwrap();
+            let addrs = peers.iter().map(|(_, a)| a.to_string()).collect::<Vec<_>>();
+            assert_eq!(addrs, vec!["/ip4/127.0.0.3/tcp/8387", "/dns4/node.test/tcp/8387"]);
+            assert!(peers.iter().all(|(p, _)| p.to_string() == pid));
+
+            let mut listener = transport.listen_on(listen_addr).unwrap();
+            let handle = task::spawn(async move {
+                let _ = listener.accept().await.unwrap();
+            });
+
+            let addr: Multiaddr = format!("/dnsaddr/node.test/p2p/{}", pid).parse().unwrap();
+            let _conn = client.dial(addr).await.expect("client dial");
+            handle.await;
+
+            let addr: Multiaddr = "/dnsaddr/unknown.test".parse().unwrap();
+            assert!(client.dial(addr).await.is_err());
+        });
+    }
+
+    #[test]
+    fn listen_on_dns() {
+        let addr: Multiaddr = "/dns4/localhost/tcp/0".parse().unwrap();
+        let mut transport = DnsConfig::new(TcpConfig::default());
+        let listener = transport.listen_on(addr).unwrap();
+        let listened = listener.multi_addr().unwrap().to_string();
+        assert!(listened.starts_with("/ip4/127.0.0.1/tcp/"));
+
+        let addr: Multiaddr = "/dns4/node.test/tcp/0".parse().unwrap();
+        let resolver = StaticResolver::new().with_host("node.test", "127.0.0.1".parse().unwrap());
+        let mut transport = DnsConfig::new(TcpConfig::default()).with_resolver(resolver);
+        let listener = transport.listen_on(addr).unwrap();
+        assert!(listener.multi_addr().unwrap().to_string().starts_with("/ip4/127.0.0.1/tcp/"));
+    }
 
     #[test]
     fn basic_resolve_v4() {
@@ -0,0 +1,611 @@
+// Copyright 2020 Netwarps Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! A resolver which talks to the name servers directly, over UDP or TCP.
+
+use async_trait::async_trait;
+use dns_parser::{Builder, Packet, QueryClass, QueryType, RData, ResponseCode};
+use futures::{AsyncReadExt, AsyncWriteExt};
+use libp2prs_runtime::{
+    net::{TcpStream, UdpSocket},
+    task,
+};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::{Duration, Instant};
+
+use crate::resolver::Resolver;
+
+/// The default timeout of a query to a name server.
+pub const DEFAULT_QUERY_TIMEOUT: Duration = Duration::from_secs(5);
+
+const RESOLV_CONF: &str = "/etc/resolv.conf";
+
+/// The transport protocol to talk to a name server.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NameServerProtocol {
+    /// UDP, falling back to TCP if the response is truncated.
+    Udp,
+    Tcp,
+}
+
+/// A resolver which sends the queries to a list of name servers, e.g. an internal resolver
+/// of split-horizon DNS, regardless of the configuration of the operating system.
+///
+/// The name servers are tried in the order they are added, until one of them responds.
+/// A name without any dot is qualified with the search domains first, as is done with
+/// the default `ndots:1` of resolv.conf.
+///
+/// ```
+/// use libp2prs_dns::NameServerResolver;
+///
+/// let resolver = NameServerResolver::new()
+///     .with_udp("10.0.0.53:53".parse().unwrap())
+///     .with_tcp("10.0.1.53:53".parse().unwrap());
+/// ```
+#[derive(Clone, Debug)]
+pub struct NameServerResolver {
+    servers: Vec<(SocketAddr, NameServerProtocol)>,
+    search: Vec<String>,
+    timeout: Duration,
+}
+
+impl Default for NameServerResolver {
+    fn default() -> Self {
+        NameServerResolver::new()
+    }
+}
+
+impl NameServerResolver {
+    /// Creates a resolver without any name server.
+    pub fn new() -> Self {
+        NameServerResolver {
+            servers: vec![],
+            search: vec![],
+            timeout: DEFAULT_QUERY_TIMEOUT,
+        }
+    }
+
+    /// Creates a resolver with the name servers in `/etc/resolv.conf`, over UDP, and the
+    /// search domains of its `search` or `domain` entry.
+    pub fn from_system_conf() -> io::Result<Self> {
+        let conf = std::fs::read_to_string(RESOLV_CONF)?;
+        let (servers, search) = parse_resolv_conf(&conf);
+        if servers.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "no name server in resolv.conf"));
+        }
+        let resolver = servers.into_iter().fold(NameServerResolver::new(), |r, addr| r.with_udp(addr));
+        Ok(search.iter().fold(resolver, |r, domain| r.with_search_domain(domain)))
+    }
+
+    /// Adds a name server talked to over UDP.
+    pub fn with_udp(mut self, addr: SocketAddr) -> Self {
+        self.servers.push((addr, NameServerProtocol::Udp));
+        self
+    }
+
+    /// Adds a name server talked to over TCP.
+    pub fn with_tcp(mut self, addr: SocketAddr) -> Self {
+        self.servers.push((addr, NameServerProtocol::Tcp));
+        self
+    }
+
+    /// Adds a search domain, which qualifies the names without any dot.
+    pub fn with_search_domain(mut self, domain: &str) -> Self {
+        self.search.push(domain.trim_matches('.').to_string());
+        self
+    }
+
+    /// Sets the timeout of a query to a name server.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    // Returns the names to query for `name`. A name without any dot is qualified with the
+    // search domains, before it is queried as it is.
+    fn candidates(&self, name: &str) -> Vec<String> {
+        let mut names = vec![];
+        if !name.contains('.') {
+            names.extend(self.search.iter().map(|domain| format!("{}.{}", name, domain)));
+        }
+        names.push(name.to_string());
+        names
+    }
+
+    // Queries the candidates of `name` one by one, until one of them exists.
+    async fn query(&self, name: &str, qtype: QueryType) -> io::Result<Vec<Answer>> {
+        let mut last_error = io::Error::from(io::ErrorKind::NotFound);
+        for name in self.candidates(name) {
+            match self.query_name(&name, qtype).await {
+                Err(e) if e.kind() == io::ErrorKind::NotFound => last_error = e,
+                r => return r,
+            }
+        }
+        Err(last_error)
+    }
+
+    // The same as `query`, through the blocking sockets, which don't rely on any runtime.
+    fn query_blocking(&self, name: &str, qtype: QueryType) -> io::Result<Vec<Answer>> {
+        let mut last_error = io::Error::from(io::ErrorKind::NotFound);
+        for name in self.candidates(name) {
+            match self.query_name_blocking(&name, qtype) {
+                Err(e) if e.kind() == io::ErrorKind::NotFound => last_error = e,
+                r => return r,
+            }
+        }
+        Err(last_error)
+    }
+
+    // Sends the query to the name servers one by one, and returns the answers of the first
+    // response.
+    async fn query_name(&self, name: &str, qtype: QueryType) -> io::Result<Vec<Answer>> {
+        let (id, query) = build_query(name, qtype)?;
+        let mut last_error = io::Error::new(io::ErrorKind::NotFound, "no name server");
+        for (server, protocol) in self.servers.iter() {
+            let r = match protocol {
+                NameServerProtocol::Udp => self.exchange_udp(&query, id, server).await,
+                NameServerProtocol::Tcp => self.exchange_tcp(&query, server).await,
+            };
+            match handle_response(r, id, server, name) {
+                Ok(Some(answers)) => return Ok(answers),
+                Ok(None) => return Err(io::ErrorKind::NotFound.into()),
+                Err(e) => last_error = e,
+            }
+        }
+        Err(last_error)
+    }
+
+    // The same as `query_name`, through the blocking sockets.
+    fn query_name_blocking(&self, name: &str, qtype: QueryType) -> io::Result<Vec<Answer>> {
+        let (id, query) = build_query(name, qtype)?;
+        let mut last_error = io::Error::new(io::ErrorKind::NotFound, "no name server");
+        for (server, protocol) in self.servers.iter() {
+            let r = match protocol {
+                NameServerProtocol::Udp => self.exchange_udp_blocking(&query, id, server),
+                NameServerProtocol::Tcp => self.exchange_tcp_blocking(&query, server),
+            };
+            match handle_response(r, id, server, name) {
+                Ok(Some(answers)) => return Ok(answers),
+                Ok(None) => return Err(io::ErrorKind::NotFound.into()),
+                Err(e) => last_error = e,
+            }
+        }
+        Err(last_error)
+    }
+
+    async fn exchange_udp(&self, query: &[u8], id: u16, server: &SocketAddr) -> io::Result<Vec<u8>> {
+        let socket = UdpSocket::bind(unspecified(server)).await?;
+        socket.send_to(query, *server).await?;
+
+        let exchange = async {
+            let mut buf = [0; 4096];
+            loop {
+                let (len, from) = socket.recv_from(&mut buf).await?;
+                if is_response(&buf[..len], &from, id, server) {
+                    return Ok::<_, io::Error>(buf[..len].to_vec());
+                }
+            }
+        };
+        let response = task::timeout(self.timeout, exchange)
+            .await
+            .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??;
+
+        if is_truncated(&response) {
+            log::debug!("truncated response from {}, retry over TCP", server);
+            self.exchange_tcp(query, server).await
+        } else {
+            Ok(response)
+        }
+    }
+
+    async fn exchange_tcp(&self, query: &[u8], server: &SocketAddr) -> io::Result<Vec<u8>> {
+        let exchange = async {
+            let mut stream = TcpStream::connect(*server).await?;
+            stream.write_all(&frame(query)).await?;
+
+            let mut len = [0; 2];
+            stream.read_exact(&mut len).await?;
+            let mut response = vec![0; u16::from_be_bytes(len) as usize];
+            stream.read_exact(&mut response).await?;
+            Ok::<_, io::Error>(response)
+        };
+        task::timeout(self.timeout, exchange)
+            .await
+            .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))?
+    }
+
+    fn exchange_udp_blocking(&self, query: &[u8], id: u16, server: &SocketAddr) -> io::Result<Vec<u8>> {
+        let socket = std::net::UdpSocket::bind(unspecified(server))?;
+        socket.send_to(query, server)?;
+
+        let deadline = Instant::now() + self.timeout;
+        let mut buf = [0; 4096];
+        let response = loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left == Duration::from_secs(0) {
+                return Err(io::ErrorKind::TimedOut.into());
+            }
+            socket.set_read_timeout(Some(left))?;
+            let (len, from) = socket.recv_from(&mut buf).map_err(timed_out)?;
+            if is_response(&buf[..len], &from, id, server) {
+                break buf[..len].to_vec();
+            }
+        };
+
+        if is_truncated(&response) {
+            log::debug!("truncated response from {}, retry over TCP", server);
+            self.exchange_tcp_blocking(query, server)
+        } else {
+            Ok(response)
+        }
+    }
+
+    fn exchange_tcp_blocking(&self, query: &[u8], server: &SocketAddr) -> io::Result<Vec<u8>> {
+        use std::io::{Read, Write};
+
+        let mut stream = std::net::TcpStream::connect_timeout(server, self.timeout)?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+        stream.write_all(&frame(query)).map_err(timed_out)?;
+
+        let mut len = [0; 2];
+        stream.read_exact(&mut len).map_err(timed_out)?;
+        let mut response = vec![0; u16::from_be_bytes(len) as usize];
+        stream.read_exact(&mut response).map_err(timed_out)?;
+        Ok(response)
+    }
+}
+
+#[async_trait]
+impl Resolver for NameServerResolver {
+    async fn lookup_ip(&self, name: &str) -> io::Result<Vec<IpAddr>> {
+        let v4 = self.query(name, QueryType::A).await;
+        let v6 = self.query(name, QueryType::AAAA).await;
+        ips_of(v4, v6)
+    }
+
+    async fn lookup_txt(&self, name: &str) -> io::Result<Vec<String>> {
+        let answers = self.query(name, QueryType::TXT).await?;
+        Ok(answers
+            .into_iter()
+            .filter_map(|a| match a {
+                Answer::Txt(txt) => Some(txt),
+                Answer::Ip(_) => None,
+            })
+            .collect())
+    }
+
+    fn lookup_ip_blocking(&self, name: &str) -> io::Result<Vec<IpAddr>> {
+        let v4 = self.query_blocking(name, QueryType::A);
+        let v6 = self.query_blocking(name, QueryType::AAAA);
+        ips_of(v4, v6)
+    }
+}
+
+// Builds the query of `name`, returns the query Id along with the message.
+fn build_query(name: &str, qtype: QueryType) -> io::Result<(u16, Vec<u8>)> {
+    let id = rand::random::<u16>();
+    let mut builder = Builder::new_query(id, true);
+    builder.add_question(name, false, qtype, QueryClass::IN);
+    let query = builder
+        .build()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "the DNS query is truncated"))?;
+    Ok((id, query))
+}
+
+// Handles the response of `server`. Returns the answers, or `None` if the name doesn't
+// exist, in which case the other name servers are not asked. The error is returned if
+// the next name server is to be asked.
+fn handle_response(r: io::Result<Vec<u8>>, id: u16, server: &SocketAddr, name: &str) -> io::Result<Option<Vec<Answer>>> {
+    let response = match r {
+        Ok(response) => response,
+        Err(e) => {
+            log::debug!("failed to query {} of {}: {:?}", server, name, e);
+            return Err(e);
+        }
+    };
+    match parse_answers(&response, id) {
+        Ok(answers) => Ok(Some(answers)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => {
+            log::debug!("bad response from {} of {}: {:?}", server, name, e);
+            Err(e)
+        }
+    }
+}
+
+// Merges the answers of the A and AAAA queries, fails only if both failed.
+fn ips_of(v4: io::Result<Vec<Answer>>, v6: io::Result<Vec<Answer>>) -> io::Result<Vec<IpAddr>> {
+    let answers = match (v4, v6) {
+        (Err(e), Err(_)) => return Err(e),
+        (v4, v6) => v4.unwrap_or_default().into_iter().chain(v6.unwrap_or_default()),
+    };
+    Ok(answers
+        .filter_map(|a| match a {
+            Answer::Ip(ip) => Some(ip),
+            Answer::Txt(_) => None,
+        })
+        .collect())
+}
+
+// Returns the unspecified address to bind, of the same family as `server`.
+fn unspecified(server: &SocketAddr) -> SocketAddr {
+    match server {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    }
+}
+
+// Checks if the packet is the response to the query `id` from `server`, the packets from
+// the others or to the other queries are ignored.
+fn is_response(packet: &[u8], from: &SocketAddr, id: u16, server: &SocketAddr) -> bool {
+    from == server && packet.len() >= 2 && u16::from_be_bytes([packet[0], packet[1]]) == id
+}
+
+fn is_truncated(response: &[u8]) -> bool {
+    matches!(Packet::parse(response), Ok(packet) if packet.header.truncated)
+}
+
+// Prefixes the message with the length, as it is sent over TCP.
+fn frame(query: &[u8]) -> Vec<u8> {
+    let mut buf = (query.len() as u16).to_be_bytes().to_vec();
+    buf.extend_from_slice(query);
+    buf
+}
+
+// The blocking sockets fail with `WouldBlock` on timeout on unix.
+fn timed_out(e: io::Error) -> io::Error {
+    if e.kind() == io::ErrorKind::WouldBlock {
+        io::ErrorKind::TimedOut.into()
+    } else {
+        e
+    }
+}
+
+/// An answer in the response, owned.
+enum Answer {
+    Ip(IpAddr),
+    Txt(String),
+}
+
+// Parses the answers in the response to the query `id`.
+fn parse_answers(response: &[u8], id: u16) -> io::Result<Vec<Answer>> {
+    let packet = Packet::parse(response).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    if packet.header.id != id || packet.header.query {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not the response to the query"));
+    }
+    match packet.header.response_code {
+        ResponseCode::NoError => {}
+        ResponseCode::NameError => return Err(io::ErrorKind::NotFound.into()),
+        code => return Err(io::Error::new(io::ErrorKind::Other, format!("DNS error {:?}", code))),
+    }
+
+    Ok(packet
+        .answers
+        .iter()
+        .filter_map(|rr| match &rr.data {
+            RData::A(a) => Some(Answer::Ip(IpAddr::V4(a.0))),
+            RData::AAAA(a) => Some(Answer::Ip(IpAddr::V6(a.0))),
+            // the character strings of a record are concatenated
+            RData::TXT(txt) => {
+                let bytes = txt.iter().flat_map(|s| s.iter().cloned()).collect::<Vec<_>>();
+                Some(Answer::Txt(String::from_utf8_lossy(&bytes).into_owned()))
+            }
+            _ => None,
+        })
+        .collect())
+}
+
+// Returns the name servers and the search domains in the content of resolv.conf. The last
+// one of the `search` and `domain` entries wins, as is done by the system resolver.
+fn parse_resolv_conf(conf: &str) -> (Vec<SocketAddr>, Vec<String>) {
+    let mut servers = vec![];
+    let mut search = vec![];
+    for line in conf.lines() {
+        let mut words = line.split_whitespace();
+        match words.next() {
+            Some("nameserver") => {
+                if let Some(ip) = words.next().and_then(|ip| ip.parse::<IpAddr>().ok()) {
+                    servers.push(SocketAddr::new(ip, 53));
+                }
+            }
+            Some("search") => search = words.map(|d| d.to_string()).collect(),
+            Some("domain") => search = words.next().map(|d| d.to_string()).into_iter().collect(),
+            _ => {}
+        }
+    }
+    (servers, search)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libp2prs_runtime::net::TcpListener;
+
+    // Builds the response to `query`, with the A record of 10.0.0.1 and the TXT record of
+    // "dnsaddr=/ip4/10.0.0.1/tcp/4001". The names without any dot don't exist.
+    fn respond(query: &[u8], truncated: bool) -> Vec<u8> {
+        if query[13 + query[12] as usize] == 0 {
+            let mut resp = query.to_vec();
+            resp[2] |= 0x80;
+            resp[3] |= 0x03;
+            return resp;
+        }
+
+        let qtype = u16::from_be_bytes([query[query.len() - 4], query[query.len() - 3]]);
+        let rdata = match qtype {
+            1 => vec![10, 0, 0, 1],
+            16 => {
+                let txt = b"dnsaddr=/ip4/10.0.0.1/tcp/4001";
+                let mut rdata = vec![txt.len() as u8];
+                rdata.extend_from_slice(txt);
+                rdata
+            }
+            _ => vec![],
+        };
+
+        let mut resp = query.to_vec();
+        resp[2] |= 0x80;
+        if truncated {
+            resp[2] |= 0x02;
+        }
+        if !rdata.is_empty() && !truncated {
+            resp[7] = 1;
+            resp.extend_from_slice(&[0xc0, 0x0c]);
+            resp.extend_from_slice(&qtype.to_be_bytes());
+            resp.extend_from_slice(&1u16.to_be_bytes());
+            resp.extend_from_slice(&60u32.to_be_bytes());
+            resp.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+            resp.extend_from_slice(&rdata);
+        }
+        resp
+    }
+
+    async fn udp_server(truncated: bool) -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        task::spawn(async move {
+            let mut buf = [0; 512];
+            while let Ok((len, from)) = socket.recv_from(&mut buf).await {
+                let _ = socket.send_to(&respond(&buf[..len], truncated), from).await;
+            }
+        });
+        addr
+    }
+
+    async fn tcp_server(addr: SocketAddr) -> SocketAddr {
+        let listener = TcpListener::bind(addr).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        task::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut len = [0; 2];
+                stream.read_exact(&mut len).await.unwrap();
+                let mut query = vec![0; u16::from_be_bytes(len) as usize];
+                stream.read_exact(&mut query).await.unwrap();
+                let resp = respond(&query, false);
+                stream.write_all(&(resp.len() as u16).to_be_bytes()).await.unwrap();
+                stream.write_all(&resp).await.unwrap();
+            }
+        });
+        addr
+    }
+
+    #[test]
+    fn lookup_over_udp_and_tcp() {
+        task::block_on(async {
+            let udp = udp_server(false).await;
+            let resolver = NameServerResolver::new().with_udp(udp);
+            assert_eq!(resolver.lookup_ip("node.test").await.unwrap(), vec![IpAddr::from([10, 0, 0, 1])]);
+            assert_eq!(
+                resolver.lookup_txt("_dnsaddr.node.test").await.unwrap(),
+                vec!["dnsaddr=/ip4/10.0.0.1/tcp/4001"]
+            );
+
+            let tcp = tcp_server("127.0.0.1:0".parse().unwrap()).await;
+            let resolver = NameServerResolver::new().with_tcp(tcp);
+            assert_eq!(resolver.lookup_ip("node.test").await.unwrap(), vec![IpAddr::from([10, 0, 0, 1])]);
+        });
+    }
+
+    #[test]
+    fn fallback() {
+        task::block_on(async {
+            // the truncated response over UDP is retried over TCP on the same port
+            let udp = udp_server(true).await;
+            tcp_server(udp).await;
+            let resolver = NameServerResolver::new().with_udp(udp);
+            assert_eq!(resolver.lookup_txt("_dnsaddr.node.test").await.unwrap().len(), 1);
+
+            // the next server is tried if one doesn't respond
+            let silent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            let resolver = NameServerResolver::new()
+                .with_timeout(Duration::from_millis(100))
+                .with_udp(silent.local_addr().unwrap())
+                .with_udp(udp_server(false).await);
+            assert_eq!(resolver.lookup_ip("node.test").await.unwrap().len(), 1);
+
+            let resolver = NameServerResolver::new()
+                .with_timeout(Duration::from_millis(100))
+                .with_udp(silent.local_addr().unwrap());
+            assert_eq!(resolver.lookup_ip("node.test").await.unwrap_err().kind(), io::ErrorKind::TimedOut);
+        });
+    }
+
+    #[test]
+    fn lookup_blocking() {
+        let (udp, tcp, truncated) = task::block_on(async {
+            let udp = udp_server(false).await;
+            let tcp = tcp_server("127.0.0.1:0".parse().unwrap()).await;
+            let truncated = udp_server(true).await;
+            tcp_server(truncated).await;
+            (udp, tcp, truncated)
+        });
+        let expected = vec![IpAddr::from([10, 0, 0, 1])];
+
+        for resolver in &[
+            NameServerResolver::new().with_udp(udp),
+            NameServerResolver::new().with_tcp(tcp),
+            NameServerResolver::new().with_udp(truncated),
+        ] {
+            assert_eq!(resolver.lookup_ip_blocking("node.test").unwrap(), expected);
+        }
+
+        // the next server is tried if one doesn't respond
+        let silent = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let resolver = NameServerResolver::new()
+            .with_timeout(Duration::from_millis(100))
+            .with_udp(silent.local_addr().unwrap());
+        assert_eq!(
+            resolver.lookup_ip_blocking("node.test").unwrap_err().kind(),
+            io::ErrorKind::TimedOut
+        );
+        let resolver = resolver.with_udp(udp);
+        assert_eq!(resolver.lookup_ip_blocking("node.test").unwrap(), expected);
+    }
+
+    #[test]
+    fn resolv_conf() {
+        let conf = "# comment\nsearch example.com\nnameserver 10.0.0.53\nnameserver ::1\nnameserver fe80::1%eth0\n";
+        let expected: Vec<SocketAddr> = vec!["10.0.0.53:53".parse().unwrap(), "[::1]:53".parse().unwrap()];
+        assert_eq!(parse_resolv_conf(conf), (expected, vec!["example.com".to_string()]));
+
+        // the last one of search and domain wins
+        let conf = "search a.example b.example\ndomain c.example\n";
+        assert_eq!(parse_resolv_conf(conf).1, vec!["c.example".to_string()]);
+        let conf = "domain c.example\nsearch a.example b.example\n";
+        assert_eq!(parse_resolv_conf(conf).1, vec!["a.example".to_string(), "b.example".to_string()]);
+    }
+
+    #[test]
+    fn search_domains() {
+        task::block_on(async {
+            // the server knows only the names with a dot
+            let udp = udp_server(false).await;
+            let resolver = NameServerResolver::new().with_udp(udp);
+            assert_eq!(resolver.lookup_ip("node").await.unwrap_err().kind(), io::ErrorKind::NotFound);
+
+            let resolver = resolver.with_search_domain("test");
+            assert_eq!(resolver.lookup_ip("node").await.unwrap(), vec![IpAddr::from([10, 0, 0, 1])]);
+            assert_eq!(resolver.lookup_ip_blocking("node").unwrap(), vec![IpAddr::from([10, 0, 0, 1])]);
+            assert_eq!(resolver.candidates("node.test"), vec!["node.test".to_string()]);
+        });
+    }
+}
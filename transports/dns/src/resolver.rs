@@ -0,0 +1,170 @@
+// Copyright 2020 Netwarps Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+use async_trait::async_trait;
+use libp2prs_runtime::net;
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, ToSocketAddrs};
+
+use crate::nameserver::NameServerResolver;
+
+/// The resolver which `DnsConfig` looks up the DNS names with.
+#[async_trait]
+pub trait Resolver: Send + Sync {
+    /// Looks up the IP addresses of `name`.
+    async fn lookup_ip(&self, name: &str) -> io::Result<Vec<IpAddr>>;
+
+    /// Looks up the TXT records of `name`, which are used to resolve `/dnsaddr/`.
+    async fn lookup_txt(&self, name: &str) -> io::Result<Vec<String>>;
+
+    /// Looks up the IP addresses of `name` in a blocking way, which is used by `listen_on`.
+    ///
+    /// The default implementation blocks on `lookup_ip`, which doesn't work if `lookup_ip`
+    /// relies on the sockets of the runtime. Such resolvers should override it.
+    fn lookup_ip_blocking(&self, name: &str) -> io::Result<Vec<IpAddr>> {
+        futures::executor::block_on(self.lookup_ip(name))
+    }
+}
+
+/// The resolver of the operating system.
+///
+/// TXT records can't be looked up through the system resolver, so they are looked up
+/// through the name servers in `/etc/resolv.conf` instead, which is read once when the
+/// resolver is created, on unix. On the other platforms, e.g. Windows, or if there is no
+/// name server in `/etc/resolv.conf`, looking up TXT records fails, unless a fallback
+/// `NameServerResolver` is configured with `with_txt_fallback`.
+///
+/// ```
+/// use libp2prs_dns::{NameServerResolver, SystemResolver};
+///
+/// let resolver = SystemResolver::new().with_txt_fallback(NameServerResolver::new().with_udp("1.1.1.1:53".parse().unwrap()));
+/// ```
+#[derive(Clone, Debug)]
+pub struct SystemResolver {
+    // The resolver of TXT records, or why there isn't one.
+    txt_resolver: Result<NameServerResolver, String>,
+}
+
+impl Default for SystemResolver {
+    fn default() -> Self {
+        SystemResolver::new()
+    }
+}
+
+impl SystemResolver {
+    /// Creates a system resolver, with the name servers in `/etc/resolv.conf` to look up
+    /// TXT records, on unix.
+    pub fn new() -> Self {
+        SystemResolver {
+            txt_resolver: system_txt_resolver(),
+        }
+    }
+
+    /// Sets the resolver to look up TXT records, if there isn't one from the configuration
+    /// of the operating system.
+    pub fn with_txt_fallback(mut self, resolver: NameServerResolver) -> Self {
+        if self.txt_resolver.is_err() {
+            self.txt_resolver = Ok(resolver);
+        }
+        self
+    }
+}
+
+#[cfg(unix)]
+fn system_txt_resolver() -> Result<NameServerResolver, String> {
+    NameServerResolver::from_system_conf().map_err(|e| format!("failed to read the name servers in resolv.conf: {}", e))
+}
+
+#[cfg(not(unix))]
+fn system_txt_resolver() -> Result<NameServerResolver, String> {
+    Err("unsupported on this platform".to_string())
+}
+
+#[async_trait]
+impl Resolver for SystemResolver {
+    async fn lookup_ip(&self, name: &str) -> io::Result<Vec<IpAddr>> {
+        let list = net::resolve_host(format!("{}:0", name)).await?;
+        Ok(list.map(|s| s.ip()).collect())
+    }
+
+    fn lookup_ip_blocking(&self, name: &str) -> io::Result<Vec<IpAddr>> {
+        let list = (name, 0).to_socket_addrs()?;
+        Ok(list.map(|s| s.ip()).collect())
+    }
+
+    async fn lookup_txt(&self, name: &str) -> io::Result<Vec<String>> {
+        match &self.txt_resolver {
+            Ok(resolver) => resolver.lookup_txt(name).await,
+            Err(reason) => Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("TXT records can't be looked up through the system resolver: {}", reason),
+            )),
+        }
+    }
+}
+
+/// A resolver of the static records, which makes the tests independent of the network.
+///
+/// ```
+/// use libp2prs_dns::StaticResolver;
+///
+/// let resolver = StaticResolver::new()
+///     .with_host("node1.example.com", [10, 0, 0, 1].into())
+///     .with_txt("_dnsaddr.example.com", "dnsaddr=/dns4/node1.example.com/tcp/4001");
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct StaticResolver {
+    hosts: HashMap<String, Vec<IpAddr>>,
+    txts: HashMap<String, Vec<String>>,
+}
+
+impl StaticResolver {
+    pub fn new() -> Self {
+        StaticResolver::default()
+    }
+
+    /// Adds an IP address of `name`.
+    pub fn with_host(mut self, name: impl Into<String>, ip: IpAddr) -> Self {
+        self.hosts.entry(name.into()).or_default().push(ip);
+        self
+    }
+
+    /// Adds a TXT record of `name`.
+    pub fn with_txt(mut self, name: impl Into<String>, txt: impl Into<String>) -> Self {
+        self.txts.entry(name.into()).or_default().push(txt.into());
+        self
+    }
+}
+
+#[async_trait]
+impl Resolver for StaticResolver {
+    async fn lookup_ip(&self, name: &str) -> io::Result<Vec<IpAddr>> {
+        self.lookup_ip_blocking(name)
+    }
+
+    async fn lookup_txt(&self, name: &str) -> io::Result<Vec<String>> {
+        self.txts.get(name).cloned().ok_or_else(|| io::ErrorKind::NotFound.into())
+    }
+
+    fn lookup_ip_blocking(&self, name: &str) -> io::Result<Vec<IpAddr>> {
+        self.hosts.get(name).cloned().ok_or_else(|| io::ErrorKind::NotFound.into())
+    }
+}
@@ -1,21 +1,122 @@
 use async_trait::async_trait;
+use futures::channel::oneshot;
+use futures::future::{BoxFuture, Fuse, FusedFuture};
+use futures::lock::Mutex;
 use futures::prelude::*;
 use libp2prs_core::either::AsyncEitherOutput;
 use libp2prs_core::multiaddr::Multiaddr;
 use libp2prs_core::transport::ConnectionInfo;
+use libp2prs_runtime::task;
 use libp2prs_traits::{ReadEx, SplitEx, WriteEx};
-use soketto::connection;
+use soketto::{connection, data::ByteSlice125};
 use std::{
+    convert::TryFrom,
     io::{self, Error},
     pin::Pin,
+    sync::{Arc, Weak},
     task::{Context, Poll},
+    time::{Duration, Instant},
 };
 
 pub type TlsOrPlain<T> = AsyncEitherOutput<AsyncEitherOutput<TlsClientStream<T>, TlsServerStream<T>>, T>;
 
+/// Keep-alive state shared by the reader and the ping task of a connection.
+#[derive(Debug, Default)]
+struct KeepAliveState {
+    /// Nonce and send time of the ping awaiting its pong.
+    pending: Option<(u64, Instant)>,
+    /// Round-trip time measured by the last answered ping.
+    rtt: Option<Duration>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct KeepAlive(Arc<std::sync::Mutex<KeepAliveState>>);
+
+impl KeepAlive {
+    fn on_ping(&self, nonce: u64) {
+        self.0.lock().unwrap().pending = Some((nonce, Instant::now()));
+    }
+
+    fn on_pong(&self, payload: &[u8]) {
+        let mut state = self.0.lock().unwrap();
+        match state.pending {
+            Some((nonce, sent)) if payload == &nonce.to_be_bytes()[..] => {
+                state.pending = None;
+                state.rtt = Some(sent.elapsed());
+                log::trace!("websocket pong received, rtt {:?}", state.rtt);
+            }
+            // unsolicited pongs are allowed, just ignore them
+            _ => log::trace!("ignore unexpected websocket pong"),
+        }
+    }
+
+    fn is_pending(&self) -> bool {
+        self.0.lock().unwrap().pending.is_some()
+    }
+
+    fn rtt(&self) -> Option<Duration> {
+        self.0.lock().unwrap().rtt
+    }
+}
+
+/// Sends a ping every `interval`, until the connection is gone. If a pong does not
+/// arrive within `timeout`, the connection is closed and `timeout_tx` is notified.
+async fn keep_alive<W>(
+    sender: Weak<Mutex<connection::Sender<W>>>,
+    state: KeepAlive,
+    interval: Duration,
+    timeout: Duration,
+    timeout_tx: oneshot::Sender<()>,
+) where
+    W: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    let mut nonce = 0_u64;
+    let mut next = interval;
+    loop {
+        task::sleep(next).await;
+        let s = match sender.upgrade() {
+            Some(s) => s,
+            None => break,
+        };
+
+        nonce = nonce.wrapping_add(1);
+        let payload = nonce.to_be_bytes();
+        let data = ByteSlice125::try_from(&payload[..]).expect("8 bytes fit into a ping");
+        let sent = Instant::now();
+        state.on_ping(nonce);
+        let r = {
+            let mut s = s.lock().await;
+            match s.send_ping(data).await {
+                Ok(()) => s.flush().await,
+                Err(e) => Err(e),
+            }
+        };
+        // do not hold the connection open while waiting for the pong
+        drop(s);
+        if let Err(e) = r {
+            log::debug!("failed to send websocket ping: {:?}", e);
+            break;
+        }
+
+        task::sleep(timeout).await;
+        if state.is_pending() {
+            log::info!("no websocket pong received within {:?}, closing connection", timeout);
+            let _ = timeout_tx.send(());
+            if let Some(s) = sender.upgrade() {
+                let _ = s.lock().await.close().await;
+            }
+            break;
+        }
+        next = interval.checked_sub(sent.elapsed()).unwrap_or_default();
+    }
+}
+
 pub struct ConnectionReader<R> {
     recvier: connection::Receiver<R>,
     recv_buf: Vec<u8>,
+    keep_alive: Option<KeepAlive>,
+    /// Resolves when the keep-alive task gives up on the connection.
+    timeout: Fuse<BoxFuture<'static, ()>>,
 }
 
 impl<R> ConnectionReader<R> {
@@ -37,6 +138,11 @@ impl<R> ConnectionReader<R> {
 
         n
     }
+
+    /// Round-trip time measured by the last answered ping, if keep-alive is enabled.
+    pub fn rtt(&self) -> Option<Duration> {
+        self.keep_alive.as_ref().and_then(|k| k.rtt())
+    }
 }
 
 #[async_trait]
@@ -48,11 +154,29 @@ impl<R: AsyncRead + AsyncWrite + Unpin + Send> ReadEx for ConnectionReader<R> {
             log::debug!("drain recv buffer data size: {:?}", copied);
             return Ok(copied);
         }
+        if self.timeout.is_terminated() {
+            return Err(pong_timeout());
+        }
         let mut v = Vec::with_capacity(buf.len());
-        match self.recvier.receive_data(&mut v).await.map_err(|e| {
-            log::info!("{:?}", e);
-            std::io::Error::new(std::io::ErrorKind::Other, e)
-        })? {
+        // pongs are consumed here, only data is handed out
+        let data = loop {
+            let incoming = futures::select! {
+                r = self.recvier.receive(&mut v).fuse() => r.map_err(|e| {
+                    log::info!("{:?}", e);
+                    std::io::Error::new(std::io::ErrorKind::Other, e)
+                })?,
+                _ = &mut self.timeout => return Err(pong_timeout()),
+            };
+            match incoming {
+                soketto::Incoming::Data(data) => break data,
+                soketto::Incoming::Pong(payload) => {
+                    if let Some(k) = self.keep_alive.as_ref() {
+                        k.on_pong(payload);
+                    }
+                }
+            }
+        };
+        match data {
             soketto::Data::Binary(n) | soketto::Data::Text(n) => {
                 if buf.len() >= n {
                     buf[..n].copy_from_slice(v.as_ref());
@@ -69,8 +193,12 @@ impl<R: AsyncRead + AsyncWrite + Unpin + Send> ReadEx for ConnectionReader<R> {
     }
 }
 
+fn pong_timeout() -> io::Error {
+    io::Error::new(io::ErrorKind::TimedOut, "websocket pong timeout")
+}
+
 pub struct ConnectionWriter<W> {
-    sender: connection::Sender<W>,
+    sender: Arc<Mutex<connection::Sender<W>>>,
 }
 
 #[async_trait]
@@ -78,6 +206,8 @@ impl<W: AsyncRead + AsyncWrite + Unpin + Send> WriteEx for ConnectionWriter<W> {
     async fn write2(&mut self, buf: &[u8]) -> Result<usize, Error> {
         let n = buf.len();
         self.sender
+            .lock()
+            .await
             .send_binary(buf)
             .await
             .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
@@ -86,6 +216,8 @@ impl<W: AsyncRead + AsyncWrite + Unpin + Send> WriteEx for ConnectionWriter<W> {
 
     async fn flush2(&mut self) -> Result<(), Error> {
         self.sender
+            .lock()
+            .await
             .flush()
             .await
             .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
@@ -94,6 +226,8 @@ impl<W: AsyncRead + AsyncWrite + Unpin + Send> WriteEx for ConnectionWriter<W> {
 
     async fn close2(&mut self) -> Result<(), Error> {
         self.sender
+            .lock()
+            .await
             .close()
             .await
             .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
@@ -107,6 +241,7 @@ pub struct Connection<T> {
 
     local_addr: Multiaddr,
     remote_addr: Multiaddr,
+    protocol: Option<String>,
 }
 
 impl<T> Connection<T>
@@ -114,17 +249,71 @@ where
     T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
 {
     pub fn new(builder: connection::Builder<T>, local_addr: Multiaddr, remote_addr: Multiaddr) -> Self {
+        Self::new_with_keep_alive(builder, local_addr, remote_addr, None, Duration::default())
+    }
+
+    /// Create a connection which sends a ping every `ping_interval`, and gets closed when
+    /// the pong does not arrive within `pong_timeout`. No pings are sent if `ping_interval`
+    /// is `None`.
+    pub fn new_with_keep_alive(
+        builder: connection::Builder<T>,
+        local_addr: Multiaddr,
+        remote_addr: Multiaddr,
+        ping_interval: Option<Duration>,
+        pong_timeout: Duration,
+    ) -> Self {
         let (tx, rx) = builder.finish();
+        let sender = Arc::new(Mutex::new(tx));
+        let (keep_alive, timeout) = match ping_interval {
+            Some(interval) => {
+                let state = KeepAlive::default();
+                let (timeout_tx, timeout_rx) = oneshot::channel();
+                task::spawn(keep_alive(
+                    Arc::downgrade(&sender),
+                    state.clone(),
+                    interval,
+                    pong_timeout,
+                    timeout_tx,
+                ));
+                // the task going away without a timeout is not an error
+                let timeout = async move {
+                    if timeout_rx.await.is_err() {
+                        future::pending::<()>().await
+                    }
+                };
+                (Some(state), timeout.boxed().fuse())
+            }
+            None => (None, future::pending().boxed().fuse()),
+        };
         Connection {
             reader: ConnectionReader {
                 recvier: rx,
                 recv_buf: Vec::default(),
+                keep_alive,
+                timeout,
             },
-            writer: ConnectionWriter { sender: tx },
+            writer: ConnectionWriter { sender },
             local_addr,
             remote_addr,
+            protocol: None,
         }
     }
+
+    /// Set the subprotocol agreed on during the handshake.
+    pub(crate) fn with_protocol(mut self, protocol: Option<String>) -> Self {
+        self.protocol = protocol;
+        self
+    }
+
+    /// The subprotocol agreed on during the handshake, if any.
+    pub fn protocol(&self) -> Option<&str> {
+        self.protocol.as_deref()
+    }
+
+    /// Round-trip time measured by the last answered ping, if keep-alive is enabled.
+    pub fn rtt(&self) -> Option<Duration> {
+        self.reader.rtt()
+    }
 }
 
 #[async_trait]
@@ -19,7 +19,7 @@
 // FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
 // DEALINGS IN THE SOFTWARE.
 
-use crate::tls;
+use crate::{proxy, tls};
 use libp2prs_core::transport::TransportError;
 use libp2prs_core::Multiaddr;
 use std::{error, fmt};
@@ -32,6 +32,8 @@ pub(crate) enum WsError {
     Transport(TransportError),
     /// A TLS related error.
     Tls(tls::Error),
+    /// A HTTP proxy related error.
+    Proxy(proxy::Error),
     /// Websocket handshake error.
     Handshake(Box<dyn error::Error + Send + Sync>),
     /// The configured maximum of redirects have been made.
@@ -49,6 +51,7 @@ impl fmt::Display for WsError {
         match self {
             WsError::Transport(err) => write!(f, "{}", err),
             WsError::Tls(err) => write!(f, "{}", err),
+            WsError::Proxy(err) => write!(f, "{}", err),
             WsError::Handshake(err) => write!(f, "{}", err),
             WsError::InvalidMultiaddr(ma) => write!(f, "invalid multi-address: {}", ma),
             WsError::TooManyRedirects => f.write_str("too many redirects"),
@@ -63,6 +66,7 @@ impl error::Error for WsError {
         match self {
             WsError::Transport(err) => Some(err),
             WsError::Tls(err) => Some(err),
+            WsError::Proxy(err) => Some(err),
             WsError::Handshake(err) => Some(&**err),
             WsError::Base(err) => Some(&**err),
             WsError::InvalidMultiaddr(_) | WsError::TooManyRedirects | WsError::InvalidRedirectLocation => None,
@@ -75,3 +79,9 @@ impl From<tls::Error> for WsError {
         WsError::Tls(e)
     }
 }
+
+impl From<proxy::Error> for WsError {
+    fn from(e: proxy::Error) -> Self {
+        WsError::Proxy(e)
+    }
+}
@@ -21,7 +21,7 @@
 
 // use crate::connection::{Connection, TlsOrPlain};
 use crate::connection::{Connection, TlsClientStream, TlsOrPlain, TlsServerStream};
-use crate::{error::WsError, tls};
+use crate::{error::WsError, header, proxy, tls};
 use async_trait::async_trait;
 use either::Either;
 use futures::prelude::*;
@@ -37,11 +37,15 @@ use libp2prs_tcp::TcpTransStream;
 use log::{debug, info, trace};
 use soketto::{connection, extension::deflate::Deflate, handshake};
 use std::fmt;
+use std::time::Duration;
 use url::Url;
 
 /// Max. number of payload bytes of a single frame.
 const MAX_DATA_SIZE: usize = 256 * 1024 * 1024;
 
+/// Time to wait for the pong after sending a ping.
+const PONG_TIMEOUT: Duration = Duration::from_secs(10);
+
 /// A Websocket transport whose output type is a [`Stream`] and [`Sink`] of
 /// frame payloads which does not implement [`AsyncRead`] or
 /// [`AsyncWrite`]. See [`crate::WsConfig`] if you require the latter.
@@ -74,6 +78,13 @@ pub(crate) struct InnerConfig {
     tls_config: tls::Config,
     max_redirects: u8,
     use_deflate: bool,
+    proxy: Option<proxy::Config>,
+    ping_interval: Option<Duration>,
+    pong_timeout: Duration,
+    protocols: Vec<String>,
+    allowed_origins: Vec<String>,
+    origin: Option<String>,
+    headers: header::Headers,
 }
 
 impl InnerConfig {
@@ -84,6 +95,13 @@ impl InnerConfig {
             tls_config: tls::Config::client(),
             max_redirects: 0,
             use_deflate: false,
+            proxy: None,
+            ping_interval: None,
+            pong_timeout: PONG_TIMEOUT,
+            protocols: Vec::new(),
+            allowed_origins: Vec::new(),
+            origin: None,
+            headers: header::Headers::default(),
         }
     }
 
@@ -120,12 +138,82 @@ impl InnerConfig {
         self.use_deflate = flag;
         self
     }
+
+    /// Set the HTTP proxy used for dialing, or `None` to dial directly.
+    pub fn set_proxy_config(&mut self, c: Option<proxy::Config>) -> &mut Self {
+        self.proxy = c;
+        self
+    }
+
+    /// Return the interval of keep-alive pings, `None` if disabled.
+    pub fn ping_interval(&self) -> Option<Duration> {
+        self.ping_interval
+    }
+
+    /// Set the interval of keep-alive pings, or `None` to disable them.
+    pub fn set_ping_interval(&mut self, interval: Option<Duration>) -> &mut Self {
+        self.ping_interval = interval;
+        self
+    }
+
+    /// Return the time to wait for a pong before closing the connection.
+    pub fn pong_timeout(&self) -> Duration {
+        self.pong_timeout
+    }
+
+    /// Set the time to wait for a pong before closing the connection.
+    pub fn set_pong_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.pong_timeout = timeout;
+        self
+    }
+
+    /// Return the supported subprotocols.
+    pub fn protocols(&self) -> &[String] {
+        &self.protocols
+    }
+
+    /// Set the supported subprotocols, in order of preference.
+    pub fn set_protocols(&mut self, protocols: Vec<String>) -> &mut Self {
+        self.protocols = protocols;
+        self
+    }
+
+    /// Return the origins the listener accepts, empty if any.
+    pub fn allowed_origins(&self) -> &[String] {
+        &self.allowed_origins
+    }
+
+    /// Set the origins the listener accepts, or an empty list to accept any.
+    pub fn set_allowed_origins(&mut self, origins: Vec<String>) -> &mut Self {
+        self.allowed_origins = origins;
+        self
+    }
+
+    /// Set the origin sent by the dialer.
+    pub fn set_origin(&mut self, origin: Option<String>) -> &mut Self {
+        self.origin = origin;
+        self
+    }
+
+    /// Add a header to the upgrade request sent by the dialer.
+    pub fn add_header(&mut self, name: &str, value: &str) -> Result<&mut Self, header::Error> {
+        self.headers.add(name, value)?;
+        Ok(self)
+    }
+
+    fn new_connection<T>(&self, builder: connection::Builder<T>, local_addr: Multiaddr, remote_addr: Multiaddr) -> Connection<T>
+    where
+        T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        Connection::new_with_keep_alive(builder, local_addr, remote_addr, self.ping_interval, self.pong_timeout)
+    }
 }
 
 pub struct WsTransListener {
     inner: IListener<TcpTransStream>,
     inner_config: InnerConfig,
     use_tls: bool,
+    path: Option<String>,
 }
 
 impl fmt::Debug for WsTransListener {
@@ -133,16 +221,54 @@ impl fmt::Debug for WsTransListener {
         f.debug_struct("WsTransListener")
             .field("Config", &self.inner_config)
             .field("tls", &self.use_tls)
+            .field("path", &self.path)
             .finish()
     }
 }
 
 impl WsTransListener {
-    pub(crate) fn new(inner: IListener<TcpTransStream>, inner_config: InnerConfig, use_tls: bool) -> Self {
+    pub(crate) fn new(inner: IListener<TcpTransStream>, inner_config: InnerConfig, use_tls: bool, path: Option<String>) -> Self {
         Self {
             inner,
             inner_config,
             use_tls,
+            path,
+        }
+    }
+
+    /// Check path, origin and subprotocols of the upgrade request.
+    ///
+    /// Returns the selected subprotocol, or the status code to reject the request with.
+    /// With any of them restricted, a request whose headers can't be read is rejected.
+    fn check_request(&self, path: &str, request: &[u8]) -> Result<Option<String>, u16> {
+        let origins = &self.inner_config.allowed_origins;
+        let protocols = &self.inner_config.protocols;
+        if self.path.is_some() || !origins.is_empty() || !protocols.is_empty() {
+            header::head(request)?;
+        }
+
+        if let Some(expected) = &self.path {
+            // the query string is not part of the route
+            let path = path.split('?').next().unwrap_or(path);
+            if path != expected {
+                return Err(404);
+            }
+        }
+
+        // requests without origin do not come from browsers and are not restricted
+        if let Some(origin) = header::values(request, "Origin").next() {
+            if !origins.is_empty() && !origins.iter().any(|o| o.eq_ignore_ascii_case(origin)) {
+                return Err(403);
+            }
+        }
+
+        if protocols.is_empty() {
+            return Ok(None);
+        }
+        let offered: Vec<&str> = header::list(request, "Sec-WebSocket-Protocol").collect();
+        match protocols.iter().find(|p| offered.contains(&p.as_str())) {
+            Some(p) => Ok(Some(p.clone())),
+            None => Err(400),
         }
     }
 }
@@ -179,31 +305,61 @@ impl TransportListener for WsTransListener {
         };
 
         trace!("[Server] receiving websocket handshake request from {}", remote2);
-        let mut server = handshake::Server::new(stream);
+        let mut server = handshake::Server::new(header::Recorder::new(stream));
 
         if self.inner_config.use_deflate {
             server.add_extension(Box::new(Deflate::new(connection::Mode::Server)));
         }
 
-        let ws_key = {
+        let (ws_key, path) = {
             let request = server.receive_request().await.map_err(|e| WsError::Handshake(Box::new(e)))?;
-            request.into_key()
+            let path = request.path().to_string();
+            (request.into_key(), path)
+        };
+
+        // unwrap the recorded request, the handshake goes on with the state of the first server
+        let buffer = server.take_buffer();
+        let extensions: Vec<_> = server.drain_extensions().collect();
+        let recorder = server.into_inner();
+        let checked = self.check_request(&path, recorder.request());
+        let mut server = handshake::Server::new(recorder.into_inner());
+        for e in extensions {
+            server.add_extension(e);
+        }
+
+        let protocol = match checked {
+            Ok(protocol) => protocol,
+            Err(status_code) => {
+                debug!(
+                    "[Server] rejecting websocket handshake request for {} from {}; status code = {}",
+                    path, remote2, status_code
+                );
+                let response = handshake::server::Response::Reject { status_code };
+                server.send_response(&response).await.map_err(|e| WsError::Handshake(Box::new(e)))?;
+                let msg = format!("[Server] rejected handshake; status code = {}", status_code);
+                return Err(WsError::Handshake(msg.into()).into());
+            }
         };
 
         debug!("[Server] accepting websocket handshake request from {}", remote2);
 
         let response = handshake::server::Response::Accept {
             key: &ws_key,
-            protocol: None,
+            protocol: protocol.as_deref(),
         };
 
         server.send_response(&response).await.map_err(|e| WsError::Handshake(Box::new(e)))?;
 
         let conn = {
-            let mut builder = server.into_builder();
+            let extensions: Vec<_> = server.drain_extensions().collect();
+            let mut builder = connection::Builder::new(server.into_inner(), connection::Mode::Server);
+            builder.set_buffer(buffer);
+            builder.add_extensions(extensions);
             builder.set_max_message_size(self.inner_config.max_data_size);
             builder.set_max_frame_size(self.inner_config.max_data_size);
-            Connection::new(builder, local_addr, remote_addr)
+            self.inner_config
+                .new_connection(builder, local_addr, remote_addr)
+                .with_protocol(protocol)
         };
         Ok(ListenerEvent::Accepted(conn))
     }
@@ -220,23 +376,25 @@ impl Transport for WsConfig {
         log::debug!("WebSocket listen on addr: {}", addr);
         let mut inner_addr = addr.clone();
 
-        let (use_tls, _proto) = match inner_addr.pop() {
-            Some(p @ Protocol::Wss(_)) => {
+        let (use_tls, path) = match inner_addr.pop() {
+            Some(Protocol::Wss(path)) => {
                 if self.inner_config.tls_config.server.is_some() {
-                    (true, p)
+                    (true, path)
                 } else {
                     debug!("/wss address but TLS server support is not configured");
                     return Err(TransportError::MultiaddrNotSupported(addr));
                 }
             }
-            Some(p @ Protocol::Ws(_)) => (false, p),
+            Some(Protocol::Ws(path)) => (false, path),
             _ => {
                 debug!("{} is not a websocket multiaddr", addr);
                 return Err(TransportError::MultiaddrNotSupported(addr));
             }
         };
+        // the root path, i.e. a plain `/ws` address, accepts requests for any path
+        let path = Some(path.into_owned()).filter(|p| p != "/");
         let inner_listener = self.transport.listen_on(addr)?;
-        let listener = WsTransListener::new(inner_listener, self.inner_config.clone(), use_tls);
+        let listener = WsTransListener::new(inner_listener, self.inner_config.clone(), use_tls, path);
         Ok(Box::new(listener))
     }
 
@@ -305,10 +463,23 @@ impl WsConfig {
             }
         };
 
-        let raw_stream = self.transport.dial(inner_addr).await.map_err(WsError::Transport)?;
+        let proxy = self.inner_config.proxy.clone().filter(|p| !p.bypass(&inner_addr));
+        let (raw_stream, remote_addr) = if let Some(proxy) = proxy {
+            trace!("[Client] connecting to {} through proxy {}", address, proxy.addr());
+            let mut raw_stream = self.transport.dial(proxy.addr().clone()).await.map_err(WsError::Transport)?;
+            proxy::connect(&mut raw_stream, &host_port, proxy.auth()).await.map_err(|e| {
+                debug!("[Client] proxy tunnel to {} failed: {}", address, e);
+                WsError::Proxy(e)
+            })?;
+            // the remote end of the tunnel is the target, not the proxy
+            (raw_stream, inner_addr)
+        } else {
+            let raw_stream = self.transport.dial(inner_addr).await.map_err(WsError::Transport)?;
+            let remote_addr = raw_stream.remote_multiaddr();
+            (raw_stream, remote_addr)
+        };
         trace!("[Client] connected to {}", address);
         let local_addr = raw_stream.local_multiaddr();
-        let remote_addr = raw_stream.remote_multiaddr();
         let stream = if use_tls {
             // begin TLS session
             let dns_name = dns_name.expect("for use_tls we have checked that dns_name is some");
@@ -335,11 +506,18 @@ impl WsConfig {
 
         trace!("[Client] sending websocket handshake request to {}", address);
 
+        let stream = header::Injector::new(stream, &self.inner_config.headers);
         let mut client = handshake::Client::new(stream, &host_port, path.as_ref());
 
         if self.inner_config.use_deflate {
             client.add_extension(Box::new(Deflate::new(connection::Mode::Client)));
         }
+        if let Some(origin) = &self.inner_config.origin {
+            client.set_origin(origin);
+        }
+        for p in &self.inner_config.protocols {
+            client.add_protocol(p);
+        }
 
         match client
             .handshake()
@@ -357,9 +535,18 @@ impl WsConfig {
                 let msg = format!("[Client] server rejected handshake; status code = {}", status_code);
                 Err(WsError::Handshake(msg.into()))
             }
-            handshake::ServerResponse::Accepted { .. } => {
+            handshake::ServerResponse::Accepted { protocol } => {
                 debug!("[Client] websocket handshake with {} successful", address);
-                Ok(Either::Right(Connection::new(client.into_builder(), local_addr, remote_addr)))
+                let buffer = client.take_buffer();
+                let extensions: Vec<_> = client.drain_extensions().collect();
+                let mut builder = connection::Builder::new(client.into_inner().into_inner(), connection::Mode::Client);
+                builder.set_buffer(buffer);
+                builder.add_extensions(extensions);
+                Ok(Either::Right(
+                    self.inner_config
+                        .new_connection(builder, local_addr, remote_addr)
+                        .with_protocol(protocol),
+                ))
             }
         }
     }
@@ -379,7 +566,7 @@ fn host_and_dnsname(addr: &Multiaddr) -> Result<(String, Option<webpki::DNSName>
     let mut iter = addr.iter();
     match (iter.next(), iter.next()) {
         (Some(Protocol::Ip4(ip)), Some(Protocol::Tcp(port))) => Ok((format!("{}:{}", ip, port), None)),
-        (Some(Protocol::Ip6(ip)), Some(Protocol::Tcp(port))) => Ok((format!("{}:{}", ip, port), None)),
+        (Some(Protocol::Ip6(ip)), Some(Protocol::Tcp(port))) => Ok((format!("[{}]:{}", ip, port), None)),
         (Some(Protocol::Dns(h)), Some(Protocol::Tcp(port))) => {
             Ok((format!("{}:{}", &h, port), Some(tls::dns_name_ref(&h)?.to_owned())))
         }
@@ -0,0 +1,308 @@
+// Copyright 2020 Netwarps Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Access to the headers of the HTTP upgrade request.
+//!
+//! soketto neither exposes the request headers to the listener nor lets the
+//! dialer add its own, so both sides wrap the stream for the duration of the
+//! handshake: the listener records the request as it is read and the dialer
+//! appends the extra headers to the request before it is sent.
+
+use futures::prelude::*;
+use std::{
+    fmt, io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// Max. size of the upgrade request we are willing to record.
+const MAX_REQUEST_SIZE: usize = 8 * 1024;
+
+/// Headers set by the handshake itself, which must not be overridden.
+const RESERVED: &[&str] = &[
+    "host",
+    "upgrade",
+    "connection",
+    "origin",
+    "sec-websocket-key",
+    "sec-websocket-version",
+    "sec-websocket-protocol",
+    "sec-websocket-extensions",
+];
+
+/// The headers added to the upgrade request of the dialer.
+///
+/// The values are left out of the debug output, as they may hold credentials.
+#[derive(Clone, Default)]
+pub(crate) struct Headers(Vec<(String, String)>);
+
+impl Headers {
+    pub(crate) fn add(&mut self, name: &str, value: &str) -> Result<(), Error> {
+        validate(name, value)?;
+        self.0.push((name.to_string(), value.to_string()));
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Headers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.0.iter().map(|(name, _)| name)).finish()
+    }
+}
+
+/// Check that `name: value` is a valid request header which may be added by the dialer.
+fn validate(name: &str, value: &str) -> Result<(), Error> {
+    let is_tchar = |c: char| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c);
+    if name.is_empty() || !name.chars().all(is_tchar) {
+        return Err(Error::InvalidName(name.to_string()));
+    }
+    if RESERVED.iter().any(|r| r.eq_ignore_ascii_case(name)) {
+        return Err(Error::Reserved(name.to_string()));
+    }
+    if value.chars().any(|c| c.is_ascii_control() && c != '\t') {
+        return Err(Error::InvalidValue(name.to_string()));
+    }
+    Ok(())
+}
+
+/// Return the head of the raw `request`, up to the blank line which terminates it.
+///
+/// Fails with the status code to reject the request with if the head is incomplete,
+/// i.e. longer than [`MAX_REQUEST_SIZE`], or is not valid UTF-8.
+pub(crate) fn head(request: &[u8]) -> Result<&str, u16> {
+    let end = request.windows(4).position(|w| w == b"\r\n\r\n").ok_or(431_u16)?;
+    std::str::from_utf8(&request[..end]).map_err(|_| 400)
+}
+
+/// Return the values of all headers named `name` in the raw `request`.
+///
+/// A request whose [`head`] can't be read has no headers.
+pub(crate) fn values<'a>(request: &'a [u8], name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+    let head = head(request).unwrap_or("");
+    // skip the request line
+    head.split("\r\n").skip(1).filter_map(move |line| {
+        let i = line.find(':')?;
+        if line[..i].trim().eq_ignore_ascii_case(name) {
+            Some(line[i + 1..].trim())
+        } else {
+            None
+        }
+    })
+}
+
+/// Return the comma separated elements of all headers named `name` in the raw `request`.
+pub(crate) fn list<'a>(request: &'a [u8], name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+    values(request, name)
+        .flat_map(|v| v.split(','))
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+}
+
+/// A stream which records the bytes read, i.e. the upgrade request of the listener.
+pub(crate) struct Recorder<T> {
+    inner: T,
+    request: Vec<u8>,
+}
+
+impl<T> Recorder<T> {
+    pub(crate) fn new(inner: T) -> Self {
+        Recorder {
+            inner,
+            request: Vec::new(),
+        }
+    }
+
+    /// The bytes read so far, at most [`MAX_REQUEST_SIZE`].
+    pub(crate) fn request(&self) -> &[u8] {
+        &self.request
+    }
+
+    pub(crate) fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for Recorder<T> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        let n = futures::ready!(Pin::new(&mut self.inner).poll_read(cx, buf))?;
+        let len = n.min(MAX_REQUEST_SIZE.saturating_sub(self.request.len()));
+        self.request.extend_from_slice(&buf[..len]);
+        Poll::Ready(Ok(n))
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for Recorder<T> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
+
+/// A stream which adds headers to the upgrade request written by the dialer.
+///
+/// Writes are buffered until flushed, by then the request is complete and the
+/// headers are inserted before the blank line which terminates it.
+pub(crate) struct Injector<T> {
+    inner: T,
+    headers: Vec<u8>,
+    buf: Vec<u8>,
+    written: usize,
+}
+
+impl<T> Injector<T> {
+    pub(crate) fn new(inner: T, headers: &Headers) -> Self {
+        let mut encoded = Vec::new();
+        for (name, value) in &headers.0 {
+            encoded.extend_from_slice(name.as_bytes());
+            encoded.extend_from_slice(b": ");
+            encoded.extend_from_slice(value.as_bytes());
+            encoded.extend_from_slice(b"\r\n");
+        }
+        Injector {
+            inner,
+            headers: encoded,
+            buf: Vec::new(),
+            written: 0,
+        }
+    }
+
+    pub(crate) fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for Injector<T> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for Injector<T> {
+    fn poll_write(mut self: Pin<&mut Self>, _cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        self.buf.extend_from_slice(buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        if !this.headers.is_empty() && this.written == 0 && this.buf.ends_with(b"\r\n\r\n") {
+            let at = this.buf.len() - 2;
+            let headers = std::mem::take(&mut this.headers);
+            this.buf.splice(at..at, headers);
+        }
+        while this.written < this.buf.len() {
+            let n = futures::ready!(Pin::new(&mut this.inner).poll_write(cx, &this.buf[this.written..]))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            this.written += n;
+        }
+        this.buf.clear();
+        this.written = 0;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        futures::ready!(self.as_mut().poll_flush(cx))?;
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
+
+/// Request header related errors.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// The header name is not a valid HTTP token.
+    InvalidName(String),
+    /// The header value contains control characters.
+    InvalidValue(String),
+    /// The header is set by the websocket handshake itself.
+    Reserved(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::InvalidName(n) => write!(f, "invalid header name: {}", n),
+            Error::InvalidValue(n) => write!(f, "invalid value of header {}", n),
+            Error::Reserved(n) => write!(f, "reserved header: {}", n),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libp2prs_runtime::task;
+
+    #[test]
+    fn request_headers() {
+        let request = b"GET /ws HTTP/1.1\r\nHost: example.com\r\nOrigin: https://example.com\r\n\
+            Sec-WebSocket-Protocol: chat, json\r\nsec-websocket-protocol: mqtt\r\n\r\nframe";
+        assert_eq!(values(request, "origin").collect::<Vec<_>>(), vec!["https://example.com"]);
+        assert_eq!(
+            list(request, "Sec-WebSocket-Protocol").collect::<Vec<_>>(),
+            vec!["chat", "json", "mqtt"]
+        );
+        assert_eq!(values(request, "Authorization").count(), 0);
+        // an incomplete request has no headers
+        assert_eq!(values(b"GET / HTTP/1.1\r\nOrigin: x\r\n", "Origin").count(), 0);
+        assert_eq!(head(b"GET / HTTP/1.1\r\nOrigin: x\r\n"), Err(431));
+        assert_eq!(head(b"GET / HTTP/1.1\r\nOrigin: \xff\r\n\r\n"), Err(400));
+        assert_eq!(
+            head(request),
+            Ok("GET /ws HTTP/1.1\r\nHost: example.com\r\nOrigin: https://example.com\r\n\
+            Sec-WebSocket-Protocol: chat, json\r\nsec-websocket-protocol: mqtt")
+        );
+    }
+
+    #[test]
+    fn header_validation() {
+        assert!(validate("Authorization", "Bearer abc").is_ok());
+        assert!(matches!(validate("Bad Name", "x"), Err(Error::InvalidName(_))));
+        assert!(matches!(validate("X-Token", "a\r\nHost: evil"), Err(Error::InvalidValue(_))));
+        assert!(matches!(validate("sec-websocket-key", "x"), Err(Error::Reserved(_))));
+    }
+
+    #[test]
+    fn inject_headers() {
+        let mut headers = Headers::default();
+        headers.add("Authorization", "Bearer abc").unwrap();
+        assert_eq!(format!("{:?}", headers), r#"["Authorization"]"#);
+        let mut stream = Injector::new(futures::io::Cursor::new(Vec::new()), &headers);
+        task::block_on(async {
+            stream.write_all(b"GET / HTTP/1.1\r\nHost: a\r\n\r\n").await.unwrap();
+            stream.flush().await.unwrap();
+        });
+        assert_eq!(
+            stream.into_inner().into_inner(),
+            b"GET / HTTP/1.1\r\nHost: a\r\nAuthorization: Bearer abc\r\n\r\n".to_vec()
+        );
+    }
+}
@@ -22,6 +22,9 @@
 use async_tls::{TlsAcceptor, TlsConnector};
 use std::{fmt, io, sync::Arc};
 
+pub use self::resolver::{CertResolver, Control};
+mod resolver;
+
 /// TLS configuration.
 #[derive(Clone)]
 pub struct Config {
@@ -68,6 +71,13 @@ impl Config {
         Ok(builder.finish())
     }
 
+    /// Create a new TLS configuration whose server certificates are selected by the resolver.
+    pub fn new_with_resolver(resolver: CertResolver) -> Self {
+        let mut builder = Config::builder();
+        builder.server_resolver(resolver);
+        builder.finish()
+    }
+
     /// Create a client-only configuration.
     pub fn client() -> Self {
         Config {
@@ -112,6 +122,14 @@ impl Builder {
         Ok(self)
     }
 
+    /// Set a resolver selecting the server certificate for every connection.
+    pub fn server_resolver(&mut self, resolver: CertResolver) -> &mut Self {
+        let mut server = rustls::ServerConfig::new(rustls::NoClientAuth::new());
+        server.cert_resolver = Arc::new(resolver);
+        self.server = Some(server);
+        self
+    }
+
     /// Add an additional trust anchor.
     pub fn add_trust(&mut self, cert: &Certificate) -> Result<&mut Self, Error> {
         self.client.root_store.add(&cert.0).map_err(|e| Error::Tls(Box::new(e)))?;
@@ -143,6 +161,8 @@ pub enum Error {
     Tls(Box<dyn std::error::Error + Send + Sync>),
     /// The DNS name was invalid.
     InvalidDnsName(String),
+    /// The certificate watcher is closed.
+    Closed,
 }
 
 impl fmt::Display for Error {
@@ -151,6 +171,7 @@ impl fmt::Display for Error {
             Error::Io(e) => write!(f, "i/o error: {}", e),
             Error::Tls(e) => write!(f, "tls error: {}", e),
             Error::InvalidDnsName(n) => write!(f, "invalid DNS name: {}", n),
+            Error::Closed => f.write_str("certificate watcher closed"),
         }
     }
 }
@@ -160,7 +181,7 @@ impl std::error::Error for Error {
         match self {
             Error::Io(e) => Some(e),
             Error::Tls(e) => Some(&**e),
-            Error::InvalidDnsName(_) => None,
+            Error::InvalidDnsName(_) | Error::Closed => None,
         }
     }
 }
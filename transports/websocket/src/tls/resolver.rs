@@ -0,0 +1,389 @@
+// Copyright 2020 Netwarps Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Server certificates which can be replaced while the listener is running.
+//!
+//! [`CertResolver`] picks the certificate for every TLS handshake, based on
+//! the server name indicated by the client (SNI). Replacing a certificate only
+//! affects new handshakes, established connections are kept as they are.
+
+use super::{dns_name_ref, Error};
+use futures::channel::{mpsc, oneshot};
+use futures::prelude::*;
+use libp2prs_runtime::task;
+use rustls::internal::pemfile;
+use rustls::sign::{self, CertifiedKey};
+use rustls::{ClientHello, ResolvesServerCert, SignatureScheme};
+use std::fmt;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+/// A certificate chain and private key loaded from PEM files.
+struct CertFiles {
+    /// The server name this certificate is selected for, `None` for the default.
+    name: Option<String>,
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    /// Modification times of the files when they were last loaded.
+    modified: (Option<SystemTime>, Option<SystemTime>),
+    key: CertifiedKey,
+}
+
+impl CertFiles {
+    fn current_modified(&self) -> (Option<SystemTime>, Option<SystemTime>) {
+        (modified(&self.cert_path), modified(&self.key_path))
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// The signature schemes tried when checking that a private key matches its certificate,
+/// with their counterparts in webpki.
+const KEY_CHECK_SCHEMES: [(SignatureScheme, &webpki::SignatureAlgorithm); 5] = [
+    (SignatureScheme::ED25519, &webpki::ED25519),
+    (SignatureScheme::ECDSA_NISTP256_SHA256, &webpki::ECDSA_P256_SHA256),
+    (SignatureScheme::ECDSA_NISTP384_SHA384, &webpki::ECDSA_P384_SHA384),
+    (SignatureScheme::RSA_PSS_SHA256, &webpki::RSA_PSS_2048_8192_SHA256_LEGACY_KEY),
+    (SignatureScheme::RSA_PKCS1_SHA256, &webpki::RSA_PKCS1_2048_8192_SHA256),
+];
+
+/// Check that `key` belongs to the end-entity certificate, by verifying a signature made
+/// with the key against the public key of the certificate.
+fn check_key_matches_cert(key: &dyn sign::SigningKey, cert: &rustls::Certificate) -> Result<(), Error> {
+    let schemes: Vec<_> = KEY_CHECK_SCHEMES.iter().map(|(scheme, _)| *scheme).collect();
+    let signer = key
+        .choose_scheme(&schemes)
+        .ok_or_else(|| Error::Tls("no signature scheme for the private key".into()))?;
+    let algorithm = KEY_CHECK_SCHEMES
+        .iter()
+        .find(|(scheme, _)| *scheme == signer.get_scheme())
+        .map(|(_, algorithm)| *algorithm)
+        .ok_or_else(|| Error::Tls("no signature scheme for the private key".into()))?;
+
+    let message = b"libp2prs-websocket certificate key check";
+    let signature = signer.sign(message).map_err(|e| Error::Tls(Box::new(e)))?;
+    let cert = webpki::EndEntityCert::from(&cert.0).map_err(|e| Error::Tls(Box::new(e)))?;
+    cert.verify_signature(algorithm, message, &signature)
+        .map_err(|_| Error::Tls("private key does not match the certificate".into()))
+}
+
+/// Load the certificate chain and the private key, the first one found in the key file.
+///
+/// The key must belong to the first certificate of the chain.
+fn load_certified_key(name: Option<&str>, cert_path: &Path, key_path: &Path) -> Result<CertifiedKey, Error> {
+    let certs = pemfile::certs(&mut BufReader::new(File::open(cert_path)?)).unwrap_or_default();
+    if certs.is_empty() {
+        return Err(Error::Tls(format!("no certificate found in {}", cert_path.display()).into()));
+    }
+
+    let mut keys = pemfile::pkcs8_private_keys(&mut BufReader::new(File::open(key_path)?)).unwrap_or_default();
+    if keys.is_empty() {
+        keys = pemfile::rsa_private_keys(&mut BufReader::new(File::open(key_path)?)).unwrap_or_default();
+    }
+    let key = keys
+        .first()
+        .ok_or_else(|| Error::Tls(format!("no private key found in {}", key_path.display()).into()))?;
+    let key = sign::any_supported_type(key).map_err(|_| Error::Tls("unsupported private key type".into()))?;
+    check_key_matches_cert(key.as_ref(), &certs[0])?;
+
+    let certified = CertifiedKey::new(certs, Arc::new(key));
+    let name = name.map(dns_name_ref).transpose()?;
+    certified.cross_check_end_entity_cert(name).map_err(|e| Error::Tls(Box::new(e)))?;
+    Ok(certified)
+}
+
+/// Selects the server certificate for every incoming TLS connection.
+///
+/// Certificates are loaded from PEM files, and can be reloaded at any time by
+/// [`CertResolver::reload`], or by a task started with [`CertResolver::watch`]
+/// which reloads them as soon as the files change.
+#[derive(Clone, Default)]
+pub struct CertResolver {
+    certs: Arc<RwLock<Vec<CertFiles>>>,
+}
+
+impl fmt::Debug for CertResolver {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let certs = self.certs.read().unwrap();
+        let mut list = f.debug_list();
+        for c in certs.iter() {
+            list.entry(&(c.name.as_deref().unwrap_or("*"), &c.cert_path));
+        }
+        list.finish()
+    }
+}
+
+impl CertResolver {
+    /// Create a resolver without any certificate.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Serve the certificate chain and private key from the given PEM files to clients
+    /// asking for the server `name`. With `None`, they are served to all other clients.
+    ///
+    /// A certificate previously added for the same name is replaced.
+    pub fn add_pem_files<P: AsRef<Path>>(&self, name: Option<&str>, cert_path: P, key_path: P) -> Result<(), Error> {
+        let name = name.map(|n| n.to_ascii_lowercase());
+        let cert_path = cert_path.as_ref().to_path_buf();
+        let key_path = key_path.as_ref().to_path_buf();
+        let modified = (modified(&cert_path), modified(&key_path));
+        let key = load_certified_key(name.as_deref(), &cert_path, &key_path)?;
+
+        let mut certs = self.certs.write().unwrap();
+        certs.retain(|c| c.name != name);
+        certs.push(CertFiles {
+            name,
+            cert_path,
+            key_path,
+            modified,
+            key,
+        });
+        Ok(())
+    }
+
+    /// Reload all certificates from their files, returning how many were loaded.
+    ///
+    /// A certificate failing to load stays in use, the first error is returned.
+    pub fn reload(&self) -> Result<usize, Error> {
+        self.reload_if(|_| true)
+    }
+
+    /// Reload the certificates whose files changed since they were loaded last time.
+    pub fn reload_changed(&self) -> Result<usize, Error> {
+        self.reload_if(|c| c.current_modified() != c.modified)
+    }
+
+    fn reload_if<F: Fn(&CertFiles) -> bool>(&self, f: F) -> Result<usize, Error> {
+        // collect the paths first, the files are not read while holding the lock
+        let candidates: Vec<_> = self
+            .certs
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|&c| f(c))
+            .map(|c| (c.name.clone(), c.cert_path.clone(), c.key_path.clone()))
+            .collect();
+
+        let mut count = 0;
+        let mut error = None;
+        for (name, cert_path, key_path) in candidates {
+            let modified = (modified(&cert_path), modified(&key_path));
+            let loaded = load_certified_key(name.as_deref(), &cert_path, &key_path);
+
+            let mut certs = self.certs.write().unwrap();
+            let entry = match certs.iter_mut().find(|c| c.name == name) {
+                Some(entry) => entry,
+                None => continue,
+            };
+            // remember the modification times in either case, so broken files are not retried until changed
+            entry.modified = modified;
+            match loaded {
+                Ok(key) => {
+                    log::info!("reloaded certificate {}", cert_path.display());
+                    entry.key = key;
+                    count += 1;
+                }
+                Err(e) => {
+                    log::warn!("failed to reload certificate {}: {}", cert_path.display(), e);
+                    error.get_or_insert(e);
+                }
+            }
+        }
+
+        match error {
+            Some(e) => Err(e),
+            None => Ok(count),
+        }
+    }
+
+    /// Start a task which checks the files for changes every `interval`.
+    ///
+    /// The task stops once all [`Control`]s are closed or dropped.
+    pub fn watch(&self, interval: Duration) -> Control {
+        let (tx, rx) = mpsc::unbounded();
+        task::spawn(watch(self.clone(), interval, rx));
+        Control { tx }
+    }
+
+    fn select(&self, name: Option<&str>) -> Option<CertifiedKey> {
+        let certs = self.certs.read().unwrap();
+        name.and_then(|n| {
+            certs
+                .iter()
+                .find(|c| c.name.as_deref().map_or(false, |m| m.eq_ignore_ascii_case(n)))
+        })
+        .or_else(|| certs.iter().find(|c| c.name.is_none()))
+        .map(|c| c.key.clone())
+    }
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, client_hello: ClientHello) -> Option<CertifiedKey> {
+        let name: Option<&str> = client_hello.server_name().map(|n| n.into());
+        let key = self.select(name);
+        if key.is_none() {
+            log::debug!("no certificate for server name {:?}", name);
+        }
+        key
+    }
+}
+
+async fn watch(resolver: CertResolver, interval: Duration, mut control_rx: mpsc::UnboundedReceiver<ControlCommand>) {
+    loop {
+        futures::select! {
+            cmd = control_rx.next() => match cmd {
+                Some(ControlCommand::Reload(reply)) => {
+                    let _ = reply.send(resolver.reload());
+                }
+                None => break,
+            },
+            _ = task::sleep(interval).fuse() => {
+                // errors are logged while reloading
+                let _ = resolver.reload_changed();
+            }
+        }
+    }
+    log::debug!("certificate watcher exited");
+}
+
+enum ControlCommand {
+    Reload(oneshot::Sender<Result<usize, Error>>),
+}
+
+/// Control of the task watching the certificate files.
+#[derive(Clone)]
+pub struct Control {
+    tx: mpsc::UnboundedSender<ControlCommand>,
+}
+
+impl Control {
+    /// Reload all certificates now, without waiting for the files to change.
+    pub async fn reload(&mut self) -> Result<usize, Error> {
+        let (tx, rx) = oneshot::channel();
+        self.tx.unbounded_send(ControlCommand::Reload(tx)).map_err(|_| Error::Closed)?;
+        rx.await.map_err(|_| Error::Closed)?
+    }
+
+    /// Stop watching the certificate files.
+    pub fn close(&mut self) {
+        self.tx.close_channel();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use filetime::FileTime;
+
+    const CERT: &str = "../../examples/websocket/cert/end.cert";
+    const CA_CERT: &str = "../../examples/websocket/cert/ca.cert";
+    const KEY: &str = "../../examples/websocket/cert/end.rsa";
+    const OTHER_CERT: &str = "../../examples/websocket/cert/other.cert";
+    const OTHER_KEY: &str = "../../examples/websocket/cert/other.key";
+
+    fn end_entity_cert(key: &CertifiedKey) -> &[u8] {
+        &key.cert[0].0
+    }
+
+    #[test]
+    fn sni_selection() {
+        let other = load_certified_key(None, OTHER_CERT.as_ref(), OTHER_KEY.as_ref()).unwrap();
+        let end = load_certified_key(None, CERT.as_ref(), KEY.as_ref()).unwrap();
+
+        let resolver = CertResolver::new();
+        assert!(resolver.select(None).is_none());
+        resolver.add_pem_files(None, OTHER_CERT, OTHER_KEY).unwrap();
+        resolver.add_pem_files(Some("Localhost"), CERT, KEY).unwrap();
+        assert!(resolver.add_pem_files(Some("example.com"), CERT, KEY).is_err());
+
+        assert_eq!(end_entity_cert(&resolver.select(Some("localhost")).unwrap()), end_entity_cert(&end));
+        assert_eq!(end_entity_cert(&resolver.select(Some("LOCALHOST")).unwrap()), end_entity_cert(&end));
+        assert_eq!(
+            end_entity_cert(&resolver.select(Some("testserver.com")).unwrap()),
+            end_entity_cert(&other)
+        );
+        assert_eq!(end_entity_cert(&resolver.select(None).unwrap()), end_entity_cert(&other));
+    }
+
+    #[test]
+    fn key_mismatch() {
+        assert!(load_certified_key(None, CA_CERT.as_ref(), KEY.as_ref()).is_err());
+        assert!(load_certified_key(None, OTHER_CERT.as_ref(), KEY.as_ref()).is_err());
+        assert!(load_certified_key(None, CERT.as_ref(), OTHER_KEY.as_ref()).is_err());
+
+        // the certificate in use is kept if the new one doesn't match
+        let resolver = CertResolver::new();
+        resolver.add_pem_files(Some("localhost"), CERT, KEY).unwrap();
+        assert!(resolver.add_pem_files(Some("localhost"), OTHER_CERT, KEY).is_err());
+        assert_eq!(resolver.select(Some("localhost")).unwrap().cert.len(), 1);
+    }
+
+    #[test]
+    fn reload_changed_files() {
+        let dir = std::env::temp_dir().join(format!("libp2prs-ws-cert-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let cert = dir.join("cert.pem");
+        let key = dir.join("key.pem");
+        std::fs::copy(CERT, &cert).unwrap();
+        std::fs::copy(KEY, &key).unwrap();
+
+        let resolver = CertResolver::new();
+        resolver.add_pem_files(None, &cert, &key).unwrap();
+        assert_eq!(resolver.reload_changed().unwrap(), 0);
+
+        let other = load_certified_key(None, OTHER_CERT.as_ref(), OTHER_KEY.as_ref()).unwrap();
+        let end = load_certified_key(None, CERT.as_ref(), KEY.as_ref()).unwrap();
+
+        // the modification time is set explicitly, as it might not change in a short while
+        std::fs::copy(OTHER_CERT, &cert).unwrap();
+        std::fs::copy(OTHER_KEY, &key).unwrap();
+        filetime::set_file_mtime(&cert, FileTime::from_unix_time(1_000_000, 0)).unwrap();
+        assert_eq!(resolver.reload_changed().unwrap(), 1);
+        assert_eq!(end_entity_cert(&resolver.select(None).unwrap()), end_entity_cert(&other));
+
+        // a broken file does not replace the certificate in use
+        std::fs::write(&cert, b"garbage").unwrap();
+        filetime::set_file_mtime(&cert, FileTime::from_unix_time(2_000_000, 0)).unwrap();
+        assert!(resolver.reload_changed().is_err());
+        assert_eq!(resolver.reload_changed().unwrap(), 0);
+        assert_eq!(end_entity_cert(&resolver.select(None).unwrap()), end_entity_cert(&other));
+
+        // neither does a certificate which doesn't match the key
+        std::fs::copy(CERT, &cert).unwrap();
+        filetime::set_file_mtime(&cert, FileTime::from_unix_time(3_000_000, 0)).unwrap();
+        assert!(resolver.reload_changed().is_err());
+        assert_eq!(end_entity_cert(&resolver.select(None).unwrap()), end_entity_cert(&other));
+
+        let mut control = resolver.watch(Duration::from_secs(3600));
+        std::fs::copy(KEY, &key).unwrap();
+        assert_eq!(task::block_on(control.reload()).unwrap(), 1);
+        assert_eq!(end_entity_cert(&resolver.select(None).unwrap()), end_entity_cert(&end));
+        control.close();
+        assert!(matches!(task::block_on(control.reload()), Err(Error::Closed)));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}